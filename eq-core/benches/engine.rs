@@ -0,0 +1,195 @@
+//! CPU time of `EqEngine::process` per band per sample, run with `cargo bench -p eq-core`.
+//! Plain timing without a harness, each case takes the fastest of a few runs.
+//! Bands updated every sample are timed next to the default 32 sample sub-blocks,
+//! and both next to the baseline: the process loop from before sub-blocks and
+//! packed pairs, with one scalar biquad per channel.
+
+use basic_audio_filters::second_order_iir::IIR2;
+use eq_core::{get_coefficients_iir2, BandKind, BandSettings, DynamicMode, EqEngine, Precision};
+
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: f32 = 48000.0;
+const BLOCK_SIZE: usize = 512;
+/// Seconds of audio each run processes
+const SECONDS: usize = 2;
+const RUNS: usize = 5;
+
+#[derive(Clone, Copy)]
+enum Load {
+    /// Settings that stay put
+    Static,
+    /// Every band's frequency moving all the time, so it is always smoothing
+    Smoothing,
+    /// Every band dynamic, following the input
+    Dynamic,
+}
+
+/// Bells spread over the spectrum
+fn band(i: usize, band_count: usize) -> BandSettings {
+    BandSettings {
+        kind: BandKind::Bell,
+        freq: 40.0 * 400.0f32.powf(i as f32 / band_count as f32),
+        db_gain: [6.0, -6.0][i % 2],
+        q_value: 1.0,
+        ..BandSettings::default()
+    }
+}
+
+/// Band update intervals to compare, with their labels
+const UPDATE_INTERVALS: [(usize, &str); 2] = [(1, "every sample"), (32, "sub-blocks")];
+
+/// Noise, so nothing settles into denormals
+fn noise_block(seed: &mut u32) -> Vec<f32> {
+    (0..BLOCK_SIZE)
+        .map(|_| {
+            *seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (*seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+        })
+        .collect()
+}
+
+/// Run `process_block` over `SECONDS` of blocks `RUNS` times, returns the
+/// nanoseconds per band per sample of the fastest run
+fn time_blocks<F>(band_count: usize, mut process_block: F) -> f64
+where
+    F: FnMut(usize, &mut [f32], &mut [f32]),
+{
+    let mut seed = 1u32;
+    let left = noise_block(&mut seed);
+    let right = noise_block(&mut seed);
+    let (mut out_left, mut out_right) = (left.clone(), right.clone());
+
+    let blocks = SECONDS * SAMPLE_RATE as usize / BLOCK_SIZE;
+    let mut fastest = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        for block in 0..blocks {
+            out_left.copy_from_slice(&left);
+            out_right.copy_from_slice(&right);
+            process_block(block, &mut out_left, &mut out_right);
+            black_box((&out_left, &out_right));
+        }
+        fastest = fastest.min(start.elapsed());
+    }
+    fastest.as_nanos() as f64 / (blocks * BLOCK_SIZE * band_count) as f64
+}
+
+/// The process loop from before sub-blocks: every sample each band checks the
+/// update flags of its parameters, then left and right go through their own scalar
+/// f32 biquads. There were no dynamic bands, and smoothing bands had new
+/// coefficients every sample.
+fn bench_baseline(band_count: usize, load: Load) -> Option<f64> {
+    let smoothing = match load {
+        Load::Static => false,
+        Load::Smoothing => true,
+        Load::Dynamic => return None,
+    };
+    let bands = (0..band_count)
+        .map(|i| band(i, band_count))
+        .collect::<Vec<BandSettings>>();
+    let iir2_coeffs = |band: &BandSettings, freq: f32| {
+        get_coefficients_iir2(band.kind, freq, band.db_gain, band.q_value, SAMPLE_RATE)
+    };
+    let filters = || {
+        bands
+            .iter()
+            .map(|band| IIR2::new(iir2_coeffs(band, band.freq)))
+            .collect::<Vec<IIR2>>()
+    };
+    let (mut filters_l, mut filters_r) = (filters(), filters());
+    // Kind, frequency, gain, Q and mode of each band
+    let flags = (0..band_count)
+        .map(|_| [(); 5].map(|_| AtomicBool::new(false)))
+        .collect::<Vec<[AtomicBool; 5]>>();
+    Some(time_blocks(band_count, |block, left, right| {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            for (i, band) in bands.iter().enumerate() {
+                if smoothing {
+                    flags[i][1].store(true, Ordering::Relaxed);
+                }
+                if !flags[i]
+                    .iter()
+                    .any(|flag| flag.swap(false, Ordering::Relaxed))
+                {
+                    continue;
+                }
+                let coeffs = iir2_coeffs(band, band.freq * (1.0 + 0.5 * (block % 2) as f32));
+                filters_l[i].update(coeffs);
+                filters_r[i].update(coeffs);
+            }
+            for i in 0..band_count {
+                *l = filters_l[i].process(*l);
+                *r = filters_r[i].process(*r);
+            }
+        }
+    }))
+}
+
+/// Nanoseconds per band per sample of the fastest run
+fn bench(band_count: usize, precision: Precision, update_interval: usize, load: Load) -> f64 {
+    let mut engine = EqEngine::new(band_count, SAMPLE_RATE);
+    engine.set_update_interval(update_interval);
+    for i in 0..band_count {
+        let mut settings = band(i, band_count);
+        if let Load::Dynamic = load {
+            settings.dynamic = DynamicMode::On;
+        }
+        engine.set_band(i, settings);
+    }
+    engine.set_precision(precision);
+    engine.reset();
+
+    time_blocks(band_count, |block, left, right| {
+        if let Load::Smoothing = load {
+            for i in 0..band_count {
+                let mut settings = band(i, band_count);
+                settings.freq *= 1.0 + 0.5 * (block % 2) as f32;
+                engine.set_band(i, settings);
+            }
+        }
+        engine.process([left, right], None);
+    })
+}
+
+/// Two decimals, or "-" for a case that was not run
+fn format_time(time: Option<f64>) -> String {
+    time.map_or("-".to_string(), |time| format!("{:.2}", time))
+}
+
+fn main() {
+    println!(
+        "ns per band per sample, stereo, {} sample blocks",
+        BLOCK_SIZE
+    );
+    println!(
+        "{:>6} {:>10} {:>13} {:>10} {:>10} {:>10}",
+        "bands", "precision", "updates", "static", "smoothing", "dynamic"
+    );
+    for &band_count in &[4, 8, 16, 24] {
+        println!(
+            "{:>6} {:>10} {:>13} {:>10} {:>10} {:>10}",
+            band_count,
+            "Single",
+            "baseline",
+            format_time(bench_baseline(band_count, Load::Static)),
+            format_time(bench_baseline(band_count, Load::Smoothing)),
+            format_time(bench_baseline(band_count, Load::Dynamic)),
+        );
+        for &precision in &[Precision::Single, Precision::Double] {
+            for &(update_interval, label) in &UPDATE_INTERVALS {
+                println!(
+                    "{:>6} {:>10} {:>13} {:>10.2} {:>10.2} {:>10.2}",
+                    band_count,
+                    format!("{:?}", precision),
+                    label,
+                    bench(band_count, precision, update_interval, Load::Static),
+                    bench(band_count, precision, update_interval, Load::Smoothing),
+                    bench(band_count, precision, update_interval, Load::Dynamic),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    f32::consts::{FRAC_1_SQRT_2, LN_2},
+    fmt,
+};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BandKind {
+    Bell,
+    LowPass,
+    HighPass,
+    LowShelf,
+    HighShelf,
+    Notch,
+    BandPass,
+    AllPass,
+}
+
+impl BandKind {
+    pub fn from_u8(value: u8) -> BandKind {
+        match value {
+            0 => BandKind::Bell,
+            1 => BandKind::LowPass,
+            2 => BandKind::HighPass,
+            3 => BandKind::LowShelf,
+            4 => BandKind::HighShelf,
+            5 => BandKind::Notch,
+            6 => BandKind::BandPass,
+            7 => BandKind::AllPass,
+            _ => BandKind::LowPass,
+        }
+    }
+
+    /// Whether the db_gain parameter changes the response of this kind
+    pub fn uses_gain(&self) -> bool {
+        matches!(
+            self,
+            BandKind::Bell | BandKind::LowShelf | BandKind::HighShelf
+        )
+    }
+}
+
+impl fmt::Display for BandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BandMode {
+    FirstOrder,
+    SecondOrder,
+    Butterworth24,
+    Butterworth36,
+    Butterworth48,
+    LinkwitzRiley12,
+    LinkwitzRiley24,
+    LinkwitzRiley48,
+}
+
+impl BandMode {
+    pub fn from_u8(value: u8) -> BandMode {
+        match value {
+            0 => BandMode::FirstOrder,
+            1 => BandMode::SecondOrder,
+            2 => BandMode::Butterworth24,
+            3 => BandMode::Butterworth36,
+            4 => BandMode::Butterworth48,
+            5 => BandMode::LinkwitzRiley12,
+            6 => BandMode::LinkwitzRiley24,
+            7 => BandMode::LinkwitzRiley48,
+            _ => BandMode::SecondOrder,
+        }
+    }
+
+    /// Q of each biquad in the cascade for the steeper LowPass/HighPass slopes.
+    /// Empty for the first and second order modes, which use the band's own Q.
+    pub fn cascade_q_values(&self) -> &'static [f32] {
+        match self {
+            BandMode::FirstOrder | BandMode::SecondOrder => &[],
+            BandMode::Butterworth24 => &[0.541_196_1, 1.306_563],
+            BandMode::Butterworth36 => &[0.517_638_1, FRAC_1_SQRT_2, 1.931_852],
+            BandMode::Butterworth48 => &[0.509_795_6, 0.601_344_9, 0.899_976_2, 2.562_915],
+            // Linkwitz-Riley filters are two identical Butterworth filters in series
+            BandMode::LinkwitzRiley12 => &[0.5],
+            BandMode::LinkwitzRiley24 => &[FRAC_1_SQRT_2, FRAC_1_SQRT_2],
+            BandMode::LinkwitzRiley48 => &[0.541_196_1, 1.306_563, 0.541_196_1, 1.306_563],
+        }
+    }
+}
+
+impl fmt::Display for BandMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Which channel, or mid/side component, a band filters
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BandRouting {
+    Stereo,
+    Left,
+    Right,
+    Mid,
+    Side,
+}
+
+impl BandRouting {
+    pub fn from_u8(value: u8) -> BandRouting {
+        match value {
+            0 => BandRouting::Stereo,
+            1 => BandRouting::Left,
+            2 => BandRouting::Right,
+            3 => BandRouting::Mid,
+            4 => BandRouting::Side,
+            _ => BandRouting::Stereo,
+        }
+    }
+}
+
+impl fmt::Display for BandRouting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DynamicMode {
+    Off,
+    /// The band's own input drives the gain
+    On,
+    /// The sidechain input drives the gain
+    Sidechain,
+}
+
+impl DynamicMode {
+    pub fn from_u8(value: u8) -> DynamicMode {
+        match value {
+            0 => DynamicMode::Off,
+            1 => DynamicMode::On,
+            2 => DynamicMode::Sidechain,
+            _ => DynamicMode::Off,
+        }
+    }
+}
+
+impl fmt::Display for DynamicMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PhaseMode {
+    /// Each band is an IIR filter, no latency
+    Minimum,
+    /// The combined response is applied as one linear phase FIR, adds latency
+    Linear,
+}
+
+impl PhaseMode {
+    pub fn from_u8(value: u8) -> PhaseMode {
+        match value {
+            0 => PhaseMode::Minimum,
+            1 => PhaseMode::Linear,
+            _ => PhaseMode::Minimum,
+        }
+    }
+}
+
+impl fmt::Display for PhaseMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} phase", self)
+    }
+}
+
+/// How the analog prototype of a second order band is turned into a digital filter
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FilterDesign {
+    /// Bilinear transform, exact at the band frequency but cramped toward nyquist
+    Bilinear,
+    /// Poles matched to the analog ones and zeros fitted to the analog magnitude,
+    /// close to the analog response up to nyquist without oversampling
+    Matched,
+}
+
+impl FilterDesign {
+    pub fn from_u8(value: u8) -> FilterDesign {
+        match value {
+            1 => FilterDesign::Matched,
+            _ => FilterDesign::Bilinear,
+        }
+    }
+}
+
+impl fmt::Display for FilterDesign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterDesign::Bilinear => write!(f, "Bilinear"),
+            FilterDesign::Matched => write!(f, "Analog matched"),
+        }
+    }
+}
+
+/// How many times the sample rate the minimum phase bands run at. Keeps bands
+/// near nyquist from cramping, at the cost of latency.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Oversampling {
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Oversampling {
+    pub fn from_u8(value: u8) -> Oversampling {
+        match value {
+            1 => Oversampling::X2,
+            2 => Oversampling::X4,
+            3 => Oversampling::X8,
+            _ => Oversampling::Off,
+        }
+    }
+
+    /// Number of 2x stages
+    pub fn stages(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn factor(&self) -> usize {
+        1 << self.stages()
+    }
+}
+
+impl fmt::Display for Oversampling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Oversampling::Off => write!(f, "No oversampling"),
+            _ => write!(f, "{}x oversampling", self.factor()),
+        }
+    }
+}
+
+/// Float type the minimum phase bands, their oversampling and the solo bandpasses
+/// keep their coefficients and state in. Linear phase convolution is f32 either way.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Precision {
+    Single,
+    /// Keeps low bands at high sample rates free of the noise and drift of f32 state
+    Double,
+}
+
+impl Precision {
+    pub fn from_u8(value: u8) -> Precision {
+        match value {
+            1 => Precision::Double,
+            _ => Precision::Single,
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Precision::Single => write!(f, "32 bit filters"),
+            Precision::Double => write!(f, "64 bit filters"),
+        }
+    }
+}
+
+/// What the engine outputs in place of the processed signal, to hear what the
+/// EQ changes
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DeltaMode {
+    Off,
+    /// Processed minus the input
+    All,
+    /// What band `n` (from 0) adds to the signal
+    Band(usize),
+}
+
+impl DeltaMode {
+    /// 0 is off, 1 all bands, 2 and up a single band
+    pub fn from_u8(value: u8) -> DeltaMode {
+        match value {
+            0 => DeltaMode::Off,
+            1 => DeltaMode::All,
+            n => DeltaMode::Band(n as usize - 2),
+        }
+    }
+}
+
+impl fmt::Display for DeltaMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeltaMode::Off => write!(f, "Delta off"),
+            DeltaMode::All => write!(f, "Delta all"),
+            DeltaMode::Band(n) => write!(f, "Delta band {}", n + 1),
+        }
+    }
+}
+
+/// Plain values of one band, what the engine works from
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct BandSettings {
+    pub kind: BandKind,
+    pub freq: f32,
+    pub db_gain: f32,
+    pub q_value: f32,
+    pub mode: BandMode,
+    pub routing: BandRouting,
+    pub dynamic: DynamicMode,
+    /// Level in dB above which a dynamic band starts to move its gain
+    pub threshold: f32,
+    pub ratio: f32,
+    /// Detector attack time in ms
+    pub attack: f32,
+    /// Detector release time in ms
+    pub release: f32,
+    /// Disabled bands are faded out and leave the signal as it is
+    pub enabled: bool,
+    /// While any band is soloed, only the soloed bands are heard, each as a
+    /// bandpass at its frequency and Q
+    pub solo: bool,
+    /// Only changes second order bands, first order bands are always bilinear
+    pub design: FilterDesign,
+}
+
+impl Default for BandSettings {
+    fn default() -> Self {
+        BandSettings {
+            kind: BandKind::Bell,
+            freq: 1000.0,
+            db_gain: 0.0,
+            q_value: LN_2,
+            mode: BandMode::SecondOrder,
+            routing: BandRouting::Stereo,
+            dynamic: DynamicMode::Off,
+            threshold: -24.0,
+            ratio: 2.0,
+            attack: 10.0,
+            release: 100.0,
+            enabled: true,
+            solo: false,
+            design: FilterDesign::Bilinear,
+        }
+    }
+}
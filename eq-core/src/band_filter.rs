@@ -0,0 +1,183 @@
+use crate::band::{BandMode, BandRouting, BandSettings};
+use crate::coefficients::{get_coefficients_svf1, get_coefficients_svf2_cascade, MAX_CASCADE};
+use crate::lane::{Lane, Pair};
+use crate::svf::{Svf1, Svf1Coefficients, Svf2, Svf2Coefficients};
+
+/// Left and right to themselves
+const LEFT_RIGHT: [[f64; 2]; 2] = [[1.0, 0.0], [0.0, 1.0]];
+/// Left and right to mid and side
+const MID_SIDE: [[f64; 2]; 2] = [[0.5, 0.5], [0.5, -0.5]];
+/// Mid and side back to left and right
+const MID_SIDE_INVERSE: [[f64; 2]; 2] = [[1.0, 1.0], [1.0, -1.0]];
+
+/// How the two lanes of a band's filters are fed: the matrix from left and right
+/// to the lanes, its inverse, and which lanes are filtered
+fn routing_matrices(routing: BandRouting) -> ([[f64; 2]; 2], [[f64; 2]; 2], [f64; 2]) {
+    match routing {
+        BandRouting::Stereo => (LEFT_RIGHT, LEFT_RIGHT, [1.0, 1.0]),
+        BandRouting::Left => (LEFT_RIGHT, LEFT_RIGHT, [1.0, 0.0]),
+        BandRouting::Right => (LEFT_RIGHT, LEFT_RIGHT, [0.0, 1.0]),
+        BandRouting::Mid => (MID_SIDE, MID_SIDE_INVERSE, [1.0, 0.0]),
+        BandRouting::Side => (MID_SIDE, MID_SIDE_INVERSE, [0.0, 1.0]),
+    }
+}
+
+fn apply(matrix: &[[f64; 2]; 2], x: [f64; 2]) -> [f64; 2] {
+    [
+        matrix[0][0] * x[0] + matrix[0][1] * x[1],
+        matrix[1][0] * x[0] + matrix[1][1] * x[1],
+    ]
+}
+
+/// The filters of one band for both channels, with coefficients and state in `T`.
+/// Either a first order filter or up to `MAX_CASCADE` biquads in series.
+pub(crate) struct BandFilter<T: Lane> {
+    first_order: bool,
+    iir1: Svf1<T>,
+    iir2: [Svf2<T>; MAX_CASCADE],
+    stages: usize,
+}
+
+impl<T: Lane> BandFilter<T> {
+    /// Passes the signal through until `update`
+    pub fn new() -> BandFilter<T> {
+        let iir2 = Svf2::new(Svf2Coefficients::new(1.0, 0.0, 0.0, 1.0, 0.0, 0.0));
+        BandFilter {
+            first_order: false,
+            iir1: Svf1::new(Svf1Coefficients::new(0.0, 1.0, 0.0)),
+            iir2: [iir2; MAX_CASCADE],
+            stages: 1,
+        }
+    }
+
+    /// Coefficients for `band` moved to `f0`, `db_gain` and `q_value`, worked out
+    /// in f64 whatever `T` is
+    pub fn update(&mut self, band: &BandSettings, f0: f64, db_gain: f64, q_value: f64, fs: f64) {
+        let first_order = band.mode == BandMode::FirstOrder;
+        if first_order {
+            if !self.first_order {
+                // The first order filter did not run while the band was a cascade
+                self.iir1.reset();
+                self.first_order = true;
+            }
+            self.iir1
+                .update(get_coefficients_svf1(band.kind, f0, db_gain, fs));
+            return;
+        }
+        if self.first_order {
+            // Nor did any of the stages while it was first order
+            self.stages = 0;
+            self.first_order = false;
+        }
+        let cascade = get_coefficients_svf2_cascade(
+            band.kind,
+            band.mode,
+            band.design,
+            f0,
+            db_gain,
+            q_value,
+            fs,
+        );
+        // Stages that were not in use carry stale state, start them fresh
+        for stage in self.stages..cascade.len {
+            self.iir2[stage].reset();
+        }
+        for (filter, coeffs) in self.iir2.iter_mut().zip(cascade.iter()) {
+            filter.update(*coeffs);
+        }
+        self.stages = cascade.len;
+    }
+
+    pub fn reset(&mut self) {
+        self.iir1.reset();
+        self.iir2.iter_mut().for_each(|filter| filter.reset());
+    }
+
+    #[inline]
+    fn process(&mut self, x: T::Pair) -> T::Pair {
+        if self.first_order {
+            self.iir1.process_pair(x)
+        } else {
+            self.iir2[..self.stages]
+                .iter_mut()
+                .fold(x, |x, filter| filter.process_pair(x))
+        }
+    }
+
+    /// Run `block` through the band in place, following `routing`. Each sample is
+    /// `factor` samples of `block`, and `mix` has how much of the band is heard for
+    /// each sample. What the band adds to the sample at `tap` of each sample goes
+    /// to `deltas`.
+    pub fn process_block(
+        &mut self,
+        routing: BandRouting,
+        block: &mut [[f64; 2]],
+        mix: &[f32],
+        factor: usize,
+        tap: usize,
+        deltas: &mut [[f64; 2]],
+    ) {
+        let (to_lanes, from_lanes, filtered) = routing_matrices(routing);
+        for ((samples, mix), delta) in block
+            .chunks_mut(factor)
+            .zip(mix.iter())
+            .zip(deltas.iter_mut())
+        {
+            let mix = *mix as f64;
+            for (j, x) in samples.iter_mut().enumerate() {
+                let lanes = apply(&to_lanes, *x);
+                let y = self
+                    .process(T::Pair::new([T::from_f64(lanes[0]), T::from_f64(lanes[1])]))
+                    .to_array();
+                let change = [
+                    (y[0].to_f64() - lanes[0]) * filtered[0] * mix,
+                    (y[1].to_f64() - lanes[1]) * filtered[1] * mix,
+                ];
+                let change = apply(&from_lanes, change);
+                if j == tap {
+                    *delta = change;
+                }
+                x[0] += change[0];
+                x[1] += change[1];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::band::BandKind;
+
+    /// Output of `filter` for a few hundred samples of a sine, with `band` at 48 kHz
+    fn run(filter: &mut BandFilter<f64>, band: &BandSettings) -> Vec<[f64; 2]> {
+        let (f0, db_gain, q_value) = (band.freq as f64, band.db_gain as f64, band.q_value as f64);
+        filter.update(band, f0, db_gain, q_value, 48000.0);
+        let mut block = (0..256)
+            .map(|n| [(n as f64 * 0.1).sin(); 2])
+            .collect::<Vec<[f64; 2]>>();
+        let mut deltas = vec![[0.0; 2]; block.len()];
+        let mix = vec![1.0; block.len()];
+        filter.process_block(BandRouting::Stereo, &mut block, &mix, 1, 0, &mut deltas);
+        block
+    }
+
+    #[test]
+    fn switching_between_first_order_and_cascades_starts_fresh() {
+        let band = |mode| BandSettings {
+            kind: BandKind::LowPass,
+            freq: 200.0,
+            mode,
+            ..BandSettings::default()
+        };
+        let (first_order, cascade) = (band(BandMode::FirstOrder), band(BandMode::Butterworth24));
+        for (from, to) in [(first_order, cascade), (cascade, first_order)] {
+            // Each filter ran once before, so the switch back leaves state behind
+            let mut filter = BandFilter::new();
+            for band in [to, from] {
+                run(&mut filter, &band);
+            }
+            assert_eq!(run(&mut filter, &to), run(&mut BandFilter::new(), &to));
+        }
+    }
+}
@@ -0,0 +1,583 @@
+use basic_audio_filters::first_order_iir::IIR1Coefficients;
+use basic_audio_filters::second_order_iir::IIR2Coefficients;
+use rustfft::num_complex::Complex;
+
+use crate::band::{BandKind, BandMode, BandSettings, FilterDesign};
+use crate::svf::{Svf1Coefficients, Svf2Coefficients};
+
+use std::f64::consts::PI;
+
+/// Most biquads a single band chains together, needed by the 48 dB/oct modes
+pub const MAX_CASCADE: usize = 4;
+
+pub fn get_coefficients_iir2(
+    kind: BandKind,
+    f0: f32,
+    db_gain: f32,
+    q_value: f32,
+    fs: f32,
+) -> IIR2Coefficients {
+    match kind {
+        BandKind::Bell => IIR2Coefficients::bell(f0, db_gain, q_value, fs),
+        BandKind::LowPass => IIR2Coefficients::lowpass(f0, db_gain, q_value, fs),
+        BandKind::HighPass => IIR2Coefficients::highpass(f0, db_gain, q_value, fs),
+        BandKind::LowShelf => IIR2Coefficients::lowshelf(f0, db_gain, q_value, fs),
+        BandKind::HighShelf => IIR2Coefficients::highshelf(f0, db_gain, q_value, fs),
+        BandKind::Notch => IIR2Coefficients::notch(f0, db_gain, q_value, fs),
+        BandKind::BandPass => IIR2Coefficients::bandpass(f0, db_gain, q_value, fs),
+        BandKind::AllPass => IIR2Coefficients::allpass(f0, db_gain, q_value, fs),
+    }
+}
+
+/// `get_coefficients_iir2` computed in f64
+pub fn get_coefficients_svf2(
+    kind: BandKind,
+    f0: f64,
+    db_gain: f64,
+    q_value: f64,
+    fs: f64,
+) -> Svf2Coefficients {
+    match kind {
+        BandKind::Bell => Svf2Coefficients::bell(f0, db_gain, q_value, fs),
+        BandKind::LowPass => Svf2Coefficients::lowpass(f0, db_gain, q_value, fs),
+        BandKind::HighPass => Svf2Coefficients::highpass(f0, db_gain, q_value, fs),
+        BandKind::LowShelf => Svf2Coefficients::lowshelf(f0, db_gain, q_value, fs),
+        BandKind::HighShelf => Svf2Coefficients::highshelf(f0, db_gain, q_value, fs),
+        BandKind::Notch => Svf2Coefficients::notch(f0, db_gain, q_value, fs),
+        BandKind::BandPass => Svf2Coefficients::bandpass(f0, db_gain, q_value, fs),
+        BandKind::AllPass => Svf2Coefficients::allpass(f0, db_gain, q_value, fs),
+    }
+}
+
+/// `phi0`, `phi1` and `phi2` at `w`. The squared magnitude of a biquad numerator
+/// is `B0 phi0 + B1 phi1 + B2 phi2` with `B0 = (b0 + b1 + b2)^2`,
+/// `B1 = (b0 - b1 + b2)^2` and `B2 = -4 b0 b2`, and the same goes for the denominator.
+fn phi(w: f64) -> [f64; 3] {
+    let s = (w * 0.5).sin().powi(2);
+    [1.0 - s, s, 4.0 * (1.0 - s) * s]
+}
+
+fn dot(x: [f64; 3], y: [f64; 3]) -> f64 {
+    x[0] * y[0] + x[1] * y[1] + x[2] * y[2]
+}
+
+/// Minimum phase numerator with the squared magnitude terms `b`, see `phi`
+fn numerator(b: [f64; 3]) -> (f64, f64, f64) {
+    let (dc, nyquist) = (b[0].max(0.0).sqrt(), b[1].max(0.0).sqrt());
+    let w = (dc + nyquist) * 0.5;
+    let b0 = (w + (w * w + b[2]).max(0.0).sqrt()) * 0.5;
+    let b2 = if b0 != 0.0 { -b[2] / (4.0 * b0) } else { 0.0 };
+    (b0, (dc - nyquist) * 0.5, b2)
+}
+
+/// Analog prototype `(n2 s^2 + n1 s + n0) / (s^2 + k s + 1)`, with `s` normalized
+/// to the pole frequency `wp` in radians per sample
+#[derive(Clone, Copy)]
+struct Prototype {
+    wp: f64,
+    k: f64,
+    n2: f64,
+    n1: f64,
+    n0: f64,
+}
+
+impl Prototype {
+    /// Squared magnitude at `w` radians per sample
+    fn magnitude2(&self, w: f64) -> f64 {
+        let x = w / self.wp;
+        ((self.n0 - self.n2 * x * x).powi(2) + (self.n1 * x).powi(2))
+            / ((1.0 - x * x).powi(2) + (self.k * x).powi(2))
+    }
+
+    /// `a1` and `a2` of the analog poles by impulse invariance
+    fn poles(&self) -> (f64, f64) {
+        let zeta = self.k * 0.5;
+        let decay = (-zeta * self.wp).exp();
+        let a1 = if zeta <= 1.0 {
+            -2.0 * decay * ((1.0 - zeta * zeta).sqrt() * self.wp).cos()
+        } else {
+            -2.0 * decay * ((zeta * zeta - 1.0).sqrt() * self.wp).cosh()
+        };
+        (a1, decay * decay)
+    }
+
+    /// The prototype of `1 / H(s)`, for `n0` and `n2` above 0
+    fn inverse(&self) -> Prototype {
+        let scale = (self.n0 * self.n2).sqrt();
+        Prototype {
+            wp: self.wp * (self.n0 / self.n2).sqrt(),
+            k: self.n1 / scale,
+            n2: 1.0 / self.n2,
+            n1: self.k / scale,
+            n0: 1.0 / self.n0,
+        }
+    }
+
+    /// Matched poles with zeros fitted to the magnitude at DC, nyquist and `w0`
+    fn fit(&self, w0: f64) -> Biquad {
+        let (a1, a2) = self.poles();
+        let a = [(1.0 + a1 + a2).powi(2), (1.0 - a1 + a2).powi(2), -4.0 * a2];
+        let p = phi(w0);
+        let dc = a[0] * self.magnitude2(0.0);
+        let nyquist = a[1] * self.magnitude2(PI);
+        let r = self.magnitude2(w0) * dot(a, p);
+        let (b0, b1, b2) = numerator([dc, nyquist, (r - dc * p[0] - nyquist * p[1]) / p[2]]);
+        Biquad { b0, b1, b2, a1, a2 }
+    }
+}
+
+/// Second order coefficients that follow the analog prototype of `kind` up to
+/// nyquist, after Vicanek's "Matched Second Order Digital Filters". The poles
+/// are the analog poles by impulse invariance. The zeros are fitted to the analog
+/// magnitude at the band frequency and at DC and nyquist, where the kind allows.
+pub fn get_coefficients_iir2_matched(
+    kind: BandKind,
+    f0: f32,
+    db_gain: f32,
+    q_value: f32,
+    fs: f32,
+) -> IIR2Coefficients {
+    IIR2Coefficients::from(matched_biquad(
+        kind,
+        f0 as f64,
+        db_gain as f64,
+        q_value as f64,
+        fs as f64,
+    ))
+}
+
+/// `get_coefficients_iir2_matched` computed in f64
+pub fn get_coefficients_svf2_matched(
+    kind: BandKind,
+    f0: f64,
+    db_gain: f64,
+    q_value: f64,
+    fs: f64,
+) -> Svf2Coefficients {
+    Svf2Coefficients::from(matched_biquad(kind, f0, db_gain, q_value, fs))
+}
+
+fn matched_biquad(kind: BandKind, f0: f64, db_gain: f64, q_value: f64, fs: f64) -> Biquad {
+    // The bilinear coefficients carry the analog prototype, only the shelves have
+    // their poles away from f0
+    let bilinear = get_coefficients_svf2(kind, f0, db_gain, q_value, fs);
+    let k = bilinear.k;
+    let (m0, m1, m2) = (bilinear.m0, bilinear.m1, bilinear.m2);
+    let w0 = (2.0 * PI * f0 / fs).min(PI * 0.999);
+    let sqrt_a = bilinear.a.sqrt();
+    let prototype = Prototype {
+        wp: match kind {
+            BandKind::LowShelf => w0 / sqrt_a,
+            BandKind::HighShelf => w0 * sqrt_a,
+            _ => w0,
+        },
+        k,
+        n2: m0,
+        n1: m0 * k + m1,
+        n0: m0 + m2,
+    };
+    let (a1, a2) = prototype.poles();
+    let a = [(1.0 + a1 + a2).powi(2), (1.0 - a1 + a2).powi(2), -4.0 * a2];
+    let p = phi(w0);
+    // Squared numerator magnitude that gives the analog magnitude at f0
+    let r = prototype.magnitude2(w0) * dot(a, p);
+
+    let biquad = |(b0, b1, b2): (f64, f64, f64)| Biquad { b0, b1, b2, a1, a2 };
+    match kind {
+        // No zero at nyquist, so b2 is 0
+        BandKind::LowPass => {
+            let dc = a[0] * prototype.magnitude2(0.0);
+            biquad(numerator([dc, (r - dc * p[0]) / p[1], 0.0]))
+        }
+        // Both zeros at DC
+        BandKind::HighPass => {
+            let b0 = r.sqrt() / (4.0 * p[1]);
+            biquad((b0, -2.0 * b0, b0))
+        }
+        // Both zeros on the unit circle at f0, unity gain at DC
+        BandKind::Notch => {
+            let b0 = (1.0 + a1 + a2) / (2.0 - 2.0 * w0.cos());
+            biquad((b0, -2.0 * b0 * w0.cos(), b0))
+        }
+        BandKind::AllPass => biquad((a2, a1, 1.0)),
+        // Shelves boosting highs or cutting lows have their poles above f0, maybe
+        // past nyquist, and cutting bells have their sharp part in the zeros. Their
+        // inverses have well placed poles, which are matched exactly.
+        BandKind::LowShelf | BandKind::HighShelf if prototype.wp > w0 => {
+            prototype.inverse().fit(w0).inverse()
+        }
+        BandKind::Bell if db_gain < 0.0 => prototype.inverse().fit(w0).inverse(),
+        BandKind::Bell | BandKind::LowShelf | BandKind::HighShelf | BandKind::BandPass => {
+            prototype.fit(w0)
+        }
+    }
+}
+
+/// Coefficients for each biquad in a band, only the first `len` stages are used
+#[derive(Copy, Clone)]
+pub struct CascadeCoefficients<C = IIR2Coefficients> {
+    pub stages: [C; MAX_CASCADE],
+    pub len: usize,
+}
+
+impl<C> CascadeCoefficients<C> {
+    pub fn iter(&self) -> impl Iterator<Item = &C> {
+        self.stages[..self.len].iter()
+    }
+}
+
+/// The stages of a band of `kind` and `mode`, `stage` gives the coefficients of a
+/// biquad with a Q value
+fn cascade<C: Copy>(
+    kind: BandKind,
+    mode: BandMode,
+    q_value: f64,
+    stage: impl Fn(f64) -> C,
+) -> CascadeCoefficients<C> {
+    let mut cascade = CascadeCoefficients {
+        stages: [stage(q_value); MAX_CASCADE],
+        len: 1,
+    };
+    let q_values = mode.cascade_q_values();
+    if q_values.is_empty() || !(kind == BandKind::LowPass || kind == BandKind::HighPass) {
+        return cascade;
+    }
+    for (coeffs, q_value) in cascade.stages.iter_mut().zip(q_values) {
+        *coeffs = stage(*q_value as f64);
+    }
+    cascade.len = q_values.len();
+    cascade
+}
+
+pub fn get_coefficients_iir2_cascade(
+    kind: BandKind,
+    mode: BandMode,
+    design: FilterDesign,
+    f0: f32,
+    db_gain: f32,
+    q_value: f32,
+    fs: f32,
+) -> CascadeCoefficients {
+    let design_fn = match design {
+        FilterDesign::Bilinear => get_coefficients_iir2,
+        FilterDesign::Matched => get_coefficients_iir2_matched,
+    };
+    cascade(kind, mode, q_value as f64, |q_value| {
+        design_fn(kind, f0, db_gain, q_value as f32, fs)
+    })
+}
+
+/// `get_coefficients_iir2_cascade` computed in f64
+pub fn get_coefficients_svf2_cascade(
+    kind: BandKind,
+    mode: BandMode,
+    design: FilterDesign,
+    f0: f64,
+    db_gain: f64,
+    q_value: f64,
+    fs: f64,
+) -> CascadeCoefficients<Svf2Coefficients> {
+    let design_fn = match design {
+        FilterDesign::Bilinear => get_coefficients_svf2,
+        FilterDesign::Matched => get_coefficients_svf2_matched,
+    };
+    cascade(kind, mode, q_value, |q_value| {
+        design_fn(kind, f0, db_gain, q_value, fs)
+    })
+}
+
+pub fn get_coefficients_iir1(kind: BandKind, f0: f32, db_gain: f32, fs: f32) -> IIR1Coefficients {
+    match kind {
+        BandKind::LowPass => IIR1Coefficients::lowpass(f0, db_gain, fs),
+        BandKind::HighPass => IIR1Coefficients::highpass(f0, db_gain, fs),
+        BandKind::LowShelf => IIR1Coefficients::lowshelf(f0, db_gain, fs),
+        BandKind::HighShelf => IIR1Coefficients::highshelf(f0, db_gain, fs),
+        BandKind::AllPass => IIR1Coefficients::allpass(f0, db_gain, fs),
+        _ => IIR1Coefficients::allpass(f0, db_gain, fs),
+    }
+}
+
+/// `get_coefficients_iir1` computed in f64
+pub fn get_coefficients_svf1(kind: BandKind, f0: f64, db_gain: f64, fs: f64) -> Svf1Coefficients {
+    match kind {
+        BandKind::LowPass => Svf1Coefficients::lowpass(f0, db_gain, fs),
+        BandKind::HighPass => Svf1Coefficients::highpass(f0, db_gain, fs),
+        BandKind::LowShelf => Svf1Coefficients::lowshelf(f0, db_gain, fs),
+        BandKind::HighShelf => Svf1Coefficients::highshelf(f0, db_gain, fs),
+        _ => Svf1Coefficients::allpass(f0, db_gain, fs),
+    }
+}
+
+/// Direct form biquad with `a0` normalized to 1:
+/// `y = b0 x + b1 x[-1] + b2 x[-2] - a1 y[-1] - a2 y[-2]`
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Biquad {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl Biquad {
+    /// Only scales the signal by `gain`
+    pub fn gain(gain: f64) -> Biquad {
+        Biquad {
+            b0: gain,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+        }
+    }
+
+    /// `1 / H(z)`, stable when the zeros are inside the unit circle
+    fn inverse(&self) -> Biquad {
+        Biquad {
+            b0: 1.0 / self.b0,
+            b1: self.a1 / self.b0,
+            b2: self.a2 / self.b0,
+            a1: self.b1 / self.b0,
+            a2: self.b2 / self.b0,
+        }
+    }
+}
+
+impl From<IIR2Coefficients> for Biquad {
+    /// The state variable filter is the bilinear transform of
+    /// `(m0 s^2 + (m0 k + m1) s + m0 + m2) / (s^2 + k s + 1)` with `s` prewarped by `g`
+    fn from(coeffs: IIR2Coefficients) -> Biquad {
+        let g = coeffs.g as f64;
+        let k = coeffs.k as f64;
+        let (m0, m1, m2) = (coeffs.m0 as f64, coeffs.m1 as f64, coeffs.m2 as f64);
+        let g2 = g * g;
+        let a0 = 1.0 + g * k + g2;
+        let a1 = 2.0 * (g2 - 1.0);
+        let a2 = 1.0 - g * k + g2;
+        Biquad {
+            b0: (m0 * a0 + m1 * g + m2 * g2) / a0,
+            b1: (m0 * a1 + 2.0 * m2 * g2) / a0,
+            b2: (m0 * a2 - m1 * g + m2 * g2) / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+impl From<Biquad> for IIR2Coefficients {
+    /// See `Svf2Coefficients::from`
+    fn from(biquad: Biquad) -> IIR2Coefficients {
+        let coeffs = Svf2Coefficients::from(biquad);
+        IIR2Coefficients {
+            a: coeffs.a as f32,
+            g: coeffs.g as f32,
+            gpow2: (coeffs.g * coeffs.g) as f32,
+            k: coeffs.k as f32,
+            a1: coeffs.a1 as f32,
+            a2: coeffs.a2 as f32,
+            a3: coeffs.a3 as f32,
+            m0: coeffs.m0 as f32,
+            m1: coeffs.m1 as f32,
+            m2: coeffs.m2 as f32,
+        }
+    }
+}
+
+impl From<Biquad> for Svf2Coefficients {
+    /// The state variable filter with the response of a stable `biquad`, the
+    /// inverse of `Biquad::from`
+    fn from(biquad: Biquad) -> Svf2Coefficients {
+        let Biquad { b0, b1, b2, a1, a2 } = biquad;
+        // Denominator at DC and at nyquist, 4 g^2 / a0 and 4 / a0
+        let (dc, nyquist) = (1.0 + a1 + a2, 1.0 - a1 + a2);
+        let g = (dc / nyquist).sqrt();
+        let a0 = 4.0 / nyquist;
+        let k = 2.0 * (1.0 - a2) / (nyquist * g);
+        // Of the three parts of the numerator, only the m0 one is left at
+        // nyquist and only the m0 and m2 ones at DC
+        let m0 = a0 * (b0 - b1 + b2) * 0.25;
+        let m2 = a0 * (b0 + b1 + b2) / (4.0 * g * g) - m0;
+        let m1 = (a0 * b0 - m0 * (1.0 + g * k + g * g) - m2 * g * g) / g;
+        Svf2Coefficients::new(1.0, g, k, m0, m1, m2)
+    }
+}
+
+impl From<IIR1Coefficients> for Biquad {
+    /// First order filters leave `b2` and `a2` at 0
+    fn from(coeffs: IIR1Coefficients) -> Biquad {
+        let g = coeffs.g as f64;
+        let (m0, m1) = (coeffs.m0 as f64, coeffs.m1 as f64);
+        let a0 = 1.0 + g;
+        Biquad {
+            b0: (m0 * a0 + m1 * g) / a0,
+            b1: (m0 * (g - 1.0) + m1 * g) / a0,
+            b2: 0.0,
+            a1: (g - 1.0) / a0,
+            a2: 0.0,
+        }
+    }
+}
+
+/// Coefficients of a whole band, for looking at its response
+#[derive(Copy, Clone)]
+pub enum BandCoefficients {
+    FirstOrder(IIR1Coefficients),
+    Cascade(CascadeCoefficients),
+}
+
+impl BandCoefficients {
+    pub fn new(band: &BandSettings, fs: f32) -> BandCoefficients {
+        if band.mode == BandMode::FirstOrder {
+            BandCoefficients::FirstOrder(get_coefficients_iir1(
+                band.kind,
+                band.freq,
+                band.db_gain,
+                fs,
+            ))
+        } else {
+            BandCoefficients::Cascade(get_coefficients_iir2_cascade(
+                band.kind,
+                band.mode,
+                band.design,
+                band.freq,
+                band.db_gain,
+                band.q_value,
+                fs,
+            ))
+        }
+    }
+
+    /// The band as biquads in series
+    pub fn biquads(&self) -> Vec<Biquad> {
+        match self {
+            BandCoefficients::FirstOrder(coeffs) => vec![Biquad::from(*coeffs)],
+            BandCoefficients::Cascade(cascade) => {
+                cascade.iter().map(|c| Biquad::from(*c)).collect()
+            }
+        }
+    }
+
+    /// Complex response of the band at `f_hz`
+    pub fn response(&self, f_hz: f32, fs: f32) -> Complex<f64> {
+        // Rebuilt field by field, basic-audio-filters may use another num-complex
+        match self {
+            BandCoefficients::FirstOrder(coeffs) => {
+                let c = coeffs.get_bode_sample(f_hz, fs);
+                Complex::new(c.re, c.im)
+            }
+            BandCoefficients::Cascade(cascade) => cascade
+                .iter()
+                .map(|coeffs| {
+                    let c = coeffs.get_bode_sample(f_hz, fs);
+                    Complex::new(c.re, c.im)
+                })
+                .product(),
+        }
+    }
+
+    /// Linear gain of the band at `f_hz`
+    pub fn magnitude(&self, f_hz: f32, fs: f32) -> f64 {
+        match self {
+            BandCoefficients::FirstOrder(coeffs) => coeffs.get_bode_sample(f_hz, fs).norm(),
+            BandCoefficients::Cascade(cascade) => cascade
+                .iter()
+                .map(|coeffs| coeffs.get_bode_sample(f_hz, fs).norm())
+                .product(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Magnitude in dB of the analog filter of `kind` at `f_hz`, from the audio EQ
+    /// cookbook prototypes with `A = 10^(db_gain / 40)`
+    fn analog_db(kind: BandKind, f0: f64, db_gain: f64, q_value: f64, f_hz: f64) -> f64 {
+        let a = 10.0f64.powf(db_gain / 40.0);
+        let s = Complex::new(0.0, f_hz / f0);
+        let h = match kind {
+            BandKind::Bell => (s * s + s * (a / q_value) + 1.0) / (s * s + s / (a * q_value) + 1.0),
+            BandKind::LowPass => 1.0 / (s * s + s / q_value + 1.0),
+            BandKind::HighPass => s * s / (s * s + s / q_value + 1.0),
+            BandKind::LowShelf => {
+                let k = a.sqrt() / q_value;
+                (s * s + s * k + a) * a / (s * s * a + s * k + 1.0)
+            }
+            BandKind::HighShelf => {
+                let k = a.sqrt() / q_value;
+                (s * s * a + s * k + 1.0) * a / (s * s + s * k + a)
+            }
+            _ => unreachable!(),
+        };
+        20.0 * h.norm().log10()
+    }
+
+    /// The matched design hits the analog gain at DC and at f0
+    const EXACT_DB: f64 = 0.01;
+    /// Bells, shelves and high passes stay this close everywhere up to 0.45 fs
+    const SHAPE_DB: f64 = 1.0;
+    /// A low pass can't keep falling like the analog one towards Nyquist
+    const LOW_PASS_DB: f64 = 2.0;
+    /// Below -40 dB only rounding and the low pass roll off are left
+    const STOP_DB: f64 = 2.0;
+
+    #[test]
+    fn matched_bands_follow_the_analog_magnitude() {
+        let kinds = [
+            (BandKind::Bell, 9.0),
+            (BandKind::Bell, -9.0),
+            (BandKind::LowShelf, 6.0),
+            (BandKind::LowShelf, -6.0),
+            (BandKind::HighShelf, 6.0),
+            (BandKind::HighShelf, -6.0),
+            (BandKind::LowPass, 0.0),
+            (BandKind::HighPass, 0.0),
+        ];
+        for fs in [44100.0, 48000.0] {
+            for (kind, db_gain) in kinds {
+                for f0 in [100.0, 1000.0, 5000.0, 12000.0] {
+                    for q_value in [0.707, 2.0] {
+                        let band = BandSettings {
+                            kind,
+                            freq: f0 as f32,
+                            db_gain: db_gain as f32,
+                            q_value: q_value as f32,
+                            design: FilterDesign::Matched,
+                            ..BandSettings::default()
+                        };
+                        let coeffs = BandCoefficients::new(&band, fs as f32);
+                        let error = |f_hz: f64| {
+                            let digital = 20.0 * coeffs.magnitude(f_hz as f32, fs as f32).log10();
+                            let analog = analog_db(kind, f0, db_gain, q_value, f_hz);
+                            ((digital - analog).abs(), analog)
+                        };
+                        // A high pass has no gain at DC to match
+                        if kind != BandKind::HighPass {
+                            assert!(error(0.0).0 < EXACT_DB, "{:?} at DC, fs {}", band, fs);
+                        }
+                        assert!(error(f0).0 < EXACT_DB, "{:?} at f0, fs {}", band, fs);
+                        let tolerance = if kind == BandKind::LowPass {
+                            LOW_PASS_DB
+                        } else {
+                            SHAPE_DB
+                        };
+                        for i in 0..64 {
+                            let f_hz = 20.0 * (0.45 * fs / 20.0f64).powf(i as f64 / 63.0);
+                            let (error, analog) = error(f_hz);
+                            let tolerance = if analog > -40.0 { tolerance } else { STOP_DB };
+                            assert!(
+                                error < tolerance,
+                                "{:?} at {} Hz, fs {}: {} dB off",
+                                band,
+                                f_hz,
+                                fs,
+                                error
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
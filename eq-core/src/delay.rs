@@ -0,0 +1,38 @@
+/// Stereo delay line of up to the length it was made with. Used to line the
+/// unprocessed signal up with the latency of the processed one.
+pub(crate) struct Delay<T = f32> {
+    buffer: Vec<[T; 2]>,
+    len: usize,
+    pos: usize,
+}
+
+impl<T: Copy + Default> Delay<T> {
+    /// Delay of 0 samples that can be set up to `capacity`
+    pub fn new(capacity: usize) -> Delay<T> {
+        Delay {
+            buffer: vec![[T::default(); 2]; capacity],
+            len: 0,
+            pos: 0,
+        }
+    }
+
+    /// Change the delay in samples and clear it, does not allocate
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min(self.buffer.len());
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.iter_mut().for_each(|x| *x = [T::default(); 2]);
+        self.pos = 0;
+    }
+
+    pub fn process(&mut self, x: [T; 2]) -> [T; 2] {
+        if self.len == 0 {
+            return x;
+        }
+        let y = std::mem::replace(&mut self.buffer[self.pos], x);
+        self.pos = (self.pos + 1) % self.len;
+        y
+    }
+}
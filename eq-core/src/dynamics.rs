@@ -0,0 +1,82 @@
+use basic_audio_filters::second_order_iir::{IIR2Coefficients, IIR2};
+
+use crate::units::Units;
+
+/// Changes in the dynamic gain smaller than this do not recompute the band coefficients
+pub const GAIN_UPDATE_THRESHOLD_DB: f32 = 0.01;
+
+fn time_coeff(ms: f32, sample_rate: f32) -> f32 {
+    (-1.0 / (ms * 0.001 * sample_rate).max(1.0)).exp()
+}
+
+/// Follows the level of a band's frequency region and turns it into a gain change
+pub struct BandDetector {
+    filter: IIR2,
+    envelope: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+}
+
+impl BandDetector {
+    pub fn new(f0: f32, q_value: f32, sample_rate: f32) -> BandDetector {
+        BandDetector {
+            filter: IIR2::from(IIR2Coefficients::bandpass(f0, 0.0, q_value, sample_rate)),
+            envelope: 0.0,
+            attack_ms: 0.0,
+            release_ms: 0.0,
+            attack_coeff: 0.0,
+            release_coeff: 0.0,
+        }
+    }
+
+    /// Move the detector band pass to follow the band
+    pub fn set_band(&mut self, f0: f32, q_value: f32, sample_rate: f32) {
+        self.filter
+            .update(IIR2Coefficients::bandpass(f0, 0.0, q_value, sample_rate));
+    }
+
+    /// Clear the envelope, and the cached time constants in case the sample rate changed
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.attack_ms = 0.0;
+        self.release_ms = 0.0;
+    }
+
+    /// Feed one detector sample, returns the gain change for the band in dB
+    #[allow(clippy::float_cmp)]
+    pub fn process(
+        &mut self,
+        x: f32,
+        threshold_db: f32,
+        ratio: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        sample_rate: f32,
+    ) -> f32 {
+        if attack_ms != self.attack_ms {
+            self.attack_ms = attack_ms;
+            self.attack_coeff = time_coeff(attack_ms, sample_rate);
+        }
+        if release_ms != self.release_ms {
+            self.release_ms = release_ms;
+            self.release_coeff = time_coeff(release_ms, sample_rate);
+        }
+
+        let level = self.filter.process(x).abs();
+        let coeff = if level > self.envelope {
+            self.attack_coeff
+        } else {
+            self.release_coeff
+        };
+        self.envelope = level + (self.envelope - level) * coeff;
+
+        let over = self.envelope.lin_to_db() - threshold_db;
+        if over > 0.0 {
+            -over * (1.0 - 1.0 / ratio)
+        } else {
+            0.0
+        }
+    }
+}
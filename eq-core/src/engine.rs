@@ -0,0 +1,1230 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+use crate::band::{BandSettings, DeltaMode, DynamicMode, Oversampling, PhaseMode, Precision};
+use crate::band_filter::BandFilter;
+use crate::delay::Delay;
+use crate::dynamics::{BandDetector, GAIN_UPDATE_THRESHOLD_DB};
+use crate::linear_phase::{
+    KernelDesigner, KernelSet, LinearPhaseConvolver, LinearPhaseDesigner, LATENCY,
+};
+use crate::loudness::{LoudnessMeter, MAX_AUTO_GAIN_DB};
+use crate::oversampling::{Oversampler, MAX_OVERSAMPLING};
+use crate::smoother::{Smoother, Smoothing, SmoothingStyle};
+use crate::svf::{Svf2, Svf2Coefficients};
+use crate::units::Units;
+
+/// Time preamp changes are smoothed over
+const PREAMP_SMOOTHING_MS: f32 = 20.0;
+/// Time enabling, soloing and bypassing fade over
+const CROSSFADE_MS: f32 = 10.0;
+/// Time output gain changes, from the trim or auto gain, are smoothed over
+const OUTPUT_GAIN_SMOOTHING_MS: f32 = 50.0;
+/// Most samples processed at once. Changed, smoothing and dynamic bands have their
+/// coefficients updated once a sub-block.
+const SUB_BLOCK: usize = 32;
+
+/// Sends new kernels, receives kernels that are done with
+type KernelLink = (SyncSender<Box<KernelSet>>, Receiver<Box<KernelSet>>);
+
+/// Sample formats `EqEngine::process` takes. The engine works in f64 between its
+/// stages either way.
+pub trait Sample: Copy {
+    fn to_f64(self) -> f64;
+    fn from_f64(x: f64) -> Self;
+}
+
+impl Sample for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(x: f64) -> f32 {
+        x as f32
+    }
+}
+
+impl Sample for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(x: f64) -> f64 {
+        x
+    }
+}
+
+/// How the continuous values of a band are smoothed
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct BandSmoothing {
+    pub freq: Smoothing,
+    pub db_gain: Smoothing,
+    pub q_value: Smoothing,
+}
+
+impl Default for BandSmoothing {
+    fn default() -> Self {
+        BandSmoothing {
+            freq: Smoothing {
+                style: SmoothingStyle::OnePole,
+                ms: 20.0,
+            },
+            db_gain: Smoothing {
+                style: SmoothingStyle::Linear,
+                ms: 20.0,
+            },
+            q_value: Smoothing {
+                style: SmoothingStyle::Linear,
+                ms: 20.0,
+            },
+        }
+    }
+}
+
+/// Smoothing state for the continuous values of one band
+struct BandSmoothers {
+    freq: Smoother,
+    db_gain: Smoother,
+    q_value: Smoother,
+}
+
+impl BandSmoothers {
+    fn new(band: &BandSettings, smoothing: &BandSmoothing) -> BandSmoothers {
+        BandSmoothers {
+            freq: Smoother::new(smoothing.freq.style, band.freq),
+            db_gain: Smoother::new(smoothing.db_gain.style, band.db_gain),
+            q_value: Smoother::new(smoothing.q_value.style, band.q_value),
+        }
+    }
+
+    fn set_styles(&mut self, smoothing: &BandSmoothing) {
+        self.freq.set_style(smoothing.freq.style);
+        self.db_gain.set_style(smoothing.db_gain.style);
+        self.q_value.set_style(smoothing.q_value.style);
+    }
+
+    fn set_targets(&mut self, band: &BandSettings, smoothing: &BandSmoothing, sample_rate: f32) {
+        self.freq
+            .set_target(band.freq, smoothing.freq.ms, sample_rate);
+        self.db_gain
+            .set_target(band.db_gain, smoothing.db_gain.ms, sample_rate);
+        self.q_value
+            .set_target(band.q_value, smoothing.q_value.ms, sample_rate);
+    }
+
+    fn reset(&mut self, band: &BandSettings) {
+        self.freq.reset(band.freq);
+        self.db_gain.reset(band.db_gain);
+        self.q_value.reset(band.q_value);
+    }
+
+    fn is_smoothing(&self) -> bool {
+        self.freq.is_smoothing() || self.db_gain.is_smoothing() || self.q_value.is_smoothing()
+    }
+
+    /// Advance `n` samples at once
+    fn skip(&mut self, n: u32) -> (f32, f32, f32) {
+        (
+            self.freq.skip(n),
+            self.db_gain.skip(n),
+            self.q_value.skip(n),
+        )
+    }
+}
+
+/// The band chain of the EQ, processing plain stereo sample slices. Settings are
+/// handed over with `set_band` and friends, from the thread that processes.
+pub struct EqEngine {
+    sample_rate: f32,
+    bands: Vec<BandSettings>,
+    smoothing: Vec<BandSmoothing>,
+    /// Bands whose settings changed since their coefficients were last computed
+    changed: Vec<bool>,
+    /// Both channels of each band, `filters_f64` are used in double precision
+    filters: Vec<BandFilter<f32>>,
+    filters_f64: Vec<BandFilter<f64>>,
+    precision: Precision,
+    /// Samples between band updates, `SUB_BLOCK` unless set lower
+    update_interval: usize,
+    smoothers: Vec<BandSmoothers>,
+    detectors: Vec<BandDetector>,
+    /// Gain change in dB currently applied to each band by its detector
+    gain_offsets: Vec<f32>,
+    /// Static gain plus gain change of each band
+    live_gains: Vec<f32>,
+    /// Gain in dB applied before the bands
+    preamp: Smoother,
+    preamp_gain: f32,
+    /// How much of each band's filtering is heard, fades when it is enabled or disabled
+    band_mix: Vec<Smoother>,
+    /// Values of each `band_mix` over the current sub-block
+    band_mix_values: Vec<[f32; SUB_BLOCK]>,
+    /// How much of each band's solo bandpass is heard
+    solo_mix: Vec<Smoother>,
+    /// Soloed bandpasses of each band, `solo_filters_f64` are used in double precision
+    solo_filters: Vec<Svf2<f32>>,
+    solo_filters_f64: Vec<Svf2<f64>>,
+    /// How much of the band chain is heard, 0 while any band is soloed
+    chain_mix: Smoother,
+    bypass: bool,
+    /// How much of the processed signal is heard, 0 when bypassed
+    wet_mix: Smoother,
+    /// Lines the unprocessed signal up with the latency of the processed signal,
+    /// for bypassing, soloing and delta listening
+    dry_delay: Delay<f64>,
+    delta: DeltaMode,
+    /// Mode faded out after a change of `delta`
+    previous_delta: DeltaMode,
+    /// Goes from 0 to 1 while fading from `previous_delta` to `delta`
+    delta_fade: Smoother,
+    /// What each band added to the signal over the current sub-block
+    band_deltas: Vec<[[f64; 2]; SUB_BLOCK]>,
+    /// While oversampling, line the band deltas up with the downsampled output
+    band_delta_delays: Vec<Delay<f64>>,
+    /// Which oversampled sample of each sample the band deltas are taken from
+    band_delta_tap: usize,
+    /// Gain in dB applied after the bands, set by hand
+    output_trim: f32,
+    auto_gain: bool,
+    /// Compensation for the loudness change of the bands, while auto gain is on
+    auto_gain_db: f32,
+    /// Bands changed since `auto_gain_db` was worked out
+    loudness_changed: bool,
+    /// Response of the bands for auto gain, kept up to date as bands change
+    loudness: LoudnessMeter,
+    /// Trim plus auto gain, in dB
+    output_gain: Smoother,
+    output_gain_lin: f32,
+    phase_mode: PhaseMode,
+    /// Up and down filters, `oversampler_f64` is used in double precision. Both are
+    /// kept at the same oversampling.
+    oversampler: Oversampler<f32>,
+    oversampler_f64: Oversampler<f64>,
+    linear_phase: LinearPhaseConvolver,
+    /// Ends of the kernel channels, until a designer thread takes them
+    kernel_link: Option<KernelLink>,
+}
+
+impl EqEngine {
+    /// `band_count` bands at their default settings
+    pub fn new(band_count: usize, sample_rate: f32) -> EqEngine {
+        let bands = vec![BandSettings::default(); band_count];
+        let smoothing = vec![BandSmoothing::default(); band_count];
+
+        let coeffs = Svf2Coefficients::bandpass(1000.0, 0.0, 1.0, sample_rate as f64);
+
+        let smoothers = bands
+            .iter()
+            .zip(&smoothing)
+            .map(|(band, smoothing)| BandSmoothers::new(band, smoothing))
+            .collect::<Vec<BandSmoothers>>();
+        let detectors = bands
+            .iter()
+            .map(|band| BandDetector::new(band.freq, band.q_value, sample_rate))
+            .collect::<Vec<BandDetector>>();
+
+        let oversampler = Oversampler::new();
+        let max_latency = LATENCY.max(oversampler.latency_of(Oversampling::X8));
+
+        // One set of kernels in flight each way, see `LinearPhaseDesigner::spawn`
+        let (kernel_sender, kernel_receiver) = sync_channel(1);
+        let (old_kernel_sender, old_kernel_receiver) = sync_channel(1);
+        let linear_phase = LinearPhaseConvolver::new(
+            KernelDesigner::new().design(&bands, sample_rate),
+            kernel_receiver,
+            old_kernel_sender,
+        );
+
+        let loudness = LoudnessMeter::new(&bands, sample_rate);
+
+        let mut engine = EqEngine {
+            sample_rate,
+            live_gains: bands.iter().map(|band| band.db_gain).collect(),
+            bands,
+            smoothing,
+            changed: vec![false; band_count],
+            filters: (0..band_count).map(|_| BandFilter::new()).collect(),
+            filters_f64: (0..band_count).map(|_| BandFilter::new()).collect(),
+            precision: Precision::Single,
+            update_interval: SUB_BLOCK,
+            smoothers,
+            detectors,
+            gain_offsets: vec![0.0; band_count],
+            preamp: Smoother::new(SmoothingStyle::Linear, 0.0),
+            preamp_gain: 1.0,
+            band_mix: vec![Smoother::new(SmoothingStyle::Linear, 1.0); band_count],
+            band_mix_values: vec![[1.0; SUB_BLOCK]; band_count],
+            solo_mix: vec![Smoother::new(SmoothingStyle::Linear, 0.0); band_count],
+            solo_filters: vec![Svf2::new(coeffs); band_count],
+            solo_filters_f64: vec![Svf2::new(coeffs); band_count],
+            chain_mix: Smoother::new(SmoothingStyle::Linear, 1.0),
+            bypass: false,
+            wet_mix: Smoother::new(SmoothingStyle::Linear, 1.0),
+            dry_delay: Delay::new(max_latency),
+            delta: DeltaMode::Off,
+            previous_delta: DeltaMode::Off,
+            delta_fade: Smoother::new(SmoothingStyle::Linear, 1.0),
+            band_deltas: vec![[[0.0; 2]; SUB_BLOCK]; band_count],
+            band_delta_delays: (0..band_count).map(|_| Delay::new(max_latency)).collect(),
+            band_delta_tap: 0,
+            output_trim: 0.0,
+            auto_gain: false,
+            auto_gain_db: 0.0,
+            loudness_changed: false,
+            loudness,
+            output_gain: Smoother::new(SmoothingStyle::Linear, 0.0),
+            output_gain_lin: 1.0,
+            phase_mode: PhaseMode::Minimum,
+            oversampler,
+            oversampler_f64: Oversampler::new(),
+            linear_phase,
+            kernel_link: Some((kernel_sender, old_kernel_receiver)),
+        };
+        engine.reset();
+        engine
+    }
+
+    pub fn band_count(&self) -> usize {
+        self.bands.len()
+    }
+
+    pub fn band(&self, i: usize) -> &BandSettings {
+        &self.bands[i]
+    }
+
+    /// Move band `i` to `band`, frequency, gain and Q are smoothed
+    pub fn set_band(&mut self, i: usize, band: BandSettings) {
+        if band == self.bands[i] {
+            return;
+        }
+        self.bands[i] = band;
+        self.smoothers[i].set_targets(&band, &self.smoothing[i], self.sample_rate);
+        self.changed[i] = true;
+        self.loudness.set_band(i, &band);
+        self.loudness_changed = true;
+        self.set_mix_targets();
+    }
+
+    /// Fade the enabled, solo and bypass mixes toward the current settings
+    fn set_mix_targets(&mut self) {
+        let fs = self.sample_rate;
+        let any_solo = self.bands.iter().any(|band| band.solo);
+        for i in 0..self.bands.len() {
+            let band = self.bands[i];
+            let enabled = if band.enabled { 1.0 } else { 0.0 };
+            if enabled > 0.0 && self.band_mix[i].target() == 0.0 && !self.band_mix[i].is_smoothing()
+            {
+                // The filters were not run while disabled, start them fresh
+                self.reset_band_filters(i);
+            }
+            self.band_mix[i].set_target(enabled, CROSSFADE_MS, fs);
+            let solo = if band.solo { 1.0 } else { 0.0 };
+            if solo > 0.0 && self.solo_mix[i].target() == 0.0 && !self.solo_mix[i].is_smoothing() {
+                self.reset_solo_filters(i);
+            }
+            self.solo_mix[i].set_target(solo, CROSSFADE_MS, fs);
+        }
+        self.chain_mix
+            .set_target(if any_solo { 0.0 } else { 1.0 }, CROSSFADE_MS, fs);
+        self.wet_mix
+            .set_target(if self.bypass { 0.0 } else { 1.0 }, CROSSFADE_MS, fs);
+    }
+
+    fn reset_band_filters(&mut self, i: usize) {
+        self.filters[i].reset();
+        self.filters_f64[i].reset();
+    }
+
+    fn reset_solo_filters(&mut self, i: usize) {
+        self.solo_filters[i].reset();
+        self.solo_filters_f64[i].reset();
+    }
+
+    fn reset_oversamplers(&mut self) {
+        self.oversampler.reset();
+        self.oversampler_f64.reset();
+    }
+
+    pub fn bypass(&self) -> bool {
+        self.bypass
+    }
+
+    /// Fade between the processed and the unprocessed signal
+    pub fn set_bypass(&mut self, bypass: bool) {
+        if bypass != self.bypass {
+            self.bypass = bypass;
+            self.set_mix_targets();
+        }
+    }
+
+    pub fn set_band_smoothing(&mut self, i: usize, smoothing: BandSmoothing) {
+        if smoothing != self.smoothing[i] {
+            self.smoothing[i] = smoothing;
+            self.smoothers[i].set_styles(&smoothing);
+        }
+    }
+
+    pub fn preamp(&self) -> f32 {
+        self.preamp.target()
+    }
+
+    /// Gain in dB applied to the input before the bands, smoothed
+    pub fn set_preamp(&mut self, db: f32) {
+        self.preamp
+            .set_target(db, PREAMP_SMOOTHING_MS, self.sample_rate);
+        if !self.preamp.is_smoothing() {
+            self.preamp_gain = db.db_to_lin();
+        }
+    }
+
+    pub fn output_trim(&self) -> f32 {
+        self.output_trim
+    }
+
+    /// Gain in dB applied after the bands, smoothed
+    pub fn set_output_trim(&mut self, db: f32) {
+        if db != self.output_trim {
+            self.output_trim = db;
+            self.set_output_gain_target();
+        }
+    }
+
+    pub fn auto_gain(&self) -> bool {
+        self.auto_gain
+    }
+
+    /// Turn on compensating for the loudness change of the bands, see
+    /// `loudness_change_db`
+    pub fn set_auto_gain(&mut self, auto_gain: bool) {
+        if auto_gain != self.auto_gain {
+            self.auto_gain = auto_gain;
+            self.loudness_changed = true;
+            self.update_auto_gain();
+        }
+    }
+
+    /// Current auto gain compensation in dB, 0 while auto gain is off
+    pub fn auto_gain_db(&self) -> f32 {
+        self.auto_gain_db
+    }
+
+    fn update_auto_gain(&mut self) {
+        if !self.loudness_changed {
+            return;
+        }
+        self.loudness_changed = false;
+        self.auto_gain_db = if self.auto_gain {
+            (-self.loudness.loudness_change_db(&self.bands))
+                .clamp(-MAX_AUTO_GAIN_DB, MAX_AUTO_GAIN_DB)
+        } else {
+            0.0
+        };
+        self.set_output_gain_target();
+    }
+
+    fn set_output_gain_target(&mut self) {
+        self.output_gain.set_target(
+            self.output_trim + self.auto_gain_db,
+            OUTPUT_GAIN_SMOOTHING_MS,
+            self.sample_rate,
+        );
+        if !self.output_gain.is_smoothing() {
+            self.output_gain_lin = self.output_gain.target().db_to_lin();
+        }
+    }
+
+    pub fn delta(&self) -> DeltaMode {
+        self.delta
+    }
+
+    /// Fade to outputting the difference the EQ, or a single band, makes
+    pub fn set_delta(&mut self, delta: DeltaMode) {
+        if delta != self.delta {
+            self.previous_delta = self.delta;
+            self.delta = delta;
+            self.delta_fade.reset(0.0);
+            self.delta_fade
+                .set_target(1.0, CROSSFADE_MS, self.sample_rate);
+        }
+    }
+
+    pub fn phase_mode(&self) -> PhaseMode {
+        self.phase_mode
+    }
+
+    pub fn set_phase_mode(&mut self, phase_mode: PhaseMode) {
+        if phase_mode != self.phase_mode {
+            self.phase_mode = phase_mode;
+            self.linear_phase.reset();
+            self.filter_rate_changed();
+        }
+    }
+
+    pub fn oversampling(&self) -> Oversampling {
+        self.oversampler.oversampling()
+    }
+
+    /// Run the minimum phase bands at a multiple of the sample rate. Linear phase
+    /// mode is not oversampled.
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        if oversampling != self.oversampler.oversampling() {
+            self.oversampler.set_oversampling(oversampling);
+            self.oversampler_f64.set_oversampling(oversampling);
+            self.filter_rate_changed();
+        }
+    }
+
+    pub fn update_interval(&self) -> usize {
+        self.update_interval
+    }
+
+    /// Update changed, smoothing and dynamic bands every `samples` samples, 1 to 32.
+    /// Every sample is how earlier versions worked, kept to compare against.
+    pub fn set_update_interval(&mut self, samples: usize) {
+        self.update_interval = samples.clamp(1, SUB_BLOCK);
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// Run the minimum phase bands, their oversampling and the solo bandpasses with
+    /// f32 or f64 coefficients and state. The filters of the new precision start
+    /// fresh. Linear phase convolution is f32 either way.
+    pub fn set_precision(&mut self, precision: Precision) {
+        if precision != self.precision {
+            self.precision = precision;
+            for i in 0..self.bands.len() {
+                self.reset_band_filters(i);
+                self.reset_solo_filters(i);
+                self.changed[i] = true;
+            }
+            self.reset_oversamplers();
+        }
+    }
+
+    /// Rate the IIR filters of the bands run at
+    pub fn filter_rate(&self) -> f32 {
+        match self.phase_mode {
+            PhaseMode::Minimum => self.sample_rate * self.oversampler.factor() as f32,
+            PhaseMode::Linear => self.sample_rate,
+        }
+    }
+
+    /// Latency in samples of the current phase mode and oversampling
+    pub fn latency(&self) -> usize {
+        match self.phase_mode {
+            PhaseMode::Minimum => self.oversampler.latency(),
+            PhaseMode::Linear => LATENCY,
+        }
+    }
+
+    /// Start the filters fresh with coefficients for the new filter rate, and
+    /// line the delays up with the new latency
+    fn filter_rate_changed(&mut self) {
+        for i in 0..self.bands.len() {
+            self.reset_band_filters(i);
+            self.changed[i] = true;
+        }
+        self.loudness.set_rate(&self.bands, self.filter_rate());
+        self.loudness_changed = true;
+        self.reset_oversamplers();
+        self.dry_delay.set_len(self.latency());
+        // Band deltas are taken from the first oversampled sample that is a whole
+        // number of samples away from the output of the down filters
+        let factor = self.oversampler.factor();
+        let output_delay = self.oversampler.output_delay();
+        let delay = output_delay.div_ceil(factor);
+        self.band_delta_tap = delay * factor - output_delay;
+        for band_delta_delay in &mut self.band_delta_delays {
+            band_delta_delay.set_len(delay);
+        }
+    }
+
+    /// Gain band `i` is running at, including the gain change of a dynamic band
+    pub fn band_gain(&self, i: usize) -> f32 {
+        self.live_gains[i]
+    }
+
+    /// Set the sample rate and `reset`, not for use while processing
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    /// Jump every band to its settings without smoothing, `clear_buffers` and design
+    /// new kernels. Not for use while processing.
+    pub fn reset(&mut self) {
+        for i in 0..self.bands.len() {
+            let band = self.bands[i];
+            self.smoothers[i].reset(&band);
+            self.live_gains[i] = band.db_gain;
+            self.changed[i] = false;
+            self.update_filter(i, band.freq, band.db_gain, band.q_value);
+        }
+        self.clear_buffers();
+        self.preamp.reset(self.preamp.target());
+        self.preamp_gain = self.preamp.target().db_to_lin();
+        self.loudness.set_rate(&self.bands, self.filter_rate());
+        self.loudness_changed = true;
+        self.update_auto_gain();
+        self.output_gain.reset(self.output_gain.target());
+        self.output_gain_lin = self.output_gain.target().db_to_lin();
+        self.set_mix_targets();
+        for mix in self
+            .band_mix
+            .iter_mut()
+            .chain(self.solo_mix.iter_mut())
+            .chain([&mut self.chain_mix, &mut self.wet_mix, &mut self.delta_fade])
+        {
+            mix.reset(mix.target());
+        }
+        for (value, mix) in self.band_mix_values.iter_mut().zip(&self.band_mix) {
+            *value = [mix.target(); SUB_BLOCK];
+        }
+        self.linear_phase
+            .set_kernels(KernelDesigner::new().design(&self.bands, self.sample_rate));
+    }
+
+    /// Clear the filter and detector state, the delays and the oversampling and
+    /// linear phase buffers, keeping the settings and kernels. Does not allocate,
+    /// so hosts can call it from the audio thread.
+    pub fn clear_buffers(&mut self) {
+        for i in 0..self.bands.len() {
+            self.reset_band_filters(i);
+            self.reset_solo_filters(i);
+            self.detectors[i].reset();
+            self.gain_offsets[i] = 0.0;
+        }
+        self.dry_delay.reset();
+        self.band_delta_delays
+            .iter_mut()
+            .for_each(|delay| delay.reset());
+        self.reset_oversamplers();
+        self.linear_phase.reset();
+    }
+
+    /// Start designing linear phase kernels on a background thread, see
+    /// `LinearPhaseDesigner::spawn` for `settings`. Only one designer can be started,
+    /// later calls return `None`.
+    pub fn spawn_kernel_designer<F>(&mut self, settings: F) -> Option<LinearPhaseDesigner>
+    where
+        F: FnMut() -> Option<(Vec<BandSettings>, f32)> + Send + 'static,
+    {
+        let (kernels, old_kernels) = self.kernel_link.take()?;
+        Some(LinearPhaseDesigner::spawn(settings, kernels, old_kernels))
+    }
+
+    fn update_filter(&mut self, i: usize, f0: f32, db_gain: f32, q_value: f32) {
+        let fs = self.filter_rate() as f64;
+        let band = self.bands[i];
+        let (f0, db_gain, q_value) = (f0 as f64, db_gain as f64, q_value as f64);
+        match self.precision {
+            Precision::Single => self.filters[i].update(&band, f0, db_gain, q_value, fs),
+            Precision::Double => self.filters_f64[i].update(&band, f0, db_gain, q_value, fs),
+        }
+        // Soloing and the detectors are not oversampled
+        let fs = self.sample_rate;
+        let mut coeffs = Svf2Coefficients::bandpass(f0, 0.0, q_value, fs as f64);
+        // The bandpass peaks at Q, scale it to unity at the band frequency
+        coeffs.m1 = coeffs.k;
+        match self.precision {
+            Precision::Single => self.solo_filters[i].update(coeffs),
+            Precision::Double => self.solo_filters_f64[i].update(coeffs),
+        }
+        // Kept in step even while the band is static, so it is ready when switched to dynamic
+        self.detectors[i].set_band(f0 as f32, q_value as f32, fs);
+    }
+
+    /// Advance smoothing and dynamics over a sub-block, recomputing coefficients
+    /// where needed. The detectors see the whole sub-block first.
+    fn update_bands(&mut self, input_mono: &[f32], sidechain_mono: &[f32]) {
+        for i in 0..self.bands.len() {
+            let band = self.bands[i];
+            let dynamic = if band.kind.uses_gain() {
+                band.dynamic
+            } else {
+                DynamicMode::Off
+            };
+            let gain_offset = match dynamic {
+                DynamicMode::Off => 0.0,
+                DynamicMode::On | DynamicMode::Sidechain => {
+                    let detector_input = if dynamic == DynamicMode::On {
+                        input_mono
+                    } else {
+                        sidechain_mono
+                    };
+                    // The gain reached at the end of the sub-block
+                    let mut gain_offset = 0.0;
+                    for x in detector_input {
+                        gain_offset = self.detectors[i].process(
+                            *x,
+                            band.threshold,
+                            band.ratio,
+                            band.attack,
+                            band.release,
+                            self.sample_rate,
+                        );
+                    }
+                    gain_offset
+                }
+            };
+            let gain_moved = (gain_offset - self.gain_offsets[i]).abs() > GAIN_UPDATE_THRESHOLD_DB;
+
+            if !self.changed[i] && !self.smoothers[i].is_smoothing() && !gain_moved {
+                continue;
+            }
+            self.changed[i] = false;
+            if gain_moved {
+                self.gain_offsets[i] = gain_offset;
+            }
+            let (f0, db_gain, q_value) = self.smoothers[i].skip(input_mono.len() as u32);
+            let db_gain = db_gain + self.gain_offsets[i];
+            self.live_gains[i] = db_gain;
+            self.update_filter(i, f0, db_gain, q_value);
+        }
+    }
+
+    /// Run `block` through the filters of band `i`, see `BandFilter::process_block`
+    fn process_band(&mut self, i: usize, block: &mut [[f64; 2]], factor: usize, tap: usize) {
+        let routing = self.bands[i].routing;
+        let mix = &self.band_mix_values[i][..block.len() / factor];
+        let deltas = &mut self.band_deltas[i];
+        match self.precision {
+            Precision::Single => {
+                self.filters[i].process_block(routing, block, mix, factor, tap, deltas)
+            }
+            Precision::Double => {
+                self.filters_f64[i].process_block(routing, block, mix, factor, tap, deltas)
+            }
+        }
+    }
+
+    /// Run `block` through every band's IIR filters, one band at a time in band order.
+    /// Each sample is `factor` samples of `block`, the band deltas are taken from
+    /// the one at `tap`.
+    fn process_bands(&mut self, block: &mut [[f64; 2]], factor: usize, tap: usize) {
+        let len = block.len() / factor;
+        for i in 0..self.bands.len() {
+            if self.band_mix_values[i][..len].iter().all(|mix| *mix == 0.0) {
+                self.band_deltas[i][..len].fill([0.0; 2]);
+                continue;
+            }
+            self.process_band(i, block, factor, tap);
+        }
+    }
+
+    /// Run `block` through the band chain at the oversampled rate
+    fn process_bands_oversampled(&mut self, block: &mut [[f64; 2]]) {
+        let factor = self.oversampler.factor();
+        let mut oversampled = [[0.0; 2]; SUB_BLOCK * MAX_OVERSAMPLING];
+        let mut samples = [[0.0; 2]; MAX_OVERSAMPLING];
+        for (x, oversampled) in block.iter().zip(oversampled.chunks_mut(factor)) {
+            match self.precision {
+                Precision::Single => self.oversampler.upsample(*x, &mut samples),
+                Precision::Double => self.oversampler_f64.upsample(*x, &mut samples),
+            };
+            oversampled.copy_from_slice(&samples[..factor]);
+        }
+        let len = block.len();
+        self.process_bands(
+            &mut oversampled[..len * factor],
+            factor,
+            self.band_delta_tap,
+        );
+        for (deltas, delay) in self
+            .band_deltas
+            .iter_mut()
+            .zip(self.band_delta_delays.iter_mut())
+        {
+            for delta in &mut deltas[..len] {
+                *delta = delay.process(*delta);
+            }
+        }
+        for (x, oversampled) in block.iter_mut().zip(oversampled.chunks(factor)) {
+            samples[..factor].copy_from_slice(oversampled);
+            *x = match self.precision {
+                Precision::Single => self.oversampler.downsample(&samples),
+                Precision::Double => self.oversampler_f64.downsample(&samples),
+            };
+        }
+    }
+
+    /// In linear phase mode the band chain is not run, the bands delta listening
+    /// needs are run on their own over `input` instead
+    fn process_delta_bands(&mut self, input: &[[f64; 2]]) {
+        let fading = self.delta_fade.is_smoothing();
+        for (delta, used) in [(self.delta, true), (self.previous_delta, fading)].iter() {
+            if let (DeltaMode::Band(i), true) = (*delta, *used) {
+                if i < self.bands.len() {
+                    let mut block = [[0.0; 2]; SUB_BLOCK];
+                    block[..input.len()].copy_from_slice(input);
+                    self.process_band(i, &mut block[..input.len()], 1, 0);
+                }
+            }
+        }
+    }
+
+    /// The output of delta mode `delta` for sample `n` of the sub-block
+    fn delta_output(&self, delta: DeltaMode, wet: [f64; 2], dry: [f64; 2], n: usize) -> [f64; 2] {
+        match delta {
+            DeltaMode::Off => wet,
+            DeltaMode::All => [wet[0] - dry[0], wet[1] - dry[1]],
+            DeltaMode::Band(i) => self.band_deltas.get(i).map_or([0.0; 2], |deltas| deltas[n]),
+        }
+    }
+
+    /// Sum of the soloed bands' bandpasses
+    fn process_solo(&mut self, x: [f64; 2]) -> [f64; 2] {
+        let mut solo = [0.0; 2];
+        for i in 0..self.bands.len() {
+            let mix = self.solo_mix[i].next() as f64;
+            if mix == 0.0 {
+                continue;
+            }
+            let y = match self.precision {
+                Precision::Single => {
+                    let y = self.solo_filters[i].process([x[0] as f32, x[1] as f32]);
+                    [y[0] as f64, y[1] as f64]
+                }
+                Precision::Double => self.solo_filters_f64[i].process(x),
+            };
+            solo[0] += y[0] * mix;
+            solo[1] += y[1] * mix;
+        }
+        solo
+    }
+
+    /// Process a block of stereo audio in place, in f32 or f64. `sidechain` feeds the
+    /// dynamic bands that are in sidechain mode, without it they follow the main input.
+    /// Only as many samples as the shorter channel holds are processed, the rest of
+    /// the longer one is left as it is. A sidechain shorter than that is ignored.
+    pub fn process<S: Sample>(&mut self, channels: [&mut [S]; 2], sidechain: Option<[&[S]; 2]>) {
+        let [left, right] = channels;
+        let len = left.len().min(right.len());
+        let (left, right) = (&mut left[..len], &mut right[..len]);
+        let sidechain = sidechain.filter(|[l, r]| l.len() >= len && r.len() >= len);
+        self.update_auto_gain();
+        let mut start = 0;
+        let interval = self.update_interval;
+        for (left, right) in left.chunks_mut(interval).zip(right.chunks_mut(interval)) {
+            let end = start + left.len();
+            let sidechain = sidechain.map(|[l, r]| [&l[start..end], &r[start..end]]);
+            self.process_sub_block([left, right], sidechain);
+            start = end;
+        }
+    }
+
+    /// `process` for up to `update_interval` samples, with the bands updated once
+    fn process_sub_block<S: Sample>(
+        &mut self,
+        channels: [&mut [S]; 2],
+        sidechain: Option<[&[S]; 2]>,
+    ) {
+        let [left, right] = channels;
+        let len = left.len();
+        let mut block = [[0.0; 2]; SUB_BLOCK];
+        let mut dry = [[0.0; 2]; SUB_BLOCK];
+        let mut preamp_gains = [0.0; SUB_BLOCK];
+        let mut input_mono = [0.0; SUB_BLOCK];
+        for n in 0..len {
+            let x = [left[n].to_f64(), right[n].to_f64()];
+            dry[n] = self.dry_delay.process(x);
+            if self.preamp.is_smoothing() {
+                self.preamp_gain = self.preamp.next().db_to_lin();
+            }
+            let gain = self.preamp_gain as f64;
+            preamp_gains[n] = gain;
+            block[n] = [x[0] * gain, x[1] * gain];
+            input_mono[n] = ((block[n][0] + block[n][1]) * 0.5) as f32;
+        }
+        let mut sidechain_mono = input_mono;
+        if let Some([sidechain_l, sidechain_r]) = sidechain {
+            for (mono, (l, r)) in sidechain_mono
+                .iter_mut()
+                .zip(sidechain_l.iter().zip(sidechain_r))
+            {
+                *mono = ((l.to_f64() + r.to_f64()) * 0.5) as f32;
+            }
+        }
+        self.update_bands(&input_mono[..len], &sidechain_mono[..len]);
+        for (values, mix) in self.band_mix_values.iter_mut().zip(&mut self.band_mix) {
+            for value in &mut values[..len] {
+                *value = mix.next();
+            }
+        }
+
+        let delta_used = self.delta != DeltaMode::Off || self.delta_fade.is_smoothing();
+        let block = &mut block[..len];
+        if self.phase_mode == PhaseMode::Linear {
+            if delta_used {
+                let mut input = [[0.0; 2]; SUB_BLOCK];
+                for n in 0..len {
+                    input[n] = [dry[n][0] * preamp_gains[n], dry[n][1] * preamp_gains[n]];
+                }
+                self.process_delta_bands(&input[..len]);
+            }
+            for x in block.iter_mut() {
+                let (l, r) = self.linear_phase.process(x[0] as f32, x[1] as f32);
+                *x = [l as f64, r as f64];
+            }
+        } else if self.oversampler.factor() > 1 {
+            self.process_bands_oversampled(block);
+        } else {
+            self.process_bands(block, 1, 0);
+        }
+
+        for n in 0..len {
+            if self.output_gain.is_smoothing() {
+                self.output_gain_lin = self.output_gain.next().db_to_lin();
+            }
+            let gain = self.output_gain_lin as f64;
+            let mut wet = [block[n][0] * gain, block[n][1] * gain];
+
+            let chain_mix = self.chain_mix.next() as f64;
+            if chain_mix < 1.0 {
+                let solo =
+                    self.process_solo([dry[n][0] * preamp_gains[n], dry[n][1] * preamp_gains[n]]);
+                wet = [wet[0] * chain_mix + solo[0], wet[1] * chain_mix + solo[1]];
+            }
+            if delta_used {
+                let fade = self.delta_fade.next() as f64;
+                let current = self.delta_output(self.delta, wet, dry[n], n);
+                let previous = self.delta_output(self.previous_delta, wet, dry[n], n);
+                wet = [
+                    previous[0] + (current[0] - previous[0]) * fade,
+                    previous[1] + (current[1] - previous[1]) * fade,
+                ];
+            }
+            let wet_mix = self.wet_mix.next() as f64;
+            left[n] = S::from_f64(dry[n][0] + (wet[0] - dry[n][0]) * wet_mix);
+            right[n] = S::from_f64(dry[n][1] + (wet[1] - dry[n][1]) * wet_mix);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::band::{BandKind, DynamicMode};
+
+    use std::f64::consts::PI;
+
+    const SAMPLE_RATE: f64 = 192000.0;
+
+    /// The same low shelf as an f64 transposed direct form II biquad, from the
+    /// audio EQ cookbook
+    fn reference_low_shelf(x: &[f64], f0: f64, db_gain: f64, q_value: f64, fs: f64) -> Vec<f64> {
+        let a = 10.0f64.powf(db_gain / 40.0);
+        let w0 = 2.0 * PI * f0 / fs;
+        let alpha = w0.sin() / (2.0 * q_value);
+        let cos = w0.cos();
+        let sqrt_a = a.sqrt();
+        let b0 = a * ((a + 1.0) - (a - 1.0) * cos + 2.0 * sqrt_a * alpha);
+        let b1 = 2.0 * a * ((a - 1.0) - (a + 1.0) * cos);
+        let b2 = a * ((a + 1.0) - (a - 1.0) * cos - 2.0 * sqrt_a * alpha);
+        let a0 = (a + 1.0) + (a - 1.0) * cos + 2.0 * sqrt_a * alpha;
+        let a1 = -2.0 * ((a - 1.0) + (a + 1.0) * cos);
+        let a2 = (a + 1.0) + (a - 1.0) * cos - 2.0 * sqrt_a * alpha;
+        let (mut s1, mut s2) = (0.0, 0.0);
+        x.iter()
+            .map(|x| {
+                let y = (b0 * x + s1) / a0;
+                s1 = b1 * x - a1 * y + s2;
+                s2 = b2 * x - a2 * y;
+                y
+            })
+            .collect()
+    }
+
+    /// RMS of the difference from `reference` in dBFS, over both channels
+    fn residual_db(left: &[f64], right: &[f64], reference: &[f64]) -> f64 {
+        let power = left
+            .iter()
+            .chain(right)
+            .zip(reference.iter().chain(reference))
+            .map(|(y, r)| (y - r).powi(2))
+            .sum::<f64>()
+            / (2 * reference.len()) as f64;
+        10.0 * power.max(1e-40).log10()
+    }
+
+    /// `reference_low_shelf` run at the rate of `oversampling`, between the f64 up and
+    /// down filters
+    fn reference_oversampled_low_shelf(
+        x: &[f64],
+        f0: f64,
+        db_gain: f64,
+        q_value: f64,
+        oversampling: Oversampling,
+    ) -> Vec<f64> {
+        let mut oversampler = Oversampler::<f64>::new();
+        oversampler.set_oversampling(oversampling);
+        let factor = oversampling.factor();
+        let mut samples = [[0.0; 2]; MAX_OVERSAMPLING];
+        let mut oversampled = Vec::new();
+        for x in x {
+            oversampler.upsample([*x; 2], &mut samples);
+            oversampled.extend(samples[..factor].iter().map(|sample| sample[0]));
+        }
+        let fs = SAMPLE_RATE * factor as f64;
+        let filtered = reference_low_shelf(&oversampled, f0, db_gain, q_value, fs);
+        filtered
+            .chunks(factor)
+            .map(|chunk| {
+                for (sample, y) in samples.iter_mut().zip(chunk) {
+                    *sample = [*y; 2];
+                }
+                oversampler.downsample(&samples)[0]
+            })
+            .collect()
+    }
+
+    /// Residual of a low shelf at `f0` run through the engine at `precision` and
+    /// `oversampling`
+    fn low_shelf_residual(precision: Precision, oversampling: Oversampling, f0: f32) -> f64 {
+        let (db_gain, q_value) = (12.0, 0.707);
+        let input = (0..2 * SAMPLE_RATE as usize)
+            .map(|n| {
+                let t = n as f64 / SAMPLE_RATE;
+                0.5 * (2.0 * PI * 25.0 * t).sin()
+                    + 0.25 * (2.0 * PI * 1000.0 * t).sin()
+                    + 0.1 * (2.0 * PI * 7000.0 * t).sin()
+            })
+            .collect::<Vec<f64>>();
+
+        let mut engine = EqEngine::new(1, SAMPLE_RATE as f32);
+        engine.set_band(
+            0,
+            BandSettings {
+                kind: BandKind::LowShelf,
+                freq: f0,
+                db_gain,
+                q_value,
+                ..BandSettings::default()
+            },
+        );
+        engine.set_precision(precision);
+        engine.set_oversampling(oversampling);
+        engine.reset();
+        let (mut left, mut right) = (input.clone(), input.clone());
+        for (left, right) in left.chunks_mut(512).zip(right.chunks_mut(512)) {
+            engine.process([left, right], None);
+        }
+        let (f0, db_gain, q_value) = (f0 as f64, db_gain as f64, q_value as f64);
+        let reference = reference_oversampled_low_shelf(&input, f0, db_gain, q_value, oversampling);
+        residual_db(&left, &right, &reference)
+    }
+
+    /// A block of a few sines, long enough for several sub-blocks
+    fn test_signal(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|n| (n as f32 * 0.05).sin() * 0.5 + (n as f32 * 0.7).sin() * 0.25)
+            .collect()
+    }
+
+    /// An engine with a bell and a sidechain driven dynamic bell
+    fn sidechain_engine() -> EqEngine {
+        let mut engine = EqEngine::new(2, 48000.0);
+        engine.set_band(
+            0,
+            BandSettings {
+                db_gain: 6.0,
+                ..BandSettings::default()
+            },
+        );
+        engine.set_band(
+            1,
+            BandSettings {
+                freq: 300.0,
+                db_gain: 6.0,
+                dynamic: DynamicMode::Sidechain,
+                threshold: -60.0,
+                ..BandSettings::default()
+            },
+        );
+        engine.reset();
+        engine
+    }
+
+    #[test]
+    fn channels_of_different_lengths_process_the_shorter_length() {
+        let input = test_signal(200);
+        let mut expected = [input[..150].to_vec(), input[..150].to_vec()];
+        let [l, r] = &mut expected;
+        sidechain_engine().process([l, r], None);
+
+        let mut left = input.clone();
+        let mut right = input[..150].to_vec();
+        sidechain_engine().process([&mut left, &mut right], None);
+        assert_eq!(left[..150], expected[0][..]);
+        assert_eq!(left[150..], input[150..]);
+        assert_eq!(right, expected[1]);
+    }
+
+    #[test]
+    fn short_sidechains_are_ignored() {
+        let input = test_signal(200);
+        let mut expected = [input.clone(), input.clone()];
+        let [l, r] = &mut expected;
+        sidechain_engine().process([l, r], None);
+
+        let loud = vec![1.0; 200];
+        let mut with_sidechain = [input.clone(), input.clone()];
+        let [l, r] = &mut with_sidechain;
+        sidechain_engine().process([l, r], Some([&loud, &loud]));
+        assert_ne!(with_sidechain, expected);
+
+        for sidechain_len in [0, 100, 199] {
+            let mut output = [input.clone(), input.clone()];
+            let [l, r] = &mut output;
+            let sidechain = &loud[..sidechain_len];
+            sidechain_engine().process([l, r], Some([sidechain, &loud]));
+            assert_eq!(output, expected, "sidechain of {}", sidechain_len);
+        }
+    }
+
+    /// Left output of `input` run through a fresh one band engine
+    fn render(
+        band: BandSettings,
+        oversampling: Oversampling,
+        delta: DeltaMode,
+        input: &[f32],
+    ) -> (Vec<f32>, usize) {
+        let mut engine = EqEngine::new(1, 48000.0);
+        engine.set_band(0, band);
+        engine.set_oversampling(oversampling);
+        engine.set_delta(delta);
+        engine.reset();
+        let (mut left, mut right) = (input.to_vec(), input.to_vec());
+        engine.process([&mut left, &mut right], None);
+        (left, engine.latency())
+    }
+
+    const OVERSAMPLED: [Oversampling; 3] = [Oversampling::X2, Oversampling::X4, Oversampling::X8];
+
+    #[test]
+    fn oversampled_impulses_peak_at_the_reported_latency() {
+        let mut impulse = vec![0.0; 512];
+        impulse[0] = 1.0;
+        for oversampling in OVERSAMPLED {
+            let (output, latency) = render(
+                BandSettings::default(),
+                oversampling,
+                DeltaMode::Off,
+                &impulse,
+            );
+            let peak = (0..output.len())
+                .max_by(|a, b| output[*a].abs().total_cmp(&output[*b].abs()))
+                .unwrap();
+            assert_eq!(peak, latency, "{:?}", oversampling);
+            assert!(latency > 0);
+        }
+    }
+
+    #[test]
+    fn oversampled_band_deltas_line_up_with_the_dry_path() {
+        let input = test_signal(8192);
+        let bell = BandSettings {
+            freq: 1000.0,
+            db_gain: 6.0,
+            q_value: 1.0,
+            ..BandSettings::default()
+        };
+        for oversampling in OVERSAMPLED {
+            let (wet, latency) = render(bell, oversampling, DeltaMode::Off, &input);
+            let (delta, _) = render(bell, oversampling, DeltaMode::Band(0), &input);
+            // What the band adds on top of the dry signal, delayed by the latency,
+            // makes up the processed output. Past the start, where the filters
+            // settle.
+            let skip = 1024;
+            let error = (skip..input.len())
+                .map(|n| (wet[n] - delta[n] - input[n - latency]).powi(2))
+                .sum::<f32>();
+            let power = (skip..input.len())
+                .map(|n| input[n - latency].powi(2))
+                .sum::<f32>();
+            let error_db = 10.0 * (error / power).log10();
+            assert!(error_db < -60.0, "{:?}: {:.1} dB", oversampling, error_db);
+        }
+    }
+
+    #[test]
+    fn auto_gain_follows_band_and_rate_changes() {
+        let mut engine = sidechain_engine();
+        engine.set_auto_gain(true);
+        let expected = |engine: &EqEngine| {
+            let bands = (0..engine.band_count())
+                .map(|i| *engine.band(i))
+                .collect::<Vec<BandSettings>>();
+            -crate::loudness_change_db(&bands, engine.filter_rate())
+        };
+        let process = |engine: &mut EqEngine| {
+            let (mut left, mut right) = (vec![0.0f32; 64], vec![0.0f32; 64]);
+            engine.process([&mut left, &mut right], None);
+        };
+        process(&mut engine);
+        assert_eq!(engine.auto_gain_db(), expected(&engine));
+        engine.set_band(
+            1,
+            BandSettings {
+                kind: BandKind::HighShelf,
+                freq: 4000.0,
+                db_gain: -4.0,
+                ..*engine.band(1)
+            },
+        );
+        process(&mut engine);
+        assert_eq!(engine.auto_gain_db(), expected(&engine));
+        engine.set_oversampling(Oversampling::X2);
+        process(&mut engine);
+        assert_eq!(engine.auto_gain_db(), expected(&engine));
+    }
+
+    #[test]
+    fn cleared_buffers_leave_nothing_of_earlier_audio() {
+        for (phase_mode, oversampling) in [
+            (PhaseMode::Minimum, Oversampling::X4),
+            (PhaseMode::Linear, Oversampling::Off),
+        ] {
+            let mut engine = sidechain_engine();
+            engine.set_phase_mode(phase_mode);
+            engine.set_oversampling(oversampling);
+            let input = test_signal(8192);
+            let (mut left, mut right) = (input.clone(), input);
+            engine.process([&mut left, &mut right], None);
+
+            engine.clear_buffers();
+            let (mut left, mut right) = (vec![0.0f32; 8192], vec![0.0f32; 8192]);
+            engine.process([&mut left, &mut right], None);
+            assert!(
+                left.iter().chain(&right).all(|x| *x == 0.0),
+                "{:?}",
+                phase_mode
+            );
+        }
+    }
+
+    #[test]
+    fn double_precision_low_shelves_stay_below_the_noise_floor() {
+        for oversampling in [Oversampling::Off, Oversampling::X4] {
+            for f0 in [20.0, 30.0, 40.0] {
+                let single = low_shelf_residual(Precision::Single, oversampling, f0);
+                let double = low_shelf_residual(Precision::Double, oversampling, f0);
+                assert!(
+                    double < -150.0,
+                    "{:?}, {} Hz: double {:.1} dBFS",
+                    oversampling,
+                    f0,
+                    double
+                );
+                assert!(
+                    double < single - 40.0,
+                    "{:?}, {} Hz: single {:.1} dBFS",
+                    oversampling,
+                    f0,
+                    single
+                );
+            }
+        }
+    }
+}
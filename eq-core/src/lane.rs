@@ -0,0 +1,167 @@
+use crate::engine::Sample;
+
+use std::ops::{Add, Mul, Sub};
+
+/// Float types the filters run in, f32 or f64, with the pair type that packs two
+/// of them into one SIMD register
+pub trait Lane: Sample + Default {
+    type Pair: Pair<Self>;
+}
+
+/// Two lanes with element wise arithmetic, for running both channels of a band
+/// through the same filter at once
+pub trait Pair<T>: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> {
+    fn new(x: [T; 2]) -> Self;
+    /// Both lanes set to `x`
+    fn splat(x: T) -> Self;
+    fn to_array(self) -> [T; 2];
+}
+
+impl Lane for f32 {
+    type Pair = F32x2;
+}
+
+impl Lane for f64 {
+    type Pair = F64x2;
+}
+
+pub use packed::{F32x2, F64x2};
+
+macro_rules! impl_ops {
+    ($pair:ident, $add:ident, $sub:ident, $mul:ident) => {
+        impl Add for $pair {
+            type Output = $pair;
+            #[inline]
+            fn add(self, other: $pair) -> $pair {
+                $pair(unsafe { $add(self.0, other.0) })
+            }
+        }
+
+        impl Sub for $pair {
+            type Output = $pair;
+            #[inline]
+            fn sub(self, other: $pair) -> $pair {
+                $pair(unsafe { $sub(self.0, other.0) })
+            }
+        }
+
+        impl Mul for $pair {
+            type Output = $pair;
+            #[inline]
+            fn mul(self, other: $pair) -> $pair {
+                $pair(unsafe { $mul(self.0, other.0) })
+            }
+        }
+    };
+}
+
+/// SSE2 is part of every x86_64 target, so no runtime check is needed. Newer Rust
+/// lets the intrinsics without pointers be called without `unsafe`, older Rust
+/// does not, hence the `allow`.
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+#[allow(unused_unsafe)]
+mod packed {
+    use super::*;
+
+    use std::arch::x86_64::*;
+
+    #[derive(Clone, Copy)]
+    pub struct F64x2(__m128d);
+
+    impl Pair<f64> for F64x2 {
+        #[inline]
+        fn new(x: [f64; 2]) -> F64x2 {
+            F64x2(unsafe { _mm_set_pd(x[1], x[0]) })
+        }
+
+        #[inline]
+        fn splat(x: f64) -> F64x2 {
+            F64x2(unsafe { _mm_set1_pd(x) })
+        }
+
+        #[inline]
+        fn to_array(self) -> [f64; 2] {
+            let mut x = [0.0; 2];
+            unsafe { _mm_storeu_pd(x.as_mut_ptr(), self.0) };
+            x
+        }
+    }
+
+    impl_ops!(F64x2, _mm_add_pd, _mm_sub_pd, _mm_mul_pd);
+
+    /// Two f32 in the low half of an SSE register, the high half is unused
+    #[derive(Clone, Copy)]
+    pub struct F32x2(__m128);
+
+    impl Pair<f32> for F32x2 {
+        #[inline]
+        fn new(x: [f32; 2]) -> F32x2 {
+            F32x2(unsafe { _mm_set_ps(0.0, 0.0, x[1], x[0]) })
+        }
+
+        #[inline]
+        fn splat(x: f32) -> F32x2 {
+            F32x2(unsafe { _mm_set1_ps(x) })
+        }
+
+        #[inline]
+        fn to_array(self) -> [f32; 2] {
+            let mut x = [0.0; 4];
+            unsafe { _mm_storeu_ps(x.as_mut_ptr(), self.0) };
+            [x[0], x[1]]
+        }
+    }
+
+    impl_ops!(F32x2, _mm_add_ps, _mm_sub_ps, _mm_mul_ps);
+}
+
+/// Plain arrays elsewhere, which the compiler can still vectorize on its own
+#[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+#[allow(unused_unsafe)]
+mod packed {
+    use super::*;
+
+    #[inline]
+    fn add<T: Add<Output = T> + Copy>(a: [T; 2], b: [T; 2]) -> [T; 2] {
+        [a[0] + b[0], a[1] + b[1]]
+    }
+
+    #[inline]
+    fn sub<T: Sub<Output = T> + Copy>(a: [T; 2], b: [T; 2]) -> [T; 2] {
+        [a[0] - b[0], a[1] - b[1]]
+    }
+
+    #[inline]
+    fn mul<T: Mul<Output = T> + Copy>(a: [T; 2], b: [T; 2]) -> [T; 2] {
+        [a[0] * b[0], a[1] * b[1]]
+    }
+
+    macro_rules! scalar_pair {
+        ($pair:ident, $t:ty) => {
+            #[derive(Clone, Copy)]
+            pub struct $pair([$t; 2]);
+
+            impl Pair<$t> for $pair {
+                #[inline]
+                fn new(x: [$t; 2]) -> $pair {
+                    $pair(x)
+                }
+
+                #[inline]
+                fn splat(x: $t) -> $pair {
+                    $pair([x; 2])
+                }
+
+                #[inline]
+                fn to_array(self) -> [$t; 2] {
+                    self.0
+                }
+            }
+
+            impl_ops!($pair, add, sub, mul);
+        };
+    }
+
+    scalar_pair!(F64x2, f64);
+    scalar_pair!(F32x2, f32);
+}
@@ -0,0 +1,33 @@
+//! Host independent DSP of the Basic EQ plugin. `EqEngine` runs the band chain over
+//! plain sample slices, so the plugin wrappers, the editor and offline tools share it.
+
+mod band;
+mod band_filter;
+mod coefficients;
+mod delay;
+mod dynamics;
+mod engine;
+mod lane;
+mod linear_phase;
+mod loudness;
+mod oversampling;
+mod smoother;
+mod svf;
+pub mod units;
+
+pub use band::{
+    BandKind, BandMode, BandRouting, BandSettings, DeltaMode, DynamicMode, FilterDesign,
+    Oversampling, PhaseMode, Precision,
+};
+pub use coefficients::{
+    get_coefficients_iir1, get_coefficients_iir2, get_coefficients_iir2_cascade,
+    get_coefficients_iir2_matched, get_coefficients_svf1, get_coefficients_svf2,
+    get_coefficients_svf2_cascade, get_coefficients_svf2_matched, BandCoefficients, Biquad,
+    CascadeCoefficients, MAX_CASCADE,
+};
+pub use engine::{BandSmoothing, EqEngine, Sample};
+pub use lane::{F32x2, F64x2, Lane, Pair};
+pub use linear_phase::{KernelDesigner, KernelSet, LinearPhaseDesigner, LATENCY};
+pub use loudness::{loudness_change_db, MAX_AUTO_GAIN_DB};
+pub use smoother::{Smoother, Smoothing, SmoothingStyle};
+pub use svf::{Svf1, Svf1Coefficients, Svf2, Svf2Coefficients};
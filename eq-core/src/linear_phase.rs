@@ -0,0 +1,513 @@
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use std::f32::consts::PI;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::band::{BandRouting, BandSettings};
+use crate::coefficients::BandCoefficients;
+
+/// Length of the FIR, sets how well low frequencies are resolved
+const KERNEL_LEN: usize = 8192;
+/// Samples per convolution block, the kernel is split into partitions of this length
+const BLOCK_LEN: usize = 256;
+const PARTITIONS: usize = KERNEL_LEN / BLOCK_LEN;
+/// Delay of the linear phase mode, half the kernel plus one block of buffering
+pub const LATENCY: usize = KERNEL_LEN / 2 + BLOCK_LEN;
+/// How often the designer thread looks for parameter changes
+const DESIGN_INTERVAL: Duration = Duration::from_millis(20);
+
+type Spectrum = Vec<Complex<f32>>;
+
+/// Partition spectra of the 2x2 filter matrix, indexed by `output * 2 + input`
+pub struct KernelSet {
+    paths: [Vec<Spectrum>; 4],
+    /// False when there are no mid or side bands, the paths between channels are silent then
+    cross: bool,
+}
+
+/// Turns the combined magnitude response of the bands into linear phase kernels
+pub struct KernelDesigner {
+    inverse: Arc<dyn Fft<f32>>,
+    partition_fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Spectrum,
+}
+
+impl KernelDesigner {
+    pub fn new() -> KernelDesigner {
+        let mut planner = FftPlanner::new();
+        KernelDesigner {
+            inverse: planner.plan_fft_inverse(KERNEL_LEN),
+            partition_fft: planner.plan_fft_forward(BLOCK_LEN * 2),
+            // Hann window, peaks at the center of the kernel
+            window: (0..KERNEL_LEN)
+                .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / KERNEL_LEN as f32).cos())
+                .collect(),
+            buffer: vec![Complex::new(0.0, 0.0); KERNEL_LEN],
+        }
+    }
+
+    /// Kernels for the current parameter values. Left and right bands are applied
+    /// before mid and side bands, dynamic bands use their static gain. Disabled
+    /// bands are left out.
+    pub fn design(&mut self, bands: &[BandSettings], fs: f32) -> Box<KernelSet> {
+        let ([ll, rl, lr, rr], cross) = path_magnitudes(bands, fs);
+        Box::new(KernelSet {
+            paths: [
+                self.partitions(&ll),
+                self.partitions(&rl),
+                self.partitions(&lr),
+                self.partitions(&rr),
+            ],
+            cross,
+        })
+    }
+
+    /// Zero phase response from `magnitudes` (DC up to Nyquist), delayed by half the
+    /// kernel and windowed
+    fn kernel(&mut self, magnitudes: &[f32]) -> Vec<f32> {
+        for (k, magnitude) in magnitudes.iter().enumerate() {
+            self.buffer[k] = Complex::new(*magnitude, 0.0);
+            self.buffer[(KERNEL_LEN - k) % KERNEL_LEN] = Complex::new(*magnitude, 0.0);
+        }
+        self.inverse.process(&mut self.buffer);
+
+        let scale = 1.0 / KERNEL_LEN as f32;
+        (0..KERNEL_LEN)
+            .map(|n| self.buffer[(n + KERNEL_LEN / 2) % KERNEL_LEN].re * scale * self.window[n])
+            .collect()
+    }
+
+    /// The kernel for `magnitudes` split into partition spectra
+    fn partitions(&mut self, magnitudes: &[f32]) -> Vec<Spectrum> {
+        self.kernel(magnitudes)
+            .chunks(BLOCK_LEN)
+            .map(|chunk| {
+                let mut spectrum = vec![Complex::new(0.0, 0.0); BLOCK_LEN * 2];
+                for (c, x) in spectrum.iter_mut().zip(chunk) {
+                    *c = Complex::new(*x, 0.0);
+                }
+                self.partition_fft.process(&mut spectrum);
+                spectrum
+            })
+            .collect()
+    }
+}
+
+/// Magnitude of each path of the filter matrix from DC up to Nyquist, and whether
+/// the paths between channels are used
+fn path_magnitudes(bands: &[BandSettings], fs: f32) -> ([Vec<f32>; 4], bool) {
+    let bands = bands
+        .iter()
+        .filter(|band| band.enabled)
+        .copied()
+        .collect::<Vec<BandSettings>>();
+    let bins = KERNEL_LEN / 2 + 1;
+    let mut paths = [
+        Vec::with_capacity(bins),
+        Vec::with_capacity(bins),
+        Vec::with_capacity(bins),
+        Vec::with_capacity(bins),
+    ];
+    let band_coeffs = bands
+        .iter()
+        .map(|band| BandCoefficients::new(band, fs))
+        .collect::<Vec<BandCoefficients>>();
+    for k in 0..bins {
+        let f_hz = k as f32 * fs / KERNEL_LEN as f32;
+        let (mut left, mut right, mut mid, mut side) = (1.0, 1.0, 1.0, 1.0);
+        for (band, coeffs) in bands.iter().zip(&band_coeffs) {
+            let magnitude = coeffs.magnitude(f_hz, fs) as f32;
+            match band.routing {
+                BandRouting::Stereo => {
+                    left *= magnitude;
+                    right *= magnitude;
+                }
+                BandRouting::Left => left *= magnitude,
+                BandRouting::Right => right *= magnitude,
+                BandRouting::Mid => mid *= magnitude,
+                BandRouting::Side => side *= magnitude,
+            }
+        }
+        // Mid/side encode, filter and decode, after the left/right filters
+        let (same, other) = ((mid + side) * 0.5, (mid - side) * 0.5);
+        paths[0].push(same * left);
+        paths[1].push(other * right);
+        paths[2].push(other * left);
+        paths[3].push(same * right);
+    }
+    let cross = bands
+        .iter()
+        .any(|band| matches!(band.routing, BandRouting::Mid | BandRouting::Side));
+    (paths, cross)
+}
+
+impl Default for KernelDesigner {
+    fn default() -> Self {
+        KernelDesigner::new()
+    }
+}
+
+/// Rebuilds the kernels on its own thread whenever the band settings change while
+/// linear phase is selected. The thread stops when this is dropped.
+pub struct LinearPhaseDesigner {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LinearPhaseDesigner {
+    /// `settings` is polled for the bands and sample rate, it returns `None` while
+    /// linear phase is not selected. New kernels are sent on `kernels`, kernels
+    /// coming back on `old_kernels` are freed. While the convolver has not taken
+    /// the last kernels sent, newer ones wait here, and are replaced rather than
+    /// queued when the settings change again.
+    pub fn spawn<F>(
+        mut settings: F,
+        kernels: SyncSender<Box<KernelSet>>,
+        old_kernels: Receiver<Box<KernelSet>>,
+    ) -> LinearPhaseDesigner
+    where
+        F: FnMut() -> Option<(Vec<BandSettings>, f32)> + Send + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = thread::spawn(move || {
+            let mut designer = KernelDesigner::new();
+            let mut last_settings = None;
+            let mut pending = None;
+            while thread_running.load(Ordering::Relaxed) {
+                thread::sleep(DESIGN_INTERVAL);
+                while old_kernels.try_recv().is_ok() {}
+                let current = settings();
+                if current != last_settings {
+                    pending = current
+                        .as_ref()
+                        .map(|(bands, fs)| designer.design(bands, *fs));
+                    last_settings = current;
+                }
+                if let Some(new_kernels) = pending.take() {
+                    match kernels.try_send(new_kernels) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(new_kernels)) => pending = Some(new_kernels),
+                        Err(TrySendError::Disconnected(_)) => break,
+                    }
+                }
+            }
+        });
+        LinearPhaseDesigner {
+            running,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for LinearPhaseDesigner {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Input of both channels, as time domain frames and the spectra of recent frames
+struct InputHistory {
+    /// Previous block followed by the block being filled
+    frames: [Vec<f32>; 2],
+    spectra: [Vec<Spectrum>; 2],
+    /// Index of the newest spectrum
+    head: usize,
+}
+
+struct Workspace {
+    fft: Arc<dyn Fft<f32>>,
+    inverse: Arc<dyn Fft<f32>>,
+    accum: Spectrum,
+    scratch: Spectrum,
+}
+
+/// Output block of channel `out` for the current history
+fn convolve_block(
+    kernels: &KernelSet,
+    history: &InputHistory,
+    work: &mut Workspace,
+    out: usize,
+    block: &mut [f32],
+) {
+    for c in work.accum.iter_mut() {
+        *c = Complex::new(0.0, 0.0);
+    }
+    for input in 0..2 {
+        if input != out && !kernels.cross {
+            continue;
+        }
+        for (p, partition) in kernels.paths[out * 2 + input].iter().enumerate() {
+            let spectrum = &history.spectra[input][(history.head + PARTITIONS - p) % PARTITIONS];
+            for ((acc, x), h) in work.accum.iter_mut().zip(spectrum).zip(partition) {
+                *acc += x * h;
+            }
+        }
+    }
+    work.inverse
+        .process_with_scratch(&mut work.accum, &mut work.scratch);
+    let scale = 1.0 / (BLOCK_LEN * 2) as f32;
+    for (y, c) in block.iter_mut().zip(&work.accum[BLOCK_LEN..]) {
+        *y = c.re * scale;
+    }
+}
+
+/// Uniformly partitioned overlap-save convolution of a stereo signal, runs on the
+/// audio thread and does not allocate
+pub struct LinearPhaseConvolver {
+    kernels: Box<KernelSet>,
+    new_kernels: Receiver<Box<KernelSet>>,
+    old_kernels: SyncSender<Box<KernelSet>>,
+    /// Replaced kernels that did not fit in `old_kernels` yet. No new kernels are
+    /// taken until they are handed back.
+    retired: Option<Box<KernelSet>>,
+    history: InputHistory,
+    work: Workspace,
+    output: [Vec<f32>; 2],
+    /// Output of the previous kernels, faded out when the kernels change
+    fade_output: Vec<f32>,
+    pos: usize,
+}
+
+impl LinearPhaseConvolver {
+    /// `kernels` is used until new ones arrive on `new_kernels`, replaced kernels are
+    /// sent back on `old_kernels` so they are not freed on the audio thread
+    pub fn new(
+        kernels: Box<KernelSet>,
+        new_kernels: Receiver<Box<KernelSet>>,
+        old_kernels: SyncSender<Box<KernelSet>>,
+    ) -> LinearPhaseConvolver {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(BLOCK_LEN * 2);
+        let inverse = planner.plan_fft_inverse(BLOCK_LEN * 2);
+        let scratch_len = fft
+            .get_inplace_scratch_len()
+            .max(inverse.get_inplace_scratch_len());
+        let spectra = || vec![vec![Complex::new(0.0, 0.0); BLOCK_LEN * 2]; PARTITIONS];
+        LinearPhaseConvolver {
+            kernels,
+            new_kernels,
+            old_kernels,
+            retired: None,
+            history: InputHistory {
+                frames: [vec![0.0; BLOCK_LEN * 2], vec![0.0; BLOCK_LEN * 2]],
+                spectra: [spectra(), spectra()],
+                head: 0,
+            },
+            work: Workspace {
+                fft,
+                inverse,
+                accum: vec![Complex::new(0.0, 0.0); BLOCK_LEN * 2],
+                scratch: vec![Complex::new(0.0, 0.0); scratch_len],
+            },
+            output: [vec![0.0; BLOCK_LEN], vec![0.0; BLOCK_LEN]],
+            fade_output: vec![0.0; BLOCK_LEN],
+            pos: 0,
+        }
+    }
+
+    /// Swap in new kernels straight away, for use outside of the audio thread
+    pub fn set_kernels(&mut self, kernels: Box<KernelSet>) {
+        self.kernels = kernels;
+    }
+
+    /// Clear all buffered audio
+    pub fn reset(&mut self) {
+        for frame in self.history.frames.iter_mut() {
+            frame.iter_mut().for_each(|x| *x = 0.0);
+        }
+        for spectra in self.history.spectra.iter_mut() {
+            for spectrum in spectra.iter_mut() {
+                spectrum
+                    .iter_mut()
+                    .for_each(|c| *c = Complex::new(0.0, 0.0));
+            }
+        }
+        for output in self.output.iter_mut() {
+            output.iter_mut().for_each(|x| *x = 0.0);
+        }
+        self.pos = 0;
+    }
+
+    /// Feed one stereo sample, returns the sample from `LATENCY` samples ago, filtered
+    pub fn process(&mut self, l: f32, r: f32) -> (f32, f32) {
+        let out = (self.output[0][self.pos], self.output[1][self.pos]);
+        self.history.frames[0][BLOCK_LEN + self.pos] = l;
+        self.history.frames[1][BLOCK_LEN + self.pos] = r;
+        self.pos += 1;
+        if self.pos == BLOCK_LEN {
+            self.pos = 0;
+            self.process_block();
+        }
+        out
+    }
+
+    fn process_block(&mut self) {
+        let history = &mut self.history;
+        history.head = (history.head + 1) % PARTITIONS;
+        for (frame, spectra) in history.frames.iter_mut().zip(history.spectra.iter_mut()) {
+            let spectrum = &mut spectra[history.head];
+            for (c, x) in spectrum.iter_mut().zip(frame.iter()) {
+                *c = Complex::new(*x, 0.0);
+            }
+            self.work
+                .fft
+                .process_with_scratch(spectrum, &mut self.work.scratch);
+            frame.copy_within(BLOCK_LEN.., 0);
+        }
+
+        if let Some(retired) = self.retired.take() {
+            self.retire(retired);
+        }
+        let old_kernels = match self.retired {
+            Some(_) => None,
+            None => self
+                .new_kernels
+                .try_recv()
+                .ok()
+                .map(|kernels| mem::replace(&mut self.kernels, kernels)),
+        };
+        for out in 0..2 {
+            convolve_block(
+                &self.kernels,
+                &self.history,
+                &mut self.work,
+                out,
+                &mut self.output[out],
+            );
+            // Crossfade from the previous kernels over one block
+            if let Some(old_kernels) = &old_kernels {
+                convolve_block(
+                    old_kernels,
+                    &self.history,
+                    &mut self.work,
+                    out,
+                    &mut self.fade_output,
+                );
+                for (i, (y, old)) in self.output[out]
+                    .iter_mut()
+                    .zip(self.fade_output.iter())
+                    .enumerate()
+                {
+                    let t = i as f32 / BLOCK_LEN as f32;
+                    *y = *old + (*y - *old) * t;
+                }
+            }
+        }
+        if let Some(old_kernels) = old_kernels {
+            self.retire(old_kernels);
+        }
+    }
+
+    /// Hand replaced kernels back to be freed, or keep them until there is room
+    fn retire(&mut self, kernels: Box<KernelSet>) {
+        match self.old_kernels.try_send(kernels) {
+            Ok(()) => {}
+            Err(TrySendError::Full(kernels)) | Err(TrySendError::Disconnected(kernels)) => {
+                self.retired = Some(kernels)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::band::BandKind;
+
+    use std::sync::mpsc::sync_channel;
+
+    #[test]
+    fn impulse_response_is_the_kernel_delayed_by_the_latency() {
+        let fs = 48000.0;
+        let bands = [
+            BandSettings {
+                kind: BandKind::Bell,
+                freq: 2000.0,
+                db_gain: 9.0,
+                q_value: 2.0,
+                ..BandSettings::default()
+            },
+            BandSettings {
+                kind: BandKind::LowShelf,
+                freq: 150.0,
+                db_gain: -6.0,
+                routing: BandRouting::Side,
+                ..BandSettings::default()
+            },
+        ];
+        let mut designer = KernelDesigner::new();
+        let (magnitudes, cross) = path_magnitudes(&bands, fs);
+        assert!(cross);
+        // An impulse on the left input comes out through the left to left and left
+        // to right paths
+        let expected = [
+            designer.kernel(&magnitudes[0]),
+            designer.kernel(&magnitudes[2]),
+        ];
+
+        let (_, new_kernels) = sync_channel(1);
+        let (old_kernels, _old_kernels) = sync_channel(1);
+        let mut convolver =
+            LinearPhaseConvolver::new(designer.design(&bands, fs), new_kernels, old_kernels);
+        let output = (0..LATENCY + KERNEL_LEN)
+            .map(|n| convolver.process(if n == 0 { 1.0 } else { 0.0 }, 0.0))
+            .collect::<Vec<(f32, f32)>>();
+
+        // The kernel is centered on its middle, which lands at `LATENCY`
+        let delay = LATENCY - KERNEL_LEN / 2;
+        for (n, (left, right)) in output.iter().enumerate() {
+            let kernel = |channel: usize| match n.checked_sub(delay) {
+                Some(m) if m < KERNEL_LEN => expected[channel][m],
+                _ => 0.0,
+            };
+            assert!((left - kernel(0)).abs() < 1e-6, "left at {}", n);
+            assert!((right - kernel(1)).abs() < 1e-6, "right at {}", n);
+        }
+        let peak = (0..output.len())
+            .max_by(|a, b| output[*a].0.abs().total_cmp(&output[*b].0.abs()))
+            .unwrap();
+        assert_eq!(peak, LATENCY);
+    }
+
+    #[test]
+    fn replaced_kernels_wait_for_room_to_be_handed_back() {
+        let fs = 48000.0;
+        let mut designer = KernelDesigner::new();
+        let mut kernels = || designer.design(&[BandSettings::default()], fs);
+        let (new_sender, new_kernels) = sync_channel(1);
+        let (old_kernels, old_receiver) = sync_channel(1);
+        let mut convolver = LinearPhaseConvolver::new(kernels(), new_kernels, old_kernels.clone());
+        let process_block = |convolver: &mut LinearPhaseConvolver| {
+            for _ in 0..BLOCK_LEN {
+                convolver.process(0.0, 0.0);
+            }
+        };
+        old_kernels.try_send(kernels()).unwrap();
+
+        // Taken, but the replaced kernels have nowhere to go yet
+        new_sender.try_send(kernels()).unwrap();
+        process_block(&mut convolver);
+        assert!(convolver.retired.is_some());
+        // So the next kernels are left waiting
+        new_sender.try_send(kernels()).unwrap();
+        process_block(&mut convolver);
+        assert!(new_sender.try_send(kernels()).is_err());
+
+        // Once there is room the replaced kernels go back first
+        old_receiver.try_recv().unwrap();
+        process_block(&mut convolver);
+        assert!(convolver.retired.is_some());
+        // The waiting kernels were taken in turn
+        assert!(new_sender.try_send(kernels()).is_ok());
+    }
+}
@@ -0,0 +1,93 @@
+use crate::band::{BandRouting, BandSettings};
+use crate::coefficients::BandCoefficients;
+
+/// Points the response is measured at between `LOUDNESS_MIN_HZ` and `LOUDNESS_MAX_HZ`
+const LOUDNESS_POINTS: usize = 96;
+const LOUDNESS_MIN_HZ: f32 = 20.0;
+const LOUDNESS_MAX_HZ: f32 = 20000.0;
+
+/// Most the auto gain compensation moves the output, either way
+pub const MAX_AUTO_GAIN_DB: f32 = 24.0;
+
+/// Change in loudness of pink noise run through the enabled bands, in dB.
+/// The points are log spaced, so each octave weighs the same like pink noise does.
+/// Dynamic bands count at their static gain, left and right are averaged.
+pub fn loudness_change_db(bands: &[BandSettings], fs: f32) -> f32 {
+    LoudnessMeter::new(bands, fs).loudness_change_db(bands)
+}
+
+/// `loudness_change_db` for a set of bands that changes a band at a time. The
+/// frequencies are worked out once per filter rate and each band's response
+/// when that band changes, so the engine only measures what changed.
+pub struct LoudnessMeter {
+    fs: f32,
+    freqs: [f32; LOUDNESS_POINTS],
+    /// Power response of each band at `freqs`
+    band_power: Vec<[f64; LOUDNESS_POINTS]>,
+}
+
+impl LoudnessMeter {
+    pub fn new(bands: &[BandSettings], fs: f32) -> LoudnessMeter {
+        let mut meter = LoudnessMeter {
+            fs,
+            freqs: [0.0; LOUDNESS_POINTS],
+            band_power: vec![[1.0; LOUDNESS_POINTS]; bands.len()],
+        };
+        meter.set_rate(bands, fs);
+        meter
+    }
+
+    /// Measure every band again at the filter rate `fs`
+    pub fn set_rate(&mut self, bands: &[BandSettings], fs: f32) {
+        self.fs = fs;
+        let max_hz = LOUDNESS_MAX_HZ.min(fs * 0.49);
+        let octaves = (max_hz / LOUDNESS_MIN_HZ).log2();
+        for (k, freq) in self.freqs.iter_mut().enumerate() {
+            *freq =
+                LOUDNESS_MIN_HZ * 2.0f32.powf(octaves * k as f32 / (LOUDNESS_POINTS - 1) as f32);
+        }
+        for (i, band) in bands.iter().enumerate() {
+            self.set_band(i, band);
+        }
+    }
+
+    /// Measure band `i` again
+    pub fn set_band(&mut self, i: usize, band: &BandSettings) {
+        let coeffs = BandCoefficients::new(band, self.fs);
+        for (power, freq) in self.band_power[i].iter_mut().zip(self.freqs.iter()) {
+            *power = coeffs.magnitude(*freq, self.fs).powi(2);
+        }
+    }
+
+    /// See `loudness_change_db`, `bands` are the settings last measured
+    pub fn loudness_change_db(&self, bands: &[BandSettings]) -> f32 {
+        let mut power_l = [1.0f64; LOUDNESS_POINTS];
+        let mut power_r = [1.0f64; LOUDNESS_POINTS];
+        for (band, band_power) in bands.iter().zip(self.band_power.iter()) {
+            if !band.enabled {
+                continue;
+            }
+            let (left, right) = match band.routing {
+                BandRouting::Left => (true, false),
+                BandRouting::Right => (false, true),
+                // Mid and side are taken as reaching both channels
+                _ => (true, true),
+            };
+            for k in 0..LOUDNESS_POINTS {
+                if left {
+                    power_l[k] *= band_power[k];
+                }
+                if right {
+                    power_r[k] *= band_power[k];
+                }
+            }
+        }
+        let mean = power_l
+            .iter()
+            .zip(power_r.iter())
+            .map(|(l, r)| (l + r) * 0.5)
+            .sum::<f64>()
+            / LOUDNESS_POINTS as f64;
+        (10.0 * mean.max(1e-20).log10()) as f32
+    }
+}
@@ -0,0 +1,251 @@
+use crate::band::Oversampling;
+use crate::delay::Delay;
+use crate::lane::{Lane, Pair};
+
+use std::f64::consts::PI;
+
+pub const MAX_OVERSAMPLING: usize = 8;
+
+/// Half the number of even taps of each 2x stage, first stage first. A stage with
+/// `k` has a half band filter of `4 * k - 1` taps. The first stage has the
+/// narrowest transition band, up to 20 kHz at 44.1 kHz, later ones get by with less.
+const STAGE_HALF_TAPS: [usize; 3] = [32, 8, 5];
+
+/// Kaiser window beta for about 90 dB of image and alias rejection
+const KAISER_BETA: f64 = 8.96;
+
+/// Modified Bessel function of the first kind, order 0
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..50 {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Even taps of a Kaiser windowed half band lowpass of `4 * k - 1` taps. The odd
+/// taps are all 0 apart from the center one, which is 1/2.
+fn half_band_taps<T: Lane>(k: usize) -> Vec<T::Pair> {
+    let center = (2 * k - 1) as f64;
+    let taps = (0..2 * k)
+        .map(|i| {
+            let x = 2.0 * i as f64 - center;
+            let r = x / center;
+            let window =
+                bessel_i0(KAISER_BETA * (1.0 - r * r).max(0.0).sqrt()) / bessel_i0(KAISER_BETA);
+            (PI * x * 0.5).sin() / (PI * x) * window
+        })
+        .collect::<Vec<f64>>();
+    // The even taps sum to 1/2, so with the center tap the DC gain is exactly 1
+    let sum = taps.iter().sum::<f64>();
+    taps.iter()
+        .map(|tap| T::Pair::splat(T::from_f64(tap * 0.5 / sum)))
+        .collect()
+}
+
+/// Convolve `taps` with `history`, whose newest sample is at `pos` and older ones
+/// after it, wrapping around
+fn convolve<T: Lane>(taps: &[T::Pair], history: &[T::Pair], pos: usize) -> T::Pair {
+    let (older, newer) = history.split_at(pos);
+    newer
+        .iter()
+        .chain(older)
+        .zip(taps)
+        .fold(T::Pair::splat(T::default()), |sum, (x, tap)| {
+            sum + *x * *tap
+        })
+}
+
+/// Push `x` as the newest sample of `history`, see `convolve`
+fn push<P: Copy>(history: &mut [P], pos: &mut usize, x: P) {
+    *pos = (*pos + history.len() - 1) % history.len();
+    history[*pos] = x;
+}
+
+/// Polyphase half band filters for one 2x up and down stage. Only the even taps
+/// are convolved, the center tap is a plain delay. Both channels are filtered as
+/// one pair of `T`.
+struct HalfBand<T: Lane> {
+    k: usize,
+    taps: Vec<T::Pair>,
+    up_history: Vec<T::Pair>,
+    up_pos: usize,
+    down_even: Vec<T::Pair>,
+    down_even_pos: usize,
+    down_odd: Vec<T::Pair>,
+    down_odd_pos: usize,
+}
+
+impl<T: Lane> HalfBand<T> {
+    fn new(k: usize) -> HalfBand<T> {
+        let zero = T::Pair::splat(T::default());
+        HalfBand {
+            k,
+            taps: half_band_taps::<T>(k),
+            up_history: vec![zero; 2 * k],
+            up_pos: 0,
+            down_even: vec![zero; 2 * k],
+            down_even_pos: 0,
+            down_odd: vec![zero; k + 1],
+            down_odd_pos: 0,
+        }
+    }
+
+    /// Delay of the up or the down filter, in samples at the higher rate
+    fn delay(&self) -> usize {
+        2 * self.k - 1
+    }
+
+    fn reset(&mut self) {
+        let zero = T::Pair::splat(T::default());
+        for history in [
+            &mut self.up_history,
+            &mut self.down_even,
+            &mut self.down_odd,
+        ] {
+            history.iter_mut().for_each(|x| *x = zero);
+        }
+        self.up_pos = 0;
+        self.down_even_pos = 0;
+        self.down_odd_pos = 0;
+    }
+
+    /// One sample in, two out at twice the rate
+    fn up(&mut self, x: T::Pair) -> [T::Pair; 2] {
+        push(&mut self.up_history, &mut self.up_pos, x);
+        let even = convolve::<T>(&self.taps, &self.up_history, self.up_pos);
+        let odd = self.up_history[(self.up_pos + self.k - 1) % self.up_history.len()];
+        [even * T::Pair::splat(T::from_f64(2.0)), odd]
+    }
+
+    /// Two samples in, one out at half the rate
+    fn down(&mut self, even: T::Pair, odd: T::Pair) -> T::Pair {
+        push(&mut self.down_even, &mut self.down_even_pos, even);
+        push(&mut self.down_odd, &mut self.down_odd_pos, odd);
+        let y = convolve::<T>(&self.taps, &self.down_even, self.down_even_pos);
+        let center = self.down_odd[(self.down_odd_pos + self.k) % self.down_odd.len()];
+        y + center * T::Pair::splat(T::from_f64(0.5))
+    }
+}
+
+/// Up and downsamples by 2, 4 or 8 with cascaded half band stages. The up and
+/// down filters are linear phase, a short delay at the oversampled rate makes
+/// their latency a whole number of samples. The filters run in `T`, the samples
+/// going in and out are f64 like everywhere between the stages of the engine.
+pub(crate) struct Oversampler<T: Lane> {
+    oversampling: Oversampling,
+    stages: Vec<HalfBand<T>>,
+    pad: Delay<f64>,
+    latency: usize,
+}
+
+impl<T: Lane> Oversampler<T> {
+    pub fn new() -> Oversampler<T> {
+        let mut oversampler = Oversampler {
+            oversampling: Oversampling::Off,
+            stages: STAGE_HALF_TAPS.iter().map(|k| HalfBand::new(*k)).collect(),
+            pad: Delay::new(MAX_OVERSAMPLING),
+            latency: 0,
+        };
+        oversampler.set_oversampling(Oversampling::Off);
+        oversampler
+    }
+
+    /// Delay of the down filters of `oversampling`, in oversampled samples
+    fn output_delay_of(&self, oversampling: Oversampling) -> usize {
+        let stages = oversampling.stages();
+        self.stages[..stages]
+            .iter()
+            .enumerate()
+            .map(|(s, stage)| stage.delay() << (stages - 1 - s))
+            .sum()
+    }
+
+    /// Latency of `oversampling` in samples
+    pub fn latency_of(&self, oversampling: Oversampling) -> usize {
+        let factor = oversampling.factor();
+        (2 * self.output_delay_of(oversampling)).div_ceil(factor)
+    }
+
+    pub fn oversampling(&self) -> Oversampling {
+        self.oversampling
+    }
+
+    /// Change the factor and clear the filters, does not allocate
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        self.oversampling = oversampling;
+        self.latency = self.latency_of(oversampling);
+        let delay = 2 * self.output_delay_of(oversampling);
+        self.pad
+            .set_len(self.latency * oversampling.factor() - delay);
+        self.reset();
+    }
+
+    pub fn factor(&self) -> usize {
+        self.oversampling.factor()
+    }
+
+    pub fn latency(&self) -> usize {
+        self.latency
+    }
+
+    /// Delay of the down filters, in oversampled samples. The up filters and the
+    /// pad make up the rest of the latency.
+    pub fn output_delay(&self) -> usize {
+        self.output_delay_of(self.oversampling)
+    }
+
+    pub fn reset(&mut self) {
+        self.stages.iter_mut().for_each(|stage| stage.reset());
+        self.pad.reset();
+    }
+
+    /// Fill the first `factor` samples of `out` from `x`, returns `factor`
+    pub fn upsample(&mut self, x: [f64; 2], out: &mut [[f64; 2]; MAX_OVERSAMPLING]) -> usize {
+        let mut samples = [to_pair::<T>(x); MAX_OVERSAMPLING];
+        let mut len = 1;
+        for stage in &mut self.stages[..self.oversampling.stages()] {
+            let input = samples;
+            for (i, x) in input[..len].iter().enumerate() {
+                let [even, odd] = stage.up(*x);
+                samples[2 * i] = even;
+                samples[2 * i + 1] = odd;
+            }
+            len *= 2;
+        }
+        for (y, x) in out[..len].iter_mut().zip(&samples) {
+            *y = self.pad.process(from_pair::<T>(*x));
+        }
+        len
+    }
+
+    /// Bring the first `factor` samples of `x` back down to one
+    pub fn downsample(&mut self, x: &[[f64; 2]; MAX_OVERSAMPLING]) -> [f64; 2] {
+        let mut samples = [T::Pair::splat(T::default()); MAX_OVERSAMPLING];
+        let mut len = self.factor();
+        for (y, x) in samples[..len].iter_mut().zip(x) {
+            *y = to_pair::<T>(*x);
+        }
+        for stage in self.stages[..self.oversampling.stages()].iter_mut().rev() {
+            len /= 2;
+            for i in 0..len {
+                samples[i] = stage.down(samples[2 * i], samples[2 * i + 1]);
+            }
+        }
+        from_pair::<T>(samples[0])
+    }
+}
+
+fn to_pair<T: Lane>(x: [f64; 2]) -> T::Pair {
+    T::Pair::new([T::from_f64(x[0]), T::from_f64(x[1])])
+}
+
+fn from_pair<T: Lane>(x: T::Pair) -> [f64; 2] {
+    let [l, r] = x.to_array();
+    [l.to_f64(), r.to_f64()]
+}
@@ -0,0 +1,141 @@
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SmoothingStyle {
+    /// Ramp to the target in a straight line over the smoothing time
+    Linear,
+    /// Exponential approach with the smoothing time as time constant
+    OnePole,
+}
+
+/// How one value is smoothed
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Smoothing {
+    pub style: SmoothingStyle,
+    pub ms: f32,
+}
+
+/// Once a one pole smoother is this close to its target it snaps to it
+const ONE_POLE_EPSILON: f32 = 1.0e-4;
+
+/// Per sample smoothing of a single value, owned by the DSP.
+#[derive(Clone)]
+pub struct Smoother {
+    style: SmoothingStyle,
+    current: f32,
+    target: f32,
+    step: f32,
+    steps_left: u32,
+    coeff: f32,
+}
+
+impl Smoother {
+    pub fn new(style: SmoothingStyle, value: f32) -> Smoother {
+        Smoother {
+            style,
+            current: value,
+            target: value,
+            step: 0.0,
+            steps_left: 0,
+            coeff: 0.0,
+        }
+    }
+
+    /// Switch style, a ramp in progress jumps to its target
+    pub fn set_style(&mut self, style: SmoothingStyle) {
+        if style != self.style {
+            self.style = style;
+            self.reset(self.target);
+        }
+    }
+
+    /// Start moving toward `target`, taking `smoothing_ms` to get there.
+    /// Setting the target it already has keeps the ramp in progress going.
+    pub fn set_target(&mut self, target: f32, smoothing_ms: f32, sample_rate: f32) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        let samples = (smoothing_ms * 0.001 * sample_rate).round();
+        if samples < 1.0 {
+            self.reset(target);
+            return;
+        }
+        match self.style {
+            SmoothingStyle::Linear => {
+                self.steps_left = samples as u32;
+                self.step = (self.target - self.current) / samples;
+            }
+            SmoothingStyle::OnePole => {
+                self.coeff = (-1.0 / samples).exp();
+            }
+        }
+    }
+
+    /// Jump straight to `value`, dropping any ramp in progress
+    pub fn reset(&mut self, value: f32) {
+        self.current = value;
+        self.target = value;
+        self.step = 0.0;
+        self.steps_left = 0;
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn is_smoothing(&self) -> bool {
+        self.current != self.target
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> f32 {
+        if !self.is_smoothing() {
+            return self.current;
+        }
+        match self.style {
+            SmoothingStyle::Linear => {
+                if self.steps_left <= 1 {
+                    self.reset(self.target);
+                } else {
+                    self.steps_left -= 1;
+                    self.current += self.step;
+                }
+            }
+            SmoothingStyle::OnePole => {
+                self.current = self.target + (self.current - self.target) * self.coeff;
+                if (self.current - self.target).abs()
+                    <= ONE_POLE_EPSILON * self.target.abs().max(1.0)
+                {
+                    self.reset(self.target);
+                }
+            }
+        }
+        self.current
+    }
+
+    /// Advance `n` samples at once, the same as calling `next` `n` times
+    pub fn skip(&mut self, n: u32) -> f32 {
+        if !self.is_smoothing() || n == 0 {
+            return self.current;
+        }
+        match self.style {
+            SmoothingStyle::Linear => {
+                if self.steps_left <= n {
+                    self.reset(self.target);
+                } else {
+                    self.steps_left -= n;
+                    self.current += self.step * n as f32;
+                }
+            }
+            SmoothingStyle::OnePole => {
+                self.current =
+                    self.target + (self.current - self.target) * self.coeff.powi(n as i32);
+                if (self.current - self.target).abs()
+                    <= ONE_POLE_EPSILON * self.target.abs().max(1.0)
+                {
+                    self.reset(self.target);
+                }
+            }
+        }
+        self.current
+    }
+}
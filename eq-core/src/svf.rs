@@ -0,0 +1,330 @@
+use crate::lane::{Lane, Pair};
+
+use std::f64::consts::PI;
+
+/// Second order state variable filter coefficients in f64, the same filters as
+/// `IIR2Coefficients` of basic-audio-filters
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Svf2Coefficients {
+    /// Linear gain of shelves and bells, 1 for the other kinds
+    pub a: f64,
+    pub g: f64,
+    pub k: f64,
+    pub a1: f64,
+    pub a2: f64,
+    pub a3: f64,
+    pub m0: f64,
+    pub m1: f64,
+    pub m2: f64,
+}
+
+impl Svf2Coefficients {
+    pub fn new(a: f64, g: f64, k: f64, m0: f64, m1: f64, m2: f64) -> Svf2Coefficients {
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        Svf2Coefficients {
+            a,
+            g,
+            k,
+            a1,
+            a2,
+            a3: g * a2,
+            m0,
+            m1,
+            m2,
+        }
+    }
+
+    pub fn bell(f0: f64, db_gain: f64, q_value: f64, fs: f64) -> Svf2Coefficients {
+        let a = 10.0f64.powf(db_gain / 40.0);
+        let k = 1.0 / (q_value * a);
+        Svf2Coefficients::new(a, prewarp(f0, fs), k, 1.0, k * (a * a - 1.0), 0.0)
+    }
+
+    pub fn lowpass(f0: f64, _db_gain: f64, q_value: f64, fs: f64) -> Svf2Coefficients {
+        Svf2Coefficients::new(1.0, prewarp(f0, fs), 1.0 / q_value, 0.0, 0.0, 1.0)
+    }
+
+    pub fn highpass(f0: f64, _db_gain: f64, q_value: f64, fs: f64) -> Svf2Coefficients {
+        let k = 1.0 / q_value;
+        Svf2Coefficients::new(1.0, prewarp(f0, fs), k, 1.0, -k, -1.0)
+    }
+
+    pub fn bandpass(f0: f64, _db_gain: f64, q_value: f64, fs: f64) -> Svf2Coefficients {
+        Svf2Coefficients::new(1.0, prewarp(f0, fs), 1.0 / q_value, 0.0, 1.0, 0.0)
+    }
+
+    pub fn notch(f0: f64, _db_gain: f64, q_value: f64, fs: f64) -> Svf2Coefficients {
+        let k = 1.0 / q_value;
+        Svf2Coefficients::new(1.0, prewarp(f0, fs), k, 1.0, -k, 0.0)
+    }
+
+    pub fn allpass(f0: f64, _db_gain: f64, q_value: f64, fs: f64) -> Svf2Coefficients {
+        let k = 1.0 / q_value;
+        Svf2Coefficients::new(1.0, prewarp(f0, fs), k, 1.0, -2.0 * k, 0.0)
+    }
+
+    pub fn lowshelf(f0: f64, db_gain: f64, q_value: f64, fs: f64) -> Svf2Coefficients {
+        let a = 10.0f64.powf(db_gain / 40.0);
+        let g = prewarp(f0, fs) / a.sqrt();
+        let k = 1.0 / q_value;
+        Svf2Coefficients::new(a, g, k, 1.0, k * (a - 1.0), a * a - 1.0)
+    }
+
+    pub fn highshelf(f0: f64, db_gain: f64, q_value: f64, fs: f64) -> Svf2Coefficients {
+        let a = 10.0f64.powf(db_gain / 40.0);
+        let g = prewarp(f0, fs) * a.sqrt();
+        let k = 1.0 / q_value;
+        Svf2Coefficients::new(a, g, k, a * a, k * (1.0 - a) * a, 1.0 - a * a)
+    }
+}
+
+/// First order state variable filter coefficients in f64, the same filters as
+/// `IIR1Coefficients` of basic-audio-filters
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Svf1Coefficients {
+    pub g: f64,
+    pub a1: f64,
+    pub m0: f64,
+    pub m1: f64,
+}
+
+impl Svf1Coefficients {
+    pub fn new(g: f64, m0: f64, m1: f64) -> Svf1Coefficients {
+        Svf1Coefficients {
+            g,
+            a1: g / (1.0 + g),
+            m0,
+            m1,
+        }
+    }
+
+    pub fn lowpass(f0: f64, _db_gain: f64, fs: f64) -> Svf1Coefficients {
+        Svf1Coefficients::new(prewarp(f0, fs), 0.0, 1.0)
+    }
+
+    pub fn highpass(f0: f64, _db_gain: f64, fs: f64) -> Svf1Coefficients {
+        Svf1Coefficients::new(prewarp(f0, fs), 1.0, -1.0)
+    }
+
+    pub fn allpass(f0: f64, _db_gain: f64, fs: f64) -> Svf1Coefficients {
+        Svf1Coefficients::new(prewarp(f0, fs), 1.0, -2.0)
+    }
+
+    pub fn lowshelf(f0: f64, db_gain: f64, fs: f64) -> Svf1Coefficients {
+        let a = 10.0f64.powf(db_gain / 20.0);
+        Svf1Coefficients::new(prewarp(f0, fs) / a.sqrt(), 1.0, a - 1.0)
+    }
+
+    pub fn highshelf(f0: f64, db_gain: f64, fs: f64) -> Svf1Coefficients {
+        let a = 10.0f64.powf(db_gain / 20.0);
+        Svf1Coefficients::new(prewarp(f0, fs) * a.sqrt(), a, 1.0 - a)
+    }
+}
+
+/// `g` of the bilinear transform with its frequency prewarped to `f0`
+fn prewarp(f0: f64, fs: f64) -> f64 {
+    (PI * f0 / fs).tan()
+}
+
+/// Second order state variable filter over a pair of channels, with coefficients
+/// and state in `T`. Both channels go through the same arithmetic as one packed
+/// pair, see `Lane`.
+#[derive(Clone, Copy)]
+pub struct Svf2<T: Lane> {
+    ic1eq: T::Pair,
+    ic2eq: T::Pair,
+    a1: T::Pair,
+    a2: T::Pair,
+    a3: T::Pair,
+    m0: T::Pair,
+    m1: T::Pair,
+    m2: T::Pair,
+}
+
+impl<T: Lane> Svf2<T> {
+    pub fn new(coeffs: Svf2Coefficients) -> Svf2<T> {
+        let zero = T::Pair::splat(T::default());
+        let mut filter = Svf2 {
+            ic1eq: zero,
+            ic2eq: zero,
+            a1: zero,
+            a2: zero,
+            a3: zero,
+            m0: zero,
+            m1: zero,
+            m2: zero,
+        };
+        filter.update(coeffs);
+        filter
+    }
+
+    /// Take new coefficients, keeping the state
+    pub fn update(&mut self, coeffs: Svf2Coefficients) {
+        let splat = |x: f64| T::Pair::splat(T::from_f64(x));
+        self.a1 = splat(coeffs.a1);
+        self.a2 = splat(coeffs.a2);
+        self.a3 = splat(coeffs.a3);
+        self.m0 = splat(coeffs.m0);
+        self.m1 = splat(coeffs.m1);
+        self.m2 = splat(coeffs.m2);
+    }
+
+    pub fn reset(&mut self) {
+        self.ic1eq = T::Pair::splat(T::default());
+        self.ic2eq = self.ic1eq;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: [T; 2]) -> [T; 2] {
+        self.process_pair(T::Pair::new(x)).to_array()
+    }
+
+    /// `process` on an already packed pair
+    #[inline]
+    pub fn process_pair(&mut self, x: T::Pair) -> T::Pair {
+        let v3 = x - self.ic2eq;
+        let v1 = self.a1 * self.ic1eq + self.a2 * v3;
+        let v2 = self.ic2eq + self.a2 * self.ic1eq + self.a3 * v3;
+        self.ic1eq = v1 + v1 - self.ic1eq;
+        self.ic2eq = v2 + v2 - self.ic2eq;
+        self.m0 * x + self.m1 * v1 + self.m2 * v2
+    }
+}
+
+/// First order state variable filter over a pair of channels, see `Svf2`
+#[derive(Clone, Copy)]
+pub struct Svf1<T: Lane> {
+    ic1eq: T::Pair,
+    a1: T::Pair,
+    m0: T::Pair,
+    m1: T::Pair,
+}
+
+impl<T: Lane> Svf1<T> {
+    pub fn new(coeffs: Svf1Coefficients) -> Svf1<T> {
+        let zero = T::Pair::splat(T::default());
+        let mut filter = Svf1 {
+            ic1eq: zero,
+            a1: zero,
+            m0: zero,
+            m1: zero,
+        };
+        filter.update(coeffs);
+        filter
+    }
+
+    /// Take new coefficients, keeping the state
+    pub fn update(&mut self, coeffs: Svf1Coefficients) {
+        let splat = |x: f64| T::Pair::splat(T::from_f64(x));
+        self.a1 = splat(coeffs.a1);
+        self.m0 = splat(coeffs.m0);
+        self.m1 = splat(coeffs.m1);
+    }
+
+    pub fn reset(&mut self) {
+        self.ic1eq = T::Pair::splat(T::default());
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: [T; 2]) -> [T; 2] {
+        self.process_pair(T::Pair::new(x)).to_array()
+    }
+
+    /// `process` on an already packed pair
+    #[inline]
+    pub fn process_pair(&mut self, x: T::Pair) -> T::Pair {
+        let v1 = self.a1 * (x - self.ic1eq);
+        let v2 = v1 + self.ic1eq;
+        self.ic1eq = v2 + v1;
+        self.m0 * x + self.m1 * v2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::{Add, Mul, Sub};
+
+    /// One channel of `Svf2` in plain `T` arithmetic, in the same order
+    struct ScalarSvf2<T> {
+        ic1eq: T,
+        ic2eq: T,
+        coeffs: [T; 6],
+    }
+
+    impl<T> ScalarSvf2<T>
+    where
+        T: Lane + Add<Output = T> + Sub<Output = T> + Mul<Output = T>,
+    {
+        fn new(coeffs: Svf2Coefficients) -> ScalarSvf2<T> {
+            let c = [
+                coeffs.a1, coeffs.a2, coeffs.a3, coeffs.m0, coeffs.m1, coeffs.m2,
+            ];
+            ScalarSvf2 {
+                ic1eq: T::default(),
+                ic2eq: T::default(),
+                coeffs: c.map(T::from_f64),
+            }
+        }
+
+        fn process(&mut self, x: T) -> T {
+            let [a1, a2, a3, m0, m1, m2] = self.coeffs;
+            let v3 = x - self.ic2eq;
+            let v1 = a1 * self.ic1eq + a2 * v3;
+            let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+            self.ic1eq = v1 + v1 - self.ic1eq;
+            self.ic2eq = v2 + v2 - self.ic2eq;
+            m0 * x + m1 * v1 + m2 * v2
+        }
+    }
+
+    /// Run a few kinds of filter over two different channels, packed and as two
+    /// scalar filters, and check every output sample is bit for bit the same
+    fn check_pairs_match_scalar_filters<T>()
+    where
+        T: Lane + Add<Output = T> + Sub<Output = T> + Mul<Output = T>,
+    {
+        let fs = 48000.0;
+        let coeffs = [
+            Svf2Coefficients::bell(1000.0, 9.0, 2.0, fs),
+            Svf2Coefficients::lowshelf(40.0, -6.0, 0.707, fs),
+            Svf2Coefficients::highpass(15000.0, 0.0, 0.5, fs),
+        ];
+        for coeffs in coeffs {
+            let mut packed = Svf2::<T>::new(coeffs);
+            let mut left = ScalarSvf2::<T>::new(coeffs);
+            let mut right = ScalarSvf2::<T>::new(coeffs);
+            for n in 0..4096 {
+                let t = n as f64;
+                let x = [
+                    T::from_f64((t * 0.05).sin() * 0.5 + (t * 0.7).sin() * 0.25),
+                    T::from_f64(if n % 100 == 0 {
+                        1.0
+                    } else {
+                        -(t * 0.013).cos() * 0.3
+                    }),
+                ];
+                let [l, r] = packed.process_pair(T::Pair::new(x)).to_array();
+                let expected = [left.process(x[0]), right.process(x[1])];
+                assert_eq!(
+                    [l.to_f64().to_bits(), r.to_f64().to_bits()],
+                    [
+                        expected[0].to_f64().to_bits(),
+                        expected[1].to_f64().to_bits()
+                    ],
+                    "{:?}, sample {}",
+                    coeffs,
+                    n
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn packed_pairs_match_two_scalar_filters() {
+        check_pairs_match_scalar_filters::<f32>();
+        check_pairs_match_scalar_filters::<f64>();
+    }
+}
@@ -0,0 +1,27 @@
+#!/bin/bash
+
+set -e
+
+# Settings
+
+NAME="EQPlugin"
+# Add a band count feature here, e.g. "lv2 bands-8"
+export FEATURES="lv2"
+
+# Script
+
+LV2_NAME="$NAME.lv2"
+MOVE_TO="$HOME/.lv2/$LV2_NAME"
+TMP_DIR="tmp"
+
+cargo build --release --features "$FEATURES"
+./scripts/lv2_bundler.sh "$NAME" ./target/release/libbasic_eq_plugin.so
+
+mkdir -p "$HOME/.lv2"
+if [ -d "$MOVE_TO" ]; then
+    rm -r "$MOVE_TO"
+fi
+
+if mv "$TMP_DIR/$LV2_NAME" "$MOVE_TO"; then
+    echo "Copied LV2 bundle to $MOVE_TO"
+fi
@@ -0,0 +1,32 @@
+#!/bin/bash
+#
+# Lays out an LV2 bundle for a library built with the `lv2` feature, like
+# osx_vst_bundler.sh does for a macOS VST. The .ttl files are generated by the
+# lv2-ttl binary, so pass the same band count feature the library was built with
+# in FEATURES, e.g. FEATURES="lv2 bands-8".
+
+set -e
+
+FEATURES="${FEATURES:-lv2}"
+
+# Make sure we have the arguments we need
+if [[ -z $1 || -z $2 ]]; then
+    echo "Generates an LV2 bundle from a compiled .so file"
+    echo "Example:"
+    echo -e "\t$0 Plugin target/release/libbasic_eq_plugin.so"
+    echo -e "\tCreates a Plugin.lv2 bundle"
+else
+    TMP_DIR="tmp"
+    BUNDLE="$TMP_DIR/$1.lv2"
+    LIBRARY="$(basename "$2")"
+
+    # Make the bundle folder
+    mkdir -p "$BUNDLE"
+
+    # copy the provided library next to the .ttl files that describe it
+    cp "$2" "$BUNDLE/$LIBRARY"
+
+    cargo run --release --quiet --features "$FEATURES" --bin lv2-ttl -- "$BUNDLE" "$LIBRARY"
+
+    echo "Created bundle $BUNDLE"
+fi
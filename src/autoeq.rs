@@ -0,0 +1,367 @@
+use crate::eq_effect_parameters::{
+    BandKind, BandMode, BandRouting, BandSettings, DynamicMode, EQEffectParameters,
+};
+use crate::parameter::Parameter;
+
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum AutoEqError {
+    /// Line number (from 1) and what is wrong with it
+    Line(usize, String),
+    /// Line number (from 1) and the filter type, which has no matching band kind
+    UnsupportedFilter(usize, String),
+    TooManyFilters {
+        found: usize,
+        max: usize,
+    },
+    /// Band number (from 1) and the setting that the format can't describe
+    UnsupportedBand(usize, String),
+}
+
+impl fmt::Display for AutoEqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AutoEqError::Line(line, message) => write!(f, "line {}: {}", line, message),
+            AutoEqError::UnsupportedFilter(line, kind) => {
+                write!(f, "line {}: unsupported filter type {}", line, kind)
+            }
+            AutoEqError::TooManyFilters { found, max } => {
+                write!(f, "{} filters, but only {} bands", found, max)
+            }
+            AutoEqError::UnsupportedBand(band, message) => {
+                write!(f, "band {}: {}", band, message)
+            }
+        }
+    }
+}
+
+/// Preamp and filters of an Equalizer APO / AutoEQ parametric EQ file
+#[derive(Debug, Default)]
+pub struct AutoEq {
+    pub preamp: f32,
+    pub bands: Vec<BandSettings>,
+}
+
+fn filter_kind(name: &str) -> Option<BandKind> {
+    match name.to_ascii_uppercase().as_str() {
+        "PK" | "PEQ" => Some(BandKind::Bell),
+        "LSC" | "LS" => Some(BandKind::LowShelf),
+        "HSC" | "HS" => Some(BandKind::HighShelf),
+        "LP" | "LPQ" => Some(BandKind::LowPass),
+        "HP" | "HPQ" => Some(BandKind::HighPass),
+        "NO" => Some(BandKind::Notch),
+        "BP" => Some(BandKind::BandPass),
+        "AP" => Some(BandKind::AllPass),
+        _ => None,
+    }
+}
+
+fn filter_name(kind: BandKind) -> &'static str {
+    match kind {
+        BandKind::Bell => "PK",
+        BandKind::LowShelf => "LSC",
+        BandKind::HighShelf => "HSC",
+        BandKind::LowPass => "LPQ",
+        BandKind::HighPass => "HPQ",
+        BandKind::Notch => "NO",
+        BandKind::BandPass => "BP",
+        BandKind::AllPass => "AP",
+    }
+}
+
+/// Q of a filter with a bandwidth of `bw` octaves
+fn bw_to_q(bw: f32) -> f32 {
+    let x = 2.0f32.powf(bw);
+    x.sqrt() / (x - 1.0)
+}
+
+/// Short number for the text file, `1.50` becomes `1.5` and `2.00` becomes `2`
+fn format_number(x: f32) -> String {
+    let text = format!("{:.2}", x);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Parse the text Equalizer APO and AutoEQ use for parametric EQs:
+/// `Preamp: -6.5 dB` and `Filter 1: ON PK Fc 100 Hz Gain -3.2 dB Q 0.7` lines.
+/// Filters that are `OFF` are left out, `#` starts a comment.
+pub fn parse_autoeq(text: &str) -> Result<AutoEq, AutoEqError> {
+    let mut autoeq = AutoEq::default();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let line_error = |message: &str| AutoEqError::Line(n + 1, message.to_string());
+        let mut parts = line.splitn(2, ':');
+        let (key, rest) = match (parts.next(), parts.next()) {
+            (Some(key), Some(rest)) => (key.trim(), rest.trim()),
+            _ => return Err(line_error("expected `Preamp:` or `Filter:`")),
+        };
+        let mut tokens = rest.split_whitespace();
+        let key = key.split_whitespace().next().unwrap_or("");
+        if key.eq_ignore_ascii_case("preamp") {
+            autoeq.preamp = tokens
+                .next()
+                .and_then(|value| value.parse::<f32>().ok())
+                .ok_or_else(|| line_error("expected the preamp in dB"))?;
+            continue;
+        }
+        if !key.eq_ignore_ascii_case("filter") {
+            return Err(AutoEqError::Line(n + 1, format!("unknown setting {}", key)));
+        }
+
+        match tokens.next() {
+            Some(state) if state.eq_ignore_ascii_case("on") => (),
+            Some(state) if state.eq_ignore_ascii_case("off") => continue,
+            _ => return Err(line_error("expected ON or OFF")),
+        }
+        let kind_name = tokens
+            .next()
+            .ok_or_else(|| line_error("missing filter type"))?;
+        let kind = filter_kind(kind_name)
+            .ok_or_else(|| AutoEqError::UnsupportedFilter(n + 1, kind_name.to_string()))?;
+
+        let mut band = BandSettings {
+            kind,
+            q_value: FRAC_1_SQRT_2,
+            ..BandSettings::default()
+        };
+        let mut freq = None;
+        let tokens = tokens.collect::<Vec<&str>>();
+        let mut i = 0;
+        while i < tokens.len() {
+            let number = |j: usize| {
+                tokens
+                    .get(j)
+                    .and_then(|value| value.parse::<f32>().ok())
+                    .ok_or_else(|| {
+                        AutoEqError::Line(n + 1, format!("expected a number after {}", tokens[i]))
+                    })
+            };
+            match tokens[i].to_ascii_uppercase().as_str() {
+                "FC" => {
+                    freq = Some(number(i + 1)?);
+                    i += 1;
+                }
+                "GAIN" => {
+                    band.db_gain = number(i + 1)?;
+                    i += 1;
+                }
+                "Q" => {
+                    band.q_value = number(i + 1)?;
+                    i += 1;
+                }
+                "BW" => {
+                    if tokens.get(i + 1).map(|unit| unit.to_ascii_uppercase()) != Some("OCT".into())
+                    {
+                        return Err(line_error("only `BW Oct` bandwidths are supported"));
+                    }
+                    band.q_value = bw_to_q(number(i + 2)?);
+                    i += 2;
+                }
+                // Units, and the optional slope of the shelves
+                _ => (),
+            }
+            i += 1;
+        }
+        band.freq = freq.ok_or_else(|| line_error("missing Fc"))?;
+        autoeq.bands.push(band);
+    }
+    Ok(autoeq)
+}
+
+impl EQEffectParameters {
+    /// Load an Equalizer APO / AutoEQ parametric EQ, see `parse_autoeq`
+    pub fn load_autoeq(&self, text: &str) -> Result<(), AutoEqError> {
+        let autoeq = parse_autoeq(text)?;
+        self.apply_autoeq(&autoeq, |parameter, value| parameter.set(value))
+    }
+
+    /// Set the preamp and the first bands to `autoeq` and the rest back to their
+    /// defaults, passing every plain value through `set`
+    pub fn apply_autoeq<F: Fn(&Parameter, f32)>(
+        &self,
+        autoeq: &AutoEq,
+        set: F,
+    ) -> Result<(), AutoEqError> {
+        if autoeq.bands.len() > self.bands.len() {
+            return Err(AutoEqError::TooManyFilters {
+                found: autoeq.bands.len(),
+                max: self.bands.len(),
+            });
+        }
+        set(&self.preamp, autoeq.preamp);
+        for (i, band) in self.bands.iter().enumerate() {
+            let settings = autoeq.bands.get(i).copied().unwrap_or_default();
+            band.apply_settings(&settings, &set);
+        }
+        Ok(())
+    }
+
+    /// The preamp and bands as an Equalizer APO parametric EQ. Flat and disabled
+    /// bands are left out, bands the format can't describe are an error.
+    pub fn save_autoeq(&self) -> Result<String, AutoEqError> {
+        let mut text = format!("Preamp: {} dB\n", format_number(self.preamp.get()));
+        let mut filter = 1;
+        for (i, band) in self.bands.iter().enumerate() {
+            let settings = band.settings();
+            let unsupported = |message: &str| AutoEqError::UnsupportedBand(i + 1, message.into());
+            if !settings.enabled || (settings.kind.uses_gain() && settings.db_gain == 0.0) {
+                continue;
+            }
+            if settings.routing != BandRouting::Stereo {
+                return Err(unsupported("only stereo bands can be exported"));
+            }
+            if settings.dynamic != DynamicMode::Off {
+                return Err(unsupported("dynamic bands can't be exported"));
+            }
+            match settings.mode {
+                BandMode::SecondOrder => (),
+                BandMode::FirstOrder => return Err(unsupported("first order can't be exported")),
+                _ if settings.kind == BandKind::LowPass || settings.kind == BandKind::HighPass => {
+                    return Err(unsupported("only 12 dB/oct slopes can be exported"))
+                }
+                _ => (),
+            }
+            text += &format!(
+                "Filter {}: ON {} Fc {} Hz",
+                filter,
+                filter_name(settings.kind),
+                format_number(settings.freq)
+            );
+            if settings.kind.uses_gain() {
+                text += &format!(" Gain {} dB", format_number(settings.db_gain));
+            }
+            text += &format!(" Q {}\n", format_number(settings.q_value));
+            filter += 1;
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eq_effect_parameters::FILTER_COUNT;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn parses_preamp_and_filter_types() {
+        let autoeq = parse_autoeq(
+            "Preamp: -6.5 dB\n\
+             Filter 1: ON PK Fc 100 Hz Gain -3.2 dB Q 0.7\n\
+             Filter 2: ON LSC Fc 105 Hz Gain 5.5 dB Q 0.71\n\
+             Filter 3: ON HSC Fc 10000 Hz Gain -2 dB Q 0.71\n\
+             Filter 4: ON LP Fc 18000 Hz\n\
+             Filter 5: ON HP Fc 20 Hz Q 0.5\n",
+        )
+        .unwrap();
+        assert_close(autoeq.preamp, -6.5);
+        let kinds = autoeq
+            .bands
+            .iter()
+            .map(|band| band.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                BandKind::Bell,
+                BandKind::LowShelf,
+                BandKind::HighShelf,
+                BandKind::LowPass,
+                BandKind::HighPass
+            ]
+        );
+        let bell = autoeq.bands[0];
+        assert_close(bell.freq, 100.0);
+        assert_close(bell.db_gain, -3.2);
+        assert_close(bell.q_value, 0.7);
+        // Without a Q the filter is a Butterworth
+        assert_close(autoeq.bands[3].q_value, FRAC_1_SQRT_2);
+        assert_close(autoeq.bands[4].q_value, 0.5);
+    }
+
+    #[test]
+    fn converts_octave_bandwidth_to_q() {
+        let autoeq = parse_autoeq("Filter: ON PK Fc 1000 Hz Gain 3 dB BW Oct 1").unwrap();
+        assert_close(autoeq.bands[0].q_value, 2.0f32.sqrt());
+        let autoeq = parse_autoeq("Filter: ON PK Fc 1000 Hz Gain 3 dB BW Oct 2").unwrap();
+        assert_close(autoeq.bands[0].q_value, 2.0 / 3.0);
+        assert!(matches!(
+            parse_autoeq("Filter: ON PK Fc 1000 Hz Gain 3 dB BW 100 Hz"),
+            Err(AutoEqError::Line(1, _))
+        ));
+    }
+
+    #[test]
+    fn skips_filters_that_are_off() {
+        let autoeq = parse_autoeq(
+            "Filter 1: OFF PK Fc 100 Hz Gain -3 dB Q 1\n\
+             # Filter 2: ON PK Fc 200 Hz Gain -3 dB Q 1\n\
+             Filter 3: ON PK Fc 300 Hz Gain -3 dB Q 1\n",
+        )
+        .unwrap();
+        assert_eq!(autoeq.bands.len(), 1);
+        assert_close(autoeq.bands[0].freq, 300.0);
+    }
+
+    #[test]
+    fn rejects_unsupported_filters() {
+        match parse_autoeq("Preamp: -1 dB\nFilter 1: ON LSQ Fc 100 Hz Gain 3 dB Q 1") {
+            Err(AutoEqError::UnsupportedFilter(line, kind)) => {
+                assert_eq!(line, 2);
+                assert_eq!(kind, "LSQ");
+            }
+            other => panic!("expected UnsupportedFilter, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_more_filters_than_bands() {
+        let text = (0..FILTER_COUNT + 1)
+            .map(|i| {
+                format!(
+                    "Filter {}: ON PK Fc {} Hz Gain -1 dB Q 1\n",
+                    i + 1,
+                    100 * (i + 1)
+                )
+            })
+            .collect::<String>();
+        let params = EQEffectParameters::default();
+        match params.load_autoeq(&text) {
+            Err(AutoEqError::TooManyFilters { found, max }) => {
+                assert_eq!(found, FILTER_COUNT + 1);
+                assert_eq!(max, FILTER_COUNT);
+            }
+            other => panic!("expected TooManyFilters, got {:?}", other),
+        }
+        // Nothing is applied when the filters don't fit
+        assert_eq!(params.bands[0].freq.get(), params.bands[0].freq.default);
+    }
+
+    #[test]
+    fn round_trips_through_the_parameters() {
+        let text = "Preamp: -4.5 dB\n\
+                    Filter 1: ON LSC Fc 105 Hz Gain 3.5 dB Q 0.71\n\
+                    Filter 2: ON PK Fc 2500 Hz Gain -2.25 dB Q 1.5\n\
+                    Filter 3: ON HPQ Fc 30 Hz Q 0.5\n";
+        let params = EQEffectParameters::default();
+        params.load_autoeq(text).unwrap();
+        let saved = params.save_autoeq().unwrap();
+        assert_eq!(saved, text);
+        let (original, reloaded) = (parse_autoeq(text).unwrap(), parse_autoeq(&saved).unwrap());
+        assert_close(reloaded.preamp, original.preamp);
+        assert_eq!(reloaded.bands.len(), original.bands.len());
+        for (a, b) in original.bands.iter().zip(&reloaded.bands) {
+            assert_eq!(a.kind, b.kind);
+            assert_close(a.freq, b.freq);
+            assert_close(a.db_gain, b.db_gain);
+            assert_close(a.q_value, b.q_value);
+        }
+    }
+}
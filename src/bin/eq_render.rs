@@ -0,0 +1,295 @@
+//! Applies an EQ preset to WAV files, using the same processing as the plugin.
+//! See `USAGE` for the command line.
+
+use basic_eq_plugin::EQPlugin;
+use eq_core::BandRouting;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use vst::plugin::Plugin;
+
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+usage: eq-render --preset <file> [--block-size <n>] <input.wav> <output.wav>
+       eq-render --preset <file> [--block-size <n>] --out-dir <dir> <input.wav>...
+       eq-render --preset <file> --export <camilladsp|minidsp> [--sample-rate <hz>]
+       eq-render --preset <file> --export <csv|rew> [--sample-rate <hz>] [--points-per-octave <n>]
+       eq-render --dump-preset
+
+The preset is either a state chunk saved by the plugin or a text preset with one
+`parameter name = value` per line. --dump-preset prints the default settings in
+that format. Channels are processed in pairs, a last odd channel on its own as
+both left and right, which bands routed to left, right or side can't be used with.
+--export prints the preamp and bands as CamillaDSP filters or miniDSP biquads
+at --sample-rate, 48000 by default. csv prints the magnitude and phase of the sum
+and each band, rew the sum as a REW text file, with --points-per-octave points
+per octave, 48 by default.";
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+const DEFAULT_EXPORT_SAMPLE_RATE: f32 = 48000.0;
+const DEFAULT_POINTS_PER_OCTAVE: f32 = 48.0;
+
+struct Options {
+    preset: Option<PathBuf>,
+    block_size: usize,
+    out_dir: Option<PathBuf>,
+    dump_preset: bool,
+    export: Option<String>,
+    sample_rate: f32,
+    points_per_octave: f32,
+    files: Vec<PathBuf>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        preset: None,
+        block_size: DEFAULT_BLOCK_SIZE,
+        out_dir: None,
+        dump_preset: false,
+        export: None,
+        sample_rate: DEFAULT_EXPORT_SAMPLE_RATE,
+        points_per_octave: DEFAULT_POINTS_PER_OCTAVE,
+        files: Vec::new(),
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--preset" => options.preset = Some(PathBuf::from(value(&arg)?)),
+            "--out-dir" => options.out_dir = Some(PathBuf::from(value(&arg)?)),
+            "--block-size" => {
+                options.block_size = value(&arg)?
+                    .parse()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .ok_or("--block-size needs a positive number")?
+            }
+            "--dump-preset" => options.dump_preset = true,
+            "--export" => options.export = Some(value(&arg)?),
+            "--sample-rate" => {
+                options.sample_rate = value(&arg)?
+                    .parse()
+                    .ok()
+                    .filter(|rate| *rate > 0.0)
+                    .ok_or("--sample-rate needs a positive number")?
+            }
+            "--points-per-octave" => {
+                options.points_per_octave = value(&arg)?
+                    .parse()
+                    .ok()
+                    .filter(|points| *points >= 1.0)
+                    .ok_or("--points-per-octave needs a number of at least 1")?
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.files.push(PathBuf::from(arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Samples of each channel, scaled to -1..1
+fn read_wav(path: &Path) -> Result<(WavSpec, Vec<Vec<f32>>), String> {
+    let error = |err: hound::Error| format!("{}: {}", path.display(), err);
+    let mut reader = WavReader::open(path).map_err(error)?;
+    let spec = reader.spec();
+    let interleaved = match spec.sample_format {
+        SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<Result<Vec<f32>, hound::Error>>()
+            .map_err(error)?,
+        SampleFormat::Int => {
+            let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|x| x.map(|x| x as f32 * scale))
+                .collect::<Result<Vec<f32>, hound::Error>>()
+                .map_err(error)?
+        }
+    };
+    let channel_count = spec.channels as usize;
+    let channels = (0..channel_count)
+        .map(|c| {
+            interleaved
+                .iter()
+                .skip(c)
+                .step_by(channel_count)
+                .copied()
+                .collect()
+        })
+        .collect();
+    Ok((spec, channels))
+}
+
+/// Write the channels back in the format they were read in, integer formats are clipped
+fn write_wav(path: &Path, spec: WavSpec, channels: &[Vec<f32>]) -> Result<(), String> {
+    let error = |err: hound::Error| format!("{}: {}", path.display(), err);
+    let mut writer = WavWriter::create(path, spec).map_err(error)?;
+    let len = channels.first().map_or(0, |channel| channel.len());
+    let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+    for i in 0..len {
+        for channel in channels {
+            match spec.sample_format {
+                SampleFormat::Float => writer.write_sample(channel[i]),
+                SampleFormat::Int => writer.write_sample(
+                    (channel[i] * full_scale)
+                        .round()
+                        .clamp(-full_scale, full_scale - 1.0) as i32,
+                ),
+            }
+            .map_err(error)?;
+        }
+    }
+    writer.finalize().map_err(error)
+}
+
+/// Run `left` and `right` through a fresh plugin, with its latency removed
+fn render_pair(
+    preset: &[u8],
+    sample_rate: f32,
+    block_size: usize,
+    left: &[f32],
+    right: &[f32],
+) -> Result<(Vec<f32>, Vec<f32>), String> {
+    let mut plugin = EQPlugin::default();
+    plugin
+        .load_preset(preset)
+        .map_err(|err| format!("could not load preset: {}", err))?;
+    // Resets the filters to the preset, so the render does not start with a fade
+    plugin.set_sample_rate(sample_rate);
+    plugin.set_block_size(block_size as i64);
+
+    let latency = plugin.latency();
+    let len = left.len() + latency;
+    let padded = |channel: &[f32]| {
+        let mut padded = channel.to_vec();
+        padded.resize(len, 0.0);
+        padded
+    };
+    let (input_left, input_right) = (padded(left), padded(right));
+    let (mut output_left, mut output_right) = (vec![0.0; len], vec![0.0; len]);
+    for start in (0..len).step_by(block_size) {
+        let end = (start + block_size).min(len);
+        plugin.process_block(
+            [&input_left[start..end], &input_right[start..end]],
+            None,
+            [&mut output_left[start..end], &mut output_right[start..end]],
+        );
+    }
+    output_left.drain(..latency);
+    output_right.drain(..latency);
+    Ok((output_left, output_right))
+}
+
+/// A lone channel runs as left and right alike. Bands routed to one side would
+/// make them differ, and side bands would have nothing to filter, so both are
+/// refused rather than quietly rendering something the plugin would not.
+fn check_mono_routing(preset: &[u8]) -> Result<(), String> {
+    let plugin = EQPlugin::default();
+    plugin
+        .load_preset(preset)
+        .map_err(|err| format!("could not load preset: {}", err))?;
+    for (i, band) in plugin.band_settings().iter().enumerate() {
+        match band.routing {
+            BandRouting::Stereo | BandRouting::Mid => {}
+            _ if !band.enabled => {}
+            routing => {
+                return Err(format!(
+                    "band {} is routed to {:?}, which needs a stereo pair but the last channel is on its own",
+                    i + 1,
+                    routing
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn render_file(
+    preset: &[u8],
+    block_size: usize,
+    input: &Path,
+    output: &Path,
+) -> Result<(), String> {
+    let (spec, mut channels) = read_wav(input)?;
+    if channels.len() % 2 == 1 {
+        check_mono_routing(preset).map_err(|err| format!("{}: {}", input.display(), err))?;
+    }
+    let sample_rate = spec.sample_rate as f32;
+    for pair in channels.chunks_mut(2) {
+        match pair {
+            [left, right] => {
+                let (l, r) = render_pair(preset, sample_rate, block_size, left, right)?;
+                *left = l;
+                *right = r;
+            }
+            // A lone channel goes to both sides, which come out the same
+            [mono] => {
+                let (l, _) = render_pair(preset, sample_rate, block_size, mono, mono)?;
+                *mono = l;
+            }
+            _ => unreachable!(),
+        }
+    }
+    write_wav(output, spec, &channels)
+}
+
+fn run() -> Result<(), String> {
+    let options = parse_args()?;
+    if options.dump_preset {
+        print!("{}", EQPlugin::default().preset_text());
+        return Ok(());
+    }
+
+    let preset_path = options.preset.ok_or(USAGE)?;
+    let preset =
+        std::fs::read(&preset_path).map_err(|err| format!("{}: {}", preset_path.display(), err))?;
+
+    if let Some(format) = &options.export {
+        let plugin = EQPlugin::default();
+        plugin
+            .load_preset(&preset)
+            .map_err(|err| format!("could not load preset: {}", err))?;
+        let text = match format.as_str() {
+            "camilladsp" => plugin.camilladsp_config(options.sample_rate),
+            "minidsp" => plugin.minidsp_biquads(options.sample_rate),
+            "csv" | "rew" => {
+                let response =
+                    plugin.frequency_response(options.points_per_octave, options.sample_rate);
+                Ok(if format == "csv" {
+                    response.to_csv()
+                } else {
+                    response.to_rew_txt(None)
+                })
+            }
+            _ => return Err(format!("unknown export format {}", format)),
+        };
+        print!("{}", text.map_err(|err| err.to_string())?);
+        return Ok(());
+    }
+
+    let jobs = match (&options.out_dir, options.files.as_slice()) {
+        (Some(out_dir), inputs) if !inputs.is_empty() => inputs
+            .iter()
+            .map(|input| {
+                let name = input
+                    .file_name()
+                    .ok_or(format!("{} is not a file", input.display()))?;
+                Ok((input.clone(), out_dir.join(name)))
+            })
+            .collect::<Result<Vec<(PathBuf, PathBuf)>, String>>()?,
+        (None, [input, output]) => vec![(input.clone(), output.clone())],
+        _ => return Err(USAGE.to_string()),
+    };
+    for (input, output) in jobs {
+        render_file(&preset, options.block_size, &input, &output)?;
+        println!("{} -> {}", input.display(), output.display());
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,28 @@
+//! Writes `manifest.ttl` and `basic_eq.ttl` for an LV2 bundle. Build it with the
+//! same band count feature as the plugin library, or the ports will not match.
+
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: lv2-ttl <bundle dir> <library file name>";
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let (dir, binary) = match (args.next(), args.next()) {
+        (Some(dir), Some(binary)) => (PathBuf::from(dir), binary),
+        _ => return Err(USAGE.to_string()),
+    };
+    let write = |name: &str, contents: String| {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).map_err(|err| format!("{}: {}", path.display(), err))
+    };
+    write("manifest.ttl", basic_eq_plugin::manifest_ttl(&binary))?;
+    write("basic_eq.ttl", basic_eq_plugin::plugin_ttl())?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,920 @@
+//! CLAP entry point, built with the `clap` feature. It wraps the same `EQPlugin`
+//! as the VST2 side, with parameters addressed by their stable ids.
+//!
+//! Stepped parameters (band type, mode, ...) are exposed with their plain values,
+//! the rest with the same normalized 0..1 values VST2 hosts see.
+
+use crate::editor::EditHost;
+use crate::eq_effect_parameters::{EQEffectParameters, FILTER_COUNT};
+use crate::parameter::Parameter;
+use crate::{setup_logging, EQPlugin};
+
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::{
+    clap_event_header, clap_event_param_gesture, clap_event_param_value, clap_input_events,
+    clap_output_events, CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_PARAM_GESTURE_BEGIN,
+    CLAP_EVENT_PARAM_GESTURE_END, CLAP_EVENT_PARAM_VALUE,
+};
+use clap_sys::ext::audio_ports::{
+    clap_audio_port_info, clap_plugin_audio_ports, CLAP_AUDIO_PORT_IS_MAIN, CLAP_EXT_AUDIO_PORTS,
+    CLAP_PORT_STEREO,
+};
+use clap_sys::ext::gui::{clap_plugin_gui, clap_window, CLAP_EXT_GUI};
+use clap_sys::ext::latency::{clap_host_latency, clap_plugin_latency, CLAP_EXT_LATENCY};
+use clap_sys::ext::params::{
+    clap_host_params, clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS,
+    CLAP_PARAM_IS_AUTOMATABLE, CLAP_PARAM_IS_BYPASS, CLAP_PARAM_IS_ENUM, CLAP_PARAM_IS_STEPPED,
+};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::host::clap_host;
+use clap_sys::id::{clap_id, CLAP_INVALID_ID};
+use clap_sys::plugin::{clap_plugin, clap_plugin_descriptor};
+use clap_sys::plugin_features::{
+    CLAP_PLUGIN_FEATURE_AUDIO_EFFECT, CLAP_PLUGIN_FEATURE_EQUALIZER, CLAP_PLUGIN_FEATURE_STEREO,
+};
+use clap_sys::process::{
+    clap_process, clap_process_status, CLAP_PROCESS_CONTINUE, CLAP_PROCESS_ERROR,
+};
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::version::CLAP_VERSION;
+
+use vst::editor::Editor;
+use vst::plugin::{HostCallback, Plugin};
+
+use std::cell::{RefCell, UnsafeCell};
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+#[cfg(target_os = "linux")]
+const WINDOW_API: &CStr = clap_sys::ext::gui::CLAP_WINDOW_API_X11;
+#[cfg(target_os = "macos")]
+const WINDOW_API: &CStr = clap_sys::ext::gui::CLAP_WINDOW_API_COCOA;
+#[cfg(target_os = "windows")]
+const WINDOW_API: &CStr = clap_sys::ext::gui::CLAP_WINDOW_API_WIN32;
+
+/// Audio port ids, the main ports are processed in place
+const MAIN_PORT: clap_id = 0;
+const SIDECHAIN_PORT: clap_id = 1;
+
+/// Plugin descriptor, built on first use since the id and name depend on `FILTER_COUNT`
+struct Descriptor {
+    descriptor: clap_plugin_descriptor,
+    _strings: Vec<CString>,
+    _features: Box<[*const c_char; 4]>,
+}
+
+// Only points into itself and at static strings
+unsafe impl Send for Descriptor {}
+unsafe impl Sync for Descriptor {}
+
+fn descriptor() -> &'static Descriptor {
+    static DESCRIPTOR: OnceLock<Descriptor> = OnceLock::new();
+    DESCRIPTOR.get_or_init(|| {
+        let strings = vec![
+            // Like the VST id, each band count has its own id
+            CString::new(format!("net.dgdigital.basic-eq.{}-band", FILTER_COUNT)).unwrap(),
+            CString::new(format!("Basic IMGUI EQ in Rust 0.1 {} Band", FILTER_COUNT)).unwrap(),
+            CString::new("DGriffin").unwrap(),
+            CString::new("https://github.com/DGriffin91/rust-basic-eq-plugin").unwrap(),
+            CString::new(env!("CARGO_PKG_VERSION")).unwrap(),
+            CString::new("").unwrap(),
+        ];
+        let features = Box::new([
+            CLAP_PLUGIN_FEATURE_AUDIO_EFFECT.as_ptr(),
+            CLAP_PLUGIN_FEATURE_EQUALIZER.as_ptr(),
+            CLAP_PLUGIN_FEATURE_STEREO.as_ptr(),
+            ptr::null(),
+        ]);
+        Descriptor {
+            descriptor: clap_plugin_descriptor {
+                clap_version: CLAP_VERSION,
+                id: strings[0].as_ptr(),
+                name: strings[1].as_ptr(),
+                vendor: strings[2].as_ptr(),
+                url: strings[3].as_ptr(),
+                manual_url: strings[5].as_ptr(),
+                support_url: strings[5].as_ptr(),
+                version: strings[4].as_ptr(),
+                description: strings[5].as_ptr(),
+                features: features.as_ptr(),
+            },
+            _strings: strings,
+            _features: features,
+        }
+    })
+}
+
+/// Value of `parameter` as the host sees it
+fn clap_value(parameter: &Parameter) -> f64 {
+    if parameter.is_stepped() {
+        parameter.get() as f64
+    } else {
+        parameter.get_normalized() as f64
+    }
+}
+
+/// Plain value of `parameter` at the host value `value`
+fn plain_value(parameter: &Parameter, value: f64) -> f32 {
+    if parameter.is_stepped() {
+        value.round() as f32
+    } else {
+        parameter.normalized_to_value(value as f32)
+    }
+}
+
+/// Host value of `parameter` for `text`, either the display text of a step or a number
+/// in plain units, optionally around a unit like the displayed values
+fn parse_value(parameter: &Parameter, text: &str) -> Option<f64> {
+    let text = text.trim();
+    if parameter.is_stepped() {
+        let step = (parameter.min as i32..=parameter.max as i32)
+            .find(|step| parameter.display(*step as f32).eq_ignore_ascii_case(text));
+        if let Some(step) = step {
+            return Some(step as f64);
+        }
+    }
+    let value = text.split_whitespace().find_map(|word| {
+        let end = word
+            .find(|c: char| !(c.is_ascii_digit() || c == '-' || c == '+' || c == '.'))
+            .unwrap_or(word.len());
+        word[..end].parse::<f32>().ok()
+    })?;
+    if parameter.is_stepped() {
+        Some(value.round().max(parameter.min).min(parameter.max) as f64)
+    } else {
+        Some(parameter.value_to_normalized(value) as f64)
+    }
+}
+
+/// Copy `text` into a C string buffer, cut to fit
+fn write_c_str(text: &str, buffer: &mut [c_char]) {
+    if buffer.is_empty() {
+        return;
+    }
+    let len = text.len().min(buffer.len() - 1);
+    for (dst, src) in buffer.iter_mut().zip(&text.as_bytes()[..len]) {
+        *dst = *src as c_char;
+    }
+    buffer[len] = 0;
+}
+
+enum Edit {
+    Begin(usize),
+    Value(usize),
+    End(usize),
+}
+
+/// Parameter edits made in the editor, held until they can be sent to the host as
+/// events from `process` or `flush`
+struct EditQueue {
+    host: *const clap_host,
+    host_params: AtomicPtr<clap_host_params>,
+    edits: Mutex<Vec<Edit>>,
+}
+
+// request_flush is thread safe, the rest of the host is not touched
+unsafe impl Send for EditQueue {}
+unsafe impl Sync for EditQueue {}
+
+impl EditQueue {
+    fn push(&self, edit: Edit) {
+        self.edits.lock().unwrap().push(edit);
+        let host_params = self.host_params.load(Ordering::Acquire);
+        unsafe {
+            if let Some(request_flush) = host_params.as_ref().and_then(|ext| ext.request_flush) {
+                request_flush(self.host);
+            }
+        }
+    }
+
+    /// Send the waiting edits to the host. Called from the audio thread, so it
+    /// gives up rather than wait for the editor.
+    unsafe fn send(&self, params: &EQEffectParameters, out: *const clap_output_events) {
+        let try_push = match out.as_ref().and_then(|out| out.try_push) {
+            Some(try_push) => try_push,
+            None => return,
+        };
+        let mut edits = match self.edits.try_lock() {
+            Ok(edits) => edits,
+            Err(_) => return,
+        };
+        for edit in edits.drain(..) {
+            let (index, type_) = match edit {
+                Edit::Begin(index) => (index, CLAP_EVENT_PARAM_GESTURE_BEGIN),
+                Edit::Value(index) => (index, CLAP_EVENT_PARAM_VALUE),
+                Edit::End(index) => (index, CLAP_EVENT_PARAM_GESTURE_END),
+            };
+            let header = |size: usize| clap_event_header {
+                size: size as u32,
+                time: 0,
+                space_id: CLAP_CORE_EVENT_SPACE_ID,
+                type_,
+                flags: 0,
+            };
+            if type_ == CLAP_EVENT_PARAM_VALUE {
+                let event = clap_event_param_value {
+                    header: header(std::mem::size_of::<clap_event_param_value>()),
+                    param_id: params.id(index),
+                    cookie: ptr::null_mut(),
+                    note_id: -1,
+                    port_index: -1,
+                    channel: -1,
+                    key: -1,
+                    value: clap_value(&params[index]),
+                };
+                try_push(out, &event.header);
+            } else {
+                let event = clap_event_param_gesture {
+                    header: header(std::mem::size_of::<clap_event_param_gesture>()),
+                    param_id: params.id(index),
+                };
+                try_push(out, &event.header);
+            }
+        }
+    }
+}
+
+impl EditHost for EditQueue {
+    fn begin_edit(&self, index: usize) {
+        self.push(Edit::Begin(index));
+    }
+
+    // The value is read when the event is sent, the parameter already has it
+    fn automate(&self, index: usize, _normalized: f32) {
+        self.push(Edit::Value(index));
+    }
+
+    fn end_edit(&self, index: usize) {
+        self.push(Edit::End(index));
+    }
+}
+
+struct ClapEQ {
+    plugin: clap_plugin,
+    host: *const clap_host,
+    host_latency: AtomicPtr<clap_host_latency>,
+    params: Arc<EQEffectParameters>,
+    edits: Arc<EditQueue>,
+    /// Only touched from the audio thread, or from the main thread while inactive
+    eq: UnsafeCell<EQPlugin>,
+    /// Copies of the input and sidechain channels, since hosts may process in place
+    scratch: UnsafeCell<[Vec<f32>; 4]>,
+    /// Main thread only
+    editor: RefCell<Option<Box<dyn Editor>>>,
+    /// Latency the host was last told about
+    latency: AtomicU32,
+    /// Set once a restart has been asked for, cleared on activation
+    restart_requested: AtomicBool,
+}
+
+impl ClapEQ {
+    unsafe fn from_plugin<'a>(plugin: *const clap_plugin) -> &'a ClapEQ {
+        &*((*plugin).plugin_data as *const ClapEQ)
+    }
+
+    unsafe fn host_extension<T>(&self, id: &CStr) -> *const T {
+        match (*self.host).get_extension {
+            Some(get_extension) => get_extension(self.host, id.as_ptr()) as *const T,
+            None => ptr::null(),
+        }
+    }
+
+    /// Apply a parameter change from the host, other events are ignored
+    unsafe fn handle_event(&self, header: *const clap_event_header) {
+        let header = &*header;
+        if header.space_id != CLAP_CORE_EVENT_SPACE_ID || header.type_ != CLAP_EVENT_PARAM_VALUE {
+            return;
+        }
+        let event = &*(header as *const clap_event_header as *const clap_event_param_value);
+        if let Some(index) = self.params.index_of_id(event.param_id) {
+            let parameter = &self.params[index];
+            parameter.set(plain_value(parameter, event.value));
+        }
+    }
+}
+
+unsafe extern "C" fn plugin_init(plugin: *const clap_plugin) -> bool {
+    let clap_eq = ClapEQ::from_plugin(plugin);
+    setup_logging();
+    let host_params = clap_eq.host_extension::<clap_host_params>(CLAP_EXT_PARAMS);
+    clap_eq
+        .edits
+        .host_params
+        .store(host_params as *mut clap_host_params, Ordering::Release);
+    let host_latency = clap_eq.host_extension::<clap_host_latency>(CLAP_EXT_LATENCY);
+    clap_eq
+        .host_latency
+        .store(host_latency as *mut clap_host_latency, Ordering::Release);
+    true
+}
+
+unsafe extern "C" fn plugin_destroy(plugin: *const clap_plugin) {
+    let clap_eq = (*plugin).plugin_data as *mut ClapEQ;
+    drop(Box::from_raw(clap_eq));
+}
+
+unsafe extern "C" fn plugin_activate(
+    plugin: *const clap_plugin,
+    sample_rate: f64,
+    _min_frames_count: u32,
+    max_frames_count: u32,
+) -> bool {
+    let clap_eq = ClapEQ::from_plugin(plugin);
+    let eq = &mut *clap_eq.eq.get();
+    eq.set_sample_rate(sample_rate as f32);
+    eq.set_block_size(max_frames_count as i64);
+    for channel in (*clap_eq.scratch.get()).iter_mut() {
+        channel.resize(max_frames_count as usize, 0.0);
+    }
+
+    // Activation is the only time the latency may change
+    clap_eq.restart_requested.store(false, Ordering::Release);
+    let latency = eq.latency() as u32;
+    if clap_eq.latency.swap(latency, Ordering::AcqRel) != latency {
+        let host_latency = clap_eq.host_latency.load(Ordering::Acquire);
+        if let Some(changed) = host_latency.as_ref().and_then(|ext| ext.changed) {
+            changed(clap_eq.host);
+        }
+    }
+    true
+}
+
+unsafe extern "C" fn plugin_deactivate(_plugin: *const clap_plugin) {}
+
+unsafe extern "C" fn plugin_start_processing(_plugin: *const clap_plugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_stop_processing(_plugin: *const clap_plugin) {}
+
+/// Called on the audio thread, so only clears buffers and keeps the kernels
+unsafe extern "C" fn plugin_reset(plugin: *const clap_plugin) {
+    let clap_eq = ClapEQ::from_plugin(plugin);
+    let eq = &mut *clap_eq.eq.get();
+    eq.clear_buffers();
+}
+
+unsafe extern "C" fn plugin_process(
+    plugin: *const clap_plugin,
+    process: *const clap_process,
+) -> clap_process_status {
+    let clap_eq = ClapEQ::from_plugin(plugin);
+    let eq = &mut *clap_eq.eq.get();
+    let scratch = &mut *clap_eq.scratch.get();
+    let process = &*process;
+    let frames = process.frames_count as usize;
+
+    if process.audio_inputs_count < 1 || process.audio_outputs_count < 1 {
+        return CLAP_PROCESS_ERROR;
+    }
+    let input = &*process.audio_inputs;
+    let output = &*process.audio_outputs;
+    if input.channel_count < 2
+        || output.channel_count < 2
+        || input.data32.is_null()
+        || output.data32.is_null()
+        || frames > scratch[0].len()
+    {
+        return CLAP_PROCESS_ERROR;
+    }
+
+    let sidechain = if process.audio_inputs_count >= 2 {
+        let sidechain = &*process.audio_inputs.add(1);
+        if sidechain.channel_count >= 2 && !sidechain.data32.is_null() {
+            Some(sidechain)
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+    let (inputs, sidechain_inputs) = scratch.split_at_mut(2);
+    for (c, channel) in inputs.iter_mut().enumerate() {
+        channel[..frames].copy_from_slice(std::slice::from_raw_parts(*input.data32.add(c), frames));
+    }
+    if let Some(sidechain) = sidechain {
+        for (c, channel) in sidechain_inputs.iter_mut().enumerate() {
+            channel[..frames]
+                .copy_from_slice(std::slice::from_raw_parts(*sidechain.data32.add(c), frames));
+        }
+    }
+    let output_left = std::slice::from_raw_parts_mut(*output.data32, frames);
+    let output_right = std::slice::from_raw_parts_mut(*output.data32.add(1), frames);
+
+    // Split the block at each parameter change, so automation is sample accurate
+    let mut process_range = |start: usize, end: usize| {
+        if start < end {
+            eq.process_block(
+                [&inputs[0][start..end], &inputs[1][start..end]],
+                sidechain.map(|_| {
+                    [
+                        &sidechain_inputs[0][start..end],
+                        &sidechain_inputs[1][start..end],
+                    ]
+                }),
+                [&mut output_left[start..end], &mut output_right[start..end]],
+            );
+        }
+    };
+    let mut start = 0;
+    if let Some(in_events) = process.in_events.as_ref() {
+        let count = in_events.size.map_or(0, |size| size(in_events));
+        for i in 0..count {
+            let header = match in_events.get {
+                Some(get) => get(in_events, i),
+                None => break,
+            };
+            if header.is_null() {
+                continue;
+            }
+            let time = ((*header).time as usize).min(frames);
+            if time > start {
+                process_range(start, time);
+                start = time;
+            }
+            clap_eq.handle_event(header);
+        }
+    }
+    process_range(start, frames);
+
+    clap_eq.edits.send(&clap_eq.params, process.out_events);
+
+    // A new phase mode or oversampling factor changes the latency, which needs a
+    // restart to report. Asked for once, until the host activates the plugin again.
+    if eq.latency() as u32 != clap_eq.latency.load(Ordering::Acquire)
+        && !clap_eq.restart_requested.swap(true, Ordering::AcqRel)
+    {
+        if let Some(request_restart) = (*clap_eq.host).request_restart {
+            request_restart(clap_eq.host);
+        }
+    }
+    CLAP_PROCESS_CONTINUE
+}
+
+unsafe extern "C" fn plugin_get_extension(
+    _plugin: *const clap_plugin,
+    id: *const c_char,
+) -> *const c_void {
+    let id = CStr::from_ptr(id);
+    if id == CLAP_EXT_PARAMS {
+        &PARAMS as *const clap_plugin_params as *const c_void
+    } else if id == CLAP_EXT_STATE {
+        &STATE as *const clap_plugin_state as *const c_void
+    } else if id == CLAP_EXT_AUDIO_PORTS {
+        &AUDIO_PORTS as *const clap_plugin_audio_ports as *const c_void
+    } else if id == CLAP_EXT_LATENCY {
+        &LATENCY as *const clap_plugin_latency as *const c_void
+    } else if id == CLAP_EXT_GUI {
+        &GUI as *const clap_plugin_gui as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn plugin_on_main_thread(_plugin: *const clap_plugin) {}
+
+static PARAMS: clap_plugin_params = clap_plugin_params {
+    count: Some(params_count),
+    get_info: Some(params_get_info),
+    get_value: Some(params_get_value),
+    value_to_text: Some(params_value_to_text),
+    text_to_value: Some(params_text_to_value),
+    flush: Some(params_flush),
+};
+
+unsafe extern "C" fn params_count(plugin: *const clap_plugin) -> u32 {
+    ClapEQ::from_plugin(plugin).params.len() as u32
+}
+
+unsafe extern "C" fn params_get_info(
+    plugin: *const clap_plugin,
+    param_index: u32,
+    param_info: *mut clap_param_info,
+) -> bool {
+    let params = &ClapEQ::from_plugin(plugin).params;
+    let index = param_index as usize;
+    if index >= params.len() {
+        return false;
+    }
+    let parameter = &params[index];
+    let info = &mut *param_info;
+    info.id = params.id(index);
+    info.cookie = ptr::null_mut();
+    if parameter.is_stepped() {
+        info.flags = CLAP_PARAM_IS_AUTOMATABLE | CLAP_PARAM_IS_STEPPED | CLAP_PARAM_IS_ENUM;
+        info.min_value = parameter.min as f64;
+        info.max_value = parameter.max as f64;
+        info.default_value = parameter.default as f64;
+    } else {
+        info.flags = CLAP_PARAM_IS_AUTOMATABLE;
+        info.min_value = 0.0;
+        info.max_value = 1.0;
+        info.default_value = parameter.get_normalized_default() as f64;
+    }
+    if ptr::eq(parameter, &params.bypass) {
+        info.flags |= CLAP_PARAM_IS_BYPASS;
+    }
+    write_c_str(&parameter.get_name(), &mut info.name);
+    let module = match params.band_slot(index) {
+        Some((band, _)) => format!("Band {}", band + 1),
+        None => String::new(),
+    };
+    write_c_str(&module, &mut info.module);
+    true
+}
+
+unsafe extern "C" fn params_get_value(
+    plugin: *const clap_plugin,
+    param_id: clap_id,
+    out_value: *mut f64,
+) -> bool {
+    let params = &ClapEQ::from_plugin(plugin).params;
+    match params.index_of_id(param_id) {
+        Some(index) => {
+            *out_value = clap_value(&params[index]);
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn params_value_to_text(
+    plugin: *const clap_plugin,
+    param_id: clap_id,
+    value: f64,
+    out_buffer: *mut c_char,
+    out_buffer_capacity: u32,
+) -> bool {
+    let params = &ClapEQ::from_plugin(plugin).params;
+    let index = match params.index_of_id(param_id) {
+        Some(index) => index,
+        None => return false,
+    };
+    let parameter = &params[index];
+    let text = parameter.display(plain_value(parameter, value));
+    let buffer = std::slice::from_raw_parts_mut(out_buffer, out_buffer_capacity as usize);
+    write_c_str(&text, buffer);
+    true
+}
+
+unsafe extern "C" fn params_text_to_value(
+    plugin: *const clap_plugin,
+    param_id: clap_id,
+    param_value_text: *const c_char,
+    out_value: *mut f64,
+) -> bool {
+    let params = &ClapEQ::from_plugin(plugin).params;
+    let index = match params.index_of_id(param_id) {
+        Some(index) => index,
+        None => return false,
+    };
+    let text = CStr::from_ptr(param_value_text).to_string_lossy();
+    match parse_value(&params[index], &text) {
+        Some(value) => {
+            *out_value = value;
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn params_flush(
+    plugin: *const clap_plugin,
+    in_: *const clap_input_events,
+    out: *const clap_output_events,
+) {
+    let clap_eq = ClapEQ::from_plugin(plugin);
+    if let Some(in_events) = in_.as_ref() {
+        let count = in_events.size.map_or(0, |size| size(in_events));
+        for i in 0..count {
+            if let Some(get) = in_events.get {
+                let header = get(in_events, i);
+                if !header.is_null() {
+                    clap_eq.handle_event(header);
+                }
+            }
+        }
+    }
+    clap_eq.edits.send(&clap_eq.params, out);
+}
+
+static STATE: clap_plugin_state = clap_plugin_state {
+    save: Some(state_save),
+    load: Some(state_load),
+};
+
+/// Saves the same versioned chunk as the VST2 side
+unsafe extern "C" fn state_save(plugin: *const clap_plugin, stream: *const clap_ostream) -> bool {
+    let data = ClapEQ::from_plugin(plugin).params.save_state();
+    let write = match (*stream).write {
+        Some(write) => write,
+        None => return false,
+    };
+    let mut written = 0;
+    while written < data.len() {
+        let result = write(
+            stream,
+            data[written..].as_ptr() as *const c_void,
+            (data.len() - written) as u64,
+        );
+        if result <= 0 {
+            return false;
+        }
+        written += result as usize;
+    }
+    true
+}
+
+unsafe extern "C" fn state_load(plugin: *const clap_plugin, stream: *const clap_istream) -> bool {
+    let read = match (*stream).read {
+        Some(read) => read,
+        None => return false,
+    };
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let result = read(
+            stream,
+            buffer.as_mut_ptr() as *mut c_void,
+            buffer.len() as u64,
+        );
+        if result < 0 {
+            return false;
+        }
+        if result == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..result as usize]);
+    }
+    match ClapEQ::from_plugin(plugin).params.load_state(&data) {
+        Ok(()) => true,
+        Err(err) => {
+            ::log::warn!("could not load state: {}", err);
+            false
+        }
+    }
+}
+
+static AUDIO_PORTS: clap_plugin_audio_ports = clap_plugin_audio_ports {
+    count: Some(audio_ports_count),
+    get: Some(audio_ports_get),
+};
+
+/// A main stereo input and output, and a stereo sidechain input for the dynamic bands
+unsafe extern "C" fn audio_ports_count(_plugin: *const clap_plugin, is_input: bool) -> u32 {
+    if is_input {
+        2
+    } else {
+        1
+    }
+}
+
+unsafe extern "C" fn audio_ports_get(
+    _plugin: *const clap_plugin,
+    index: u32,
+    is_input: bool,
+    info: *mut clap_audio_port_info,
+) -> bool {
+    let info = &mut *info;
+    let (id, name, flags, in_place_pair) = match (index, is_input) {
+        (0, _) => (MAIN_PORT, "Main", CLAP_AUDIO_PORT_IS_MAIN, MAIN_PORT),
+        (1, true) => (SIDECHAIN_PORT, "Sidechain", 0, CLAP_INVALID_ID),
+        _ => return false,
+    };
+    info.id = id;
+    write_c_str(name, &mut info.name);
+    info.flags = flags;
+    info.channel_count = 2;
+    info.port_type = CLAP_PORT_STEREO.as_ptr();
+    info.in_place_pair = in_place_pair;
+    true
+}
+
+static LATENCY: clap_plugin_latency = clap_plugin_latency {
+    get: Some(latency_get),
+};
+
+unsafe extern "C" fn latency_get(plugin: *const clap_plugin) -> u32 {
+    ClapEQ::from_plugin(plugin).latency.load(Ordering::Acquire)
+}
+
+static GUI: clap_plugin_gui = clap_plugin_gui {
+    is_api_supported: Some(gui_is_api_supported),
+    get_preferred_api: Some(gui_get_preferred_api),
+    create: Some(gui_create),
+    destroy: Some(gui_destroy),
+    set_scale: Some(gui_set_scale),
+    get_size: Some(gui_get_size),
+    can_resize: Some(gui_can_resize),
+    get_resize_hints: None,
+    adjust_size: Some(gui_adjust_size),
+    set_size: Some(gui_set_size),
+    set_parent: Some(gui_set_parent),
+    set_transient: Some(gui_set_transient),
+    suggest_title: None,
+    show: Some(gui_show),
+    hide: Some(gui_hide),
+};
+
+/// Only embedded windows, like VST2
+unsafe extern "C" fn gui_is_api_supported(
+    _plugin: *const clap_plugin,
+    api: *const c_char,
+    is_floating: bool,
+) -> bool {
+    !is_floating && CStr::from_ptr(api) == WINDOW_API
+}
+
+unsafe extern "C" fn gui_get_preferred_api(
+    _plugin: *const clap_plugin,
+    api: *mut *const c_char,
+    is_floating: *mut bool,
+) -> bool {
+    *api = WINDOW_API.as_ptr();
+    *is_floating = false;
+    true
+}
+
+unsafe extern "C" fn gui_create(
+    plugin: *const clap_plugin,
+    api: *const c_char,
+    is_floating: bool,
+) -> bool {
+    gui_is_api_supported(plugin, api, is_floating)
+        && ClapEQ::from_plugin(plugin).editor.borrow().is_some()
+}
+
+unsafe extern "C" fn gui_destroy(plugin: *const clap_plugin) {
+    if let Some(editor) = ClapEQ::from_plugin(plugin).editor.borrow_mut().as_mut() {
+        editor.close();
+    }
+}
+
+unsafe extern "C" fn gui_set_scale(_plugin: *const clap_plugin, _scale: f64) -> bool {
+    false
+}
+
+unsafe extern "C" fn gui_get_size(
+    plugin: *const clap_plugin,
+    width: *mut u32,
+    height: *mut u32,
+) -> bool {
+    match ClapEQ::from_plugin(plugin).editor.borrow().as_ref() {
+        Some(editor) => {
+            let (w, h) = editor.size();
+            *width = w as u32;
+            *height = h as u32;
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn gui_can_resize(_plugin: *const clap_plugin) -> bool {
+    false
+}
+
+unsafe extern "C" fn gui_adjust_size(
+    plugin: *const clap_plugin,
+    width: *mut u32,
+    height: *mut u32,
+) -> bool {
+    gui_get_size(plugin, width, height)
+}
+
+unsafe extern "C" fn gui_set_size(plugin: *const clap_plugin, width: u32, height: u32) -> bool {
+    let (mut w, mut h) = (0, 0);
+    gui_get_size(plugin, &mut w, &mut h) && (w, h) == (width, height)
+}
+
+unsafe extern "C" fn gui_set_parent(
+    plugin: *const clap_plugin,
+    window: *const clap_window,
+) -> bool {
+    let window = &*window;
+    if CStr::from_ptr(window.api) != WINDOW_API {
+        return false;
+    }
+    #[cfg(target_os = "linux")]
+    let parent = window.specific.x11 as *mut c_void;
+    #[cfg(target_os = "macos")]
+    let parent = window.specific.cocoa;
+    #[cfg(target_os = "windows")]
+    let parent = window.specific.win32;
+    match ClapEQ::from_plugin(plugin).editor.borrow_mut().as_mut() {
+        Some(editor) => editor.open(parent),
+        None => false,
+    }
+}
+
+unsafe extern "C" fn gui_set_transient(
+    _plugin: *const clap_plugin,
+    _window: *const clap_window,
+) -> bool {
+    false
+}
+
+unsafe extern "C" fn gui_show(_plugin: *const clap_plugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn gui_hide(_plugin: *const clap_plugin) -> bool {
+    true
+}
+
+static FACTORY: clap_plugin_factory = clap_plugin_factory {
+    get_plugin_count: Some(factory_get_plugin_count),
+    get_plugin_descriptor: Some(factory_get_plugin_descriptor),
+    create_plugin: Some(factory_create_plugin),
+};
+
+unsafe extern "C" fn factory_get_plugin_count(_factory: *const clap_plugin_factory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn factory_get_plugin_descriptor(
+    _factory: *const clap_plugin_factory,
+    index: u32,
+) -> *const clap_plugin_descriptor {
+    if index == 0 {
+        &descriptor().descriptor
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn factory_create_plugin(
+    _factory: *const clap_plugin_factory,
+    host: *const clap_host,
+    plugin_id: *const c_char,
+) -> *const clap_plugin {
+    let descriptor = &descriptor().descriptor;
+    if host.is_null() || CStr::from_ptr(plugin_id) != CStr::from_ptr(descriptor.id) {
+        return ptr::null();
+    }
+
+    let edits = Arc::new(EditQueue {
+        host,
+        host_params: AtomicPtr::new(ptr::null_mut()),
+        edits: Mutex::new(Vec::new()),
+    });
+    // No VST host, so latency reports from the plugin itself go nowhere
+    let mut eq = EQPlugin::with_edit_host(HostCallback::default(), edits.clone());
+    let editor = eq.get_editor();
+    let latency = eq.latency() as u32;
+
+    let clap_eq = Box::into_raw(Box::new(ClapEQ {
+        plugin: clap_plugin {
+            desc: descriptor,
+            plugin_data: ptr::null_mut(),
+            init: Some(plugin_init),
+            destroy: Some(plugin_destroy),
+            activate: Some(plugin_activate),
+            deactivate: Some(plugin_deactivate),
+            start_processing: Some(plugin_start_processing),
+            stop_processing: Some(plugin_stop_processing),
+            reset: Some(plugin_reset),
+            process: Some(plugin_process),
+            get_extension: Some(plugin_get_extension),
+            on_main_thread: Some(plugin_on_main_thread),
+        },
+        host,
+        host_latency: AtomicPtr::new(ptr::null_mut()),
+        params: eq.params.clone(),
+        edits,
+        eq: UnsafeCell::new(eq),
+        scratch: UnsafeCell::new([Vec::new(), Vec::new(), Vec::new(), Vec::new()]),
+        editor: RefCell::new(editor),
+        latency: AtomicU32::new(latency),
+        restart_requested: AtomicBool::new(false),
+    }));
+    (*clap_eq).plugin.plugin_data = clap_eq as *mut c_void;
+    &(*clap_eq).plugin
+}
+
+unsafe extern "C" fn entry_init(_plugin_path: *const c_char) -> bool {
+    true
+}
+
+unsafe extern "C" fn entry_deinit() {}
+
+unsafe extern "C" fn entry_get_factory(factory_id: *const c_char) -> *const c_void {
+    if CStr::from_ptr(factory_id) == CLAP_PLUGIN_FACTORY_ID {
+        &FACTORY as *const clap_plugin_factory as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static clap_entry: clap_plugin_entry = clap_plugin_entry {
+    clap_version: CLAP_VERSION,
+    init: Some(entry_init),
+    deinit: Some(entry_deinit),
+    get_factory: Some(entry_get_factory),
+};
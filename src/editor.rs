@@ -1,30 +1,41 @@
 use crate::{
     editor_elements::*,
-    eq_effect_parameters::{BandKind, BandMode},
-    get_coefficients_iir1, get_coefficients_iir2,
+    eq_effect_parameters::{
+        BandKind, BandMode, BandRouting, DeltaMode, DynamicMode, FilterDesign, Oversampling,
+        PhaseMode, Precision,
+    },
+    LatencyReport,
 };
+use eq_core::BandCoefficients;
 use imgui::*;
 use vst::util::AtomicFloat;
 
+use crate::autoeq::parse_autoeq;
+use crate::spectrum::{AnalyzerSettings, SampleRing, SpectrumAnalyzer, FFT_SIZES};
 use crate::units::map_to_freq;
 use imgui_baseview::{HiDpiMode, ImguiWindow, RenderSettings, Settings};
 
-use crate::eq_effect_parameters::EQEffectParameters;
+use crate::eq_effect_parameters::{BandParameters, EQEffectParameters};
 use crate::parameter::Parameter;
 
 use vst::editor::Editor;
+use vst::host::Host;
+use vst::plugin::HostCallback;
 
 use baseview::{Size, WindowOpenOptions, WindowScalePolicy};
 
 use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use std::f64::consts::PI;
 use std::sync::Arc;
 
 const WINDOW_WIDTH: usize = 1300;
 const WINDOW_HEIGHT: usize = 1300;
 const WINDOW_WIDTH_F: f32 = WINDOW_WIDTH as f32;
 const WINDOW_HEIGHT_F: f32 = WINDOW_HEIGHT as f32;
+/// Bands wrap onto a new row after this many columns
+const MAX_BAND_COLUMNS: usize = 8;
 
-fn input_float(ui: &Ui, parameter: &Parameter, i: usize) {
+fn input_float(ui: &Ui, state: &EditorState, parameter: &Parameter, i: usize) {
     let knob_id = &ImString::new(format!("##{}_{}_KNOB_CONTORL_", parameter.get_name(), i));
     let mut val = parameter.get();
 
@@ -35,14 +46,20 @@ fn input_float(ui: &Ui, parameter: &Parameter, i: usize) {
         * (parameter.max - parameter.min))
         .max(0.00001) as f32;
     let cursor = ui.cursor_pos();
-    if Drag::new(knob_id)
+    let changed = Drag::new(knob_id)
         .range(parameter.min..=parameter.max)
         .speed(speed * 0.001)
         .display_format(im_str!(""))
-        .build(ui, &mut val)
-    {
+        .build(ui, &mut val);
+    if ui.is_item_activated() {
+        state.begin_edit(parameter);
+    }
+    if changed {
         //parameter.set(*knob.p_value)
-        parameter.set(val)
+        state.set_parameter(parameter, val)
+    }
+    if ui.is_item_deactivated() {
+        state.end_edit(parameter);
     }
 
     let cursor2 = ui.cursor_pos();
@@ -54,8 +71,20 @@ fn input_float(ui: &Ui, parameter: &Parameter, i: usize) {
     ui.set_cursor_pos(cursor2);
 }
 
+/// Checkbox for an on/off parameter
+fn toggle(ui: &Ui, state: &EditorState, parameter: &Parameter, label: &str, i: usize) {
+    let mut value = parameter.get() >= 0.5;
+    if ui.checkbox(
+        &ImString::new(format!("{}##{}_{}", label, parameter.get_name(), i)),
+        &mut value,
+    ) {
+        state.edit_parameter(parameter, if value { 1.0 } else { 0.0 });
+    }
+}
+
 fn popup_select<F: Fn(usize) -> bool>(
     ui: &Ui,
+    state: &EditorState,
     parameter: &Parameter,
     i: usize,
     button_fn: F,
@@ -76,7 +105,7 @@ fn popup_select<F: Fn(usize) -> bool>(
     ui.popup(popup_str, || {
         for j in 0..qty_of_options {
             if button_fn(j) {
-                parameter.set(j as f32);
+                state.edit_parameter(parameter, j as f32);
                 ui.close_current_popup();
                 break;
             }
@@ -84,14 +113,524 @@ fn popup_select<F: Fn(usize) -> bool>(
     });
 }
 
+fn draw_analyzer(
+    ui: &Ui,
+    analyzer: &SpectrumAnalyzer,
+    size: [f32; 2],
+    color: [f32; 4],
+    sample_rate: f32,
+) {
+    let width = size[0] as usize;
+    let pixel_freqs = |i: usize| {
+        (
+            map_to_freq(i as f32 / size[0]),
+            map_to_freq((i + 1) as f32 / size[0]),
+        )
+    };
+    let peak_color = [color[0], color[1], color[2], color[3] * 0.4];
+    draw_spectrum(ui, size, peak_color, 1.0, width, |i| {
+        let (f_lo, f_hi) = pixel_freqs(i);
+        analyzer.level_between(&analyzer.peaks, f_lo, f_hi, sample_rate)
+    });
+    draw_spectrum(ui, size, color, 1.0, width, |i| {
+        let (f_lo, f_hi) = pixel_freqs(i);
+        analyzer.level_between(&analyzer.smoothed, f_lo, f_hi, sample_rate)
+    });
+}
+
+fn analyzer_controls(ui: &Ui, window_state: &mut WindowState) {
+    ui.checkbox(im_str!("Pre"), &mut window_state.show_pre);
+    ui.same_line(0.0);
+    ui.checkbox(im_str!("Post"), &mut window_state.show_post);
+    ui.same_line(0.0);
+
+    let settings = &mut window_state.analyzer_settings;
+    if ui.button(
+        &ImString::new(format!("FFT {}##fft_size", settings.fft_size)),
+        [0.0, 0.0],
+    ) {
+        ui.open_popup(im_str!("fft_size_popup"));
+    }
+    ui.popup(im_str!("fft_size_popup"), || {
+        for fft_size in FFT_SIZES.iter() {
+            if ui.radio_button_bool(
+                &ImString::new(format!("{}", fft_size)),
+                settings.fft_size == *fft_size,
+            ) {
+                settings.fft_size = *fft_size;
+                ui.close_current_popup();
+            }
+        }
+    });
+    ui.same_line(0.0);
+    ui.push_item_width(200.0);
+    Slider::new(im_str!("Averaging"))
+        .range(0.0..=0.99)
+        .display_format(im_str!("%.2f"))
+        .build(ui, &mut settings.averaging);
+    ui.same_line(0.0);
+    Slider::new(im_str!("Slope"))
+        .range(0.0..=6.0)
+        .display_format(im_str!("%.1f dB/oct"))
+        .build(ui, &mut settings.slope);
+    ui.pop_item_width();
+}
+
+/// What the eq graph plots
+#[derive(PartialEq, Clone, Copy)]
+enum GraphView {
+    Magnitude,
+    /// Unwrapped, in degrees
+    Phase,
+    /// In ms
+    GroupDelay,
+}
+
+const GRAPH_VIEWS: [GraphView; 3] = [
+    GraphView::Magnitude,
+    GraphView::Phase,
+    GraphView::GroupDelay,
+];
+
+impl GraphView {
+    fn name(&self) -> &'static str {
+        match self {
+            GraphView::Magnitude => "Magnitude",
+            GraphView::Phase => "Phase",
+            GraphView::GroupDelay => "Group delay",
+        }
+    }
+
+    fn axis(&self) -> &'static ValueAxis {
+        match self {
+            GraphView::Magnitude => &MAGNITUDE_AXIS,
+            GraphView::Phase => &PHASE_AXIS,
+            GraphView::GroupDelay => &GROUP_DELAY_AXIS,
+        }
+    }
+
+    /// Values of one band at `points` positions across the graph, negated since
+    /// screen y grows downwards. Adding the values of bands gives their sum.
+    fn band_values(&self, coeffs: &BandCoefficients, points: usize, sample_rate: f32) -> Vec<f32> {
+        let freqs = (0..points).map(|x| map_to_freq(x as f32 / points as f32));
+        match self {
+            GraphView::Magnitude => freqs
+                .map(|f_hz| {
+                    let y = coeffs.magnitude(f_hz, sample_rate);
+                    -(y.max(0.0).log(10.0) * 20.0) as f32
+                })
+                .collect(),
+            GraphView::Phase => {
+                let mut last = 0.0f64;
+                let mut offset = 0.0f64;
+                freqs
+                    .enumerate()
+                    .map(|(i, f_hz)| {
+                        let mut phase = coeffs.response(f_hz, sample_rate).arg() + offset;
+                        if i > 0 {
+                            while phase - last > PI {
+                                offset -= 2.0 * PI;
+                                phase -= 2.0 * PI;
+                            }
+                            while phase - last < -PI {
+                                offset += 2.0 * PI;
+                                phase += 2.0 * PI;
+                            }
+                        }
+                        last = phase;
+                        -phase.to_degrees() as f32
+                    })
+                    .collect()
+            }
+            GraphView::GroupDelay => freqs
+                .map(|f_hz| {
+                    // Minus the phase slope, over a small step around f_hz
+                    let df = f_hz * 0.001;
+                    let ratio = coeffs.response(f_hz + df, sample_rate)
+                        / coeffs.response(f_hz - df, sample_rate);
+                    let seconds = -ratio.arg() / (2.0 * PI * 2.0 * df as f64);
+                    -(seconds * 1000.0) as f32
+                })
+                .collect(),
+        }
+    }
+}
+
+fn view_controls(ui: &Ui, window_state: &mut WindowState) {
+    for view in GRAPH_VIEWS.iter() {
+        if ui.radio_button_bool(
+            &ImString::new(view.name()),
+            window_state.graph_view == *view,
+        ) {
+            window_state.graph_view = *view;
+        }
+        ui.same_line(0.0);
+    }
+    ui.checkbox(im_str!("Bands"), &mut window_state.show_bands);
+}
+
+/// Points per octave the response exports can have
+const RESPONSE_RESOLUTIONS: [u32; 6] = [3, 6, 12, 24, 48, 96];
+
+/// Sample rates the biquad exports can be made for
+const EXPORT_SAMPLE_RATES: [u32; 5] = [44100, 48000, 88200, 96000, 192000];
+
+/// Import and export an Equalizer APO / AutoEQ file at the typed path, export
+/// the bands as CamillaDSP or miniDSP biquads, or the response as CSV or REW text
+fn file_controls(ui: &Ui, window_state: &mut WindowState) {
+    let state = window_state.state.clone();
+    ui.push_item_width(400.0);
+    ui.input_text(im_str!("##file_path"), &mut window_state.file_path)
+        .build();
+    ui.pop_item_width();
+    ui.same_line(0.0);
+    let path = window_state.file_path.to_str().to_string();
+    if ui.button(im_str!("Import AutoEQ"), [0.0, 0.0]) {
+        let result = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| parse_autoeq(&text).map_err(|err| err.to_string()))
+            .and_then(|autoeq| {
+                state
+                    .params
+                    .apply_autoeq(&autoeq, |parameter, value| {
+                        state.edit_parameter(parameter, value)
+                    })
+                    .map_err(|err| err.to_string())
+            });
+        window_state.file_status = match result {
+            Ok(()) => format!("imported {}", path),
+            Err(err) => err,
+        };
+    }
+    ui.same_line(0.0);
+    if ui.button(im_str!("Export AutoEQ"), [0.0, 0.0]) {
+        let result = state
+            .params
+            .save_autoeq()
+            .map_err(|err| err.to_string())
+            .and_then(|text| std::fs::write(&path, text).map_err(|err| err.to_string()));
+        window_state.file_status = match result {
+            Ok(()) => format!("exported {}", path),
+            Err(err) => err,
+        };
+    }
+    ui.same_line(0.0);
+    let export_rate = window_state.export_sample_rate;
+    if ui.button(
+        &ImString::new(format!("{} Hz##export_rate", export_rate)),
+        [0.0, 0.0],
+    ) {
+        ui.open_popup(im_str!("export_rate_popup"));
+    }
+    ui.popup(im_str!("export_rate_popup"), || {
+        for rate in EXPORT_SAMPLE_RATES.iter() {
+            if ui.radio_button_bool(&ImString::new(format!("{} Hz", rate)), export_rate == *rate) {
+                window_state.export_sample_rate = *rate;
+                ui.close_current_popup();
+            }
+        }
+    });
+    ui.same_line(0.0);
+    let camilladsp = ui.button(im_str!("Export CamillaDSP"), [0.0, 0.0]);
+    ui.same_line(0.0);
+    let minidsp = ui.button(im_str!("Export miniDSP"), [0.0, 0.0]);
+    if camilladsp || minidsp {
+        let sample_rate = window_state.export_sample_rate as f32;
+        let text = if camilladsp {
+            state.params.save_camilladsp(sample_rate)
+        } else {
+            state.params.save_minidsp(sample_rate)
+        };
+        let result = text
+            .map_err(|err| err.to_string())
+            .and_then(|text| std::fs::write(&path, text).map_err(|err| err.to_string()));
+        window_state.file_status = match result {
+            Ok(()) => format!("exported {}", path),
+            Err(err) => err,
+        };
+    }
+
+    let resolution = window_state.response_resolution;
+    if ui.button(
+        &ImString::new(format!("1/{} oct##response_resolution", resolution)),
+        [0.0, 0.0],
+    ) {
+        ui.open_popup(im_str!("response_resolution_popup"));
+    }
+    ui.popup(im_str!("response_resolution_popup"), || {
+        for points in RESPONSE_RESOLUTIONS.iter() {
+            if ui.radio_button_bool(
+                &ImString::new(format!("1/{} oct", points)),
+                resolution == *points,
+            ) {
+                window_state.response_resolution = *points;
+                ui.close_current_popup();
+            }
+        }
+    });
+    ui.same_line(0.0);
+    let csv = ui.button(im_str!("Export response CSV"), [0.0, 0.0]);
+    ui.same_line(0.0);
+    let rew = ui.button(im_str!("Export response REW"), [0.0, 0.0]);
+    if csv || rew {
+        let response = state
+            .params
+            .frequency_response(resolution as f32, state.sample_rate.get());
+        let text = if csv {
+            response.to_csv()
+        } else {
+            response.to_rew_txt(None)
+        };
+        window_state.file_status = match std::fs::write(&path, text) {
+            Ok(()) => format!("exported {}", path),
+            Err(err) => err.to_string(),
+        };
+    }
+    ui.same_line(0.0);
+    ui.text(&window_state.file_status);
+}
+
 pub struct EditorState {
     pub params: Arc<EQEffectParameters>,
     pub sample_rate: Arc<AtomicFloat>,
+    pub pre_samples: Arc<SampleRing>,
+    pub post_samples: Arc<SampleRing>,
+    /// Gain each band is running at, including any dynamic gain change
+    pub dynamic_gains: Arc<Vec<AtomicFloat>>,
+    pub host: Arc<dyn EditHost>,
+}
+
+/// Where the editor reports parameter edits, so the host can record automation.
+/// Parameters are given by host index.
+pub trait EditHost: Send + Sync {
+    fn begin_edit(&self, index: usize);
+    fn automate(&self, index: usize, normalized: f32);
+    fn end_edit(&self, index: usize);
+}
+
+impl EditHost for HostCallback {
+    fn begin_edit(&self, index: usize) {
+        Host::begin_edit(self, index as i32);
+    }
+
+    fn automate(&self, index: usize, normalized: f32) {
+        Host::automate(self, index as i32, normalized);
+    }
+
+    fn end_edit(&self, index: usize) {
+        Host::end_edit(self, index as i32);
+    }
+}
+
+impl EditorState {
+    /// Tell the host a gesture on `parameter` started, e.g. a drag
+    pub fn begin_edit(&self, parameter: &Parameter) {
+        if let Some(index) = self.params.index_of(parameter) {
+            self.host.begin_edit(index);
+        }
+    }
+
+    /// Set `parameter` and report the new value to the host for automation
+    pub fn set_parameter(&self, parameter: &Parameter, value: f32) {
+        parameter.set(value);
+        if let Some(index) = self.params.index_of(parameter) {
+            self.host.automate(index, parameter.get_normalized());
+        }
+    }
+
+    pub fn end_edit(&self, parameter: &Parameter) {
+        if let Some(index) = self.params.index_of(parameter) {
+            self.host.end_edit(index);
+        }
+    }
+
+    /// A single step edit, wrapped in its own gesture
+    pub fn edit_parameter(&self, parameter: &Parameter, value: f32) {
+        self.begin_edit(parameter);
+        self.set_parameter(parameter, value);
+        self.end_edit(parameter);
+    }
+}
+
+const ROUTING_TARGETS: [BandRouting; 4] = [
+    BandRouting::Left,
+    BandRouting::Right,
+    BandRouting::Mid,
+    BandRouting::Side,
+];
+
+/// Curve and node colour for bands that only filter part of the stereo signal
+fn routing_color(routing: BandRouting) -> Option<[f32; 4]> {
+    match routing {
+        BandRouting::Stereo => None,
+        BandRouting::Left => Some([0.3, 0.6, 1.0, 1.0]),
+        BandRouting::Right => Some([1.0, 0.35, 0.35, 1.0]),
+        BandRouting::Mid => Some([0.4, 0.9, 0.4, 1.0]),
+        BandRouting::Side => Some([1.0, 0.85, 0.3, 1.0]),
+    }
+}
+
+/// Disabled bands are greyed out on the graph
+const DISABLED_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+/// Colour of a band's node and curve, `stereo` is used for enabled stereo bands
+fn band_color(ui: &Ui, band: &BandParameters, stereo: StyleColor) -> [f32; 4] {
+    if !band.is_enabled() {
+        return DISABLED_COLOR;
+    }
+    routing_color(band.get_routing()).unwrap_or_else(|| ui.style_color(stereo))
+}
+
+/// Drag, scroll and right click the band handles on the graph
+fn band_nodes(ui: &Ui, window_state: &mut WindowState, graph: GraphArea, graph_hovered: bool) {
+    let state = window_state.state.clone();
+    let params = &state.params;
+    let mouse = ui.io().mouse_pos;
+    // Outside the magnitude view the nodes sit on the 0 line and only move in frequency
+    let magnitude = window_state.graph_view == GraphView::Magnitude;
+    let node_positions = params
+        .bands
+        .iter()
+        .map(|band| {
+            let db = if magnitude && band.get_kind().uses_gain() {
+                band.db_gain.get()
+            } else {
+                0.0
+            };
+            graph.to_screen(band.freq.get(), db)
+        })
+        .collect::<Vec<[f32; 2]>>();
+
+    let hovered = if graph_hovered {
+        node_positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i, (p[0] - mouse[0]).hypot(p[1] - mouse[1])))
+            .filter(|(_, dist)| *dist < 12.0)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(i, _)| i)
+    } else {
+        None
+    };
+
+    if let Some(i) = hovered {
+        if ui.is_mouse_clicked(MouseButton::Left) {
+            window_state.dragging_band = Some(i);
+            // Kinds without gain only move in frequency, so only they get a gain gesture
+            window_state.dragging_gain = magnitude && params.bands[i].get_kind().uses_gain();
+            state.begin_edit(&params.bands[i].freq);
+            if window_state.dragging_gain {
+                state.begin_edit(&params.bands[i].db_gain);
+            }
+        }
+        if ui.is_mouse_clicked(MouseButton::Right) {
+            window_state.menu_band = Some(i);
+            ui.open_popup(im_str!("band_node_menu"));
+        }
+    }
+    if let Some(i) = window_state.dragging_band {
+        let band = &params.bands[i];
+        if ui.is_mouse_down(MouseButton::Left) {
+            let [freq, db] = graph.from_screen(mouse);
+            state.set_parameter(&band.freq, freq);
+            if window_state.dragging_gain {
+                state.set_parameter(&band.db_gain, db);
+            }
+        } else {
+            state.end_edit(&band.freq);
+            if window_state.dragging_gain {
+                state.end_edit(&band.db_gain);
+            }
+            window_state.dragging_band = None;
+        }
+    }
+
+    let wheel = ui.io().mouse_wheel;
+    if let Some(i) = window_state.dragging_band.or(hovered) {
+        if wheel != 0.0 {
+            let q_value = &params.bands[i].q_value;
+            state.edit_parameter(q_value, q_value.get() * 1.1f32.powf(wheel));
+        }
+    }
+
+    ui.popup(im_str!("band_node_menu"), || {
+        if let Some(i) = window_state.menu_band {
+            let band = &params.bands[i];
+            for j in 0..8 {
+                if ui.radio_button_bool(
+                    &ImString::new(BandKind::from_u8(j as u8).to_string()),
+                    band.get_kind() as usize == j,
+                ) {
+                    state.edit_parameter(&band.kind, j as f32);
+                    ui.close_current_popup();
+                }
+            }
+        }
+    });
+
+    for (i, pos) in node_positions.iter().enumerate() {
+        let band = &params.bands[i];
+        if magnitude && band.get_dynamic() != DynamicMode::Off && band.get_kind().uses_gain() {
+            let live_pos = graph.to_screen(band.freq.get(), state.dynamic_gains[i].get());
+            draw_dynamic_node(ui, *pos, live_pos, ui.style_color(StyleColor::PlotLines));
+        }
+    }
+    for (i, pos) in node_positions.iter().enumerate() {
+        let highlight = hovered == Some(i) || window_state.dragging_band == Some(i);
+        let color = band_color(ui, &params.bands[i], StyleColor::PlotLinesHovered);
+        draw_band_node(ui, *pos, &format!("{}", i + 1), color, highlight);
+    }
+}
+
+/// Editor state that only lives while the window is open
+struct WindowState {
+    state: Arc<EditorState>,
+    dragging_band: Option<usize>,
+    /// Whether the band being dragged also moves in gain
+    dragging_gain: bool,
+    menu_band: Option<usize>,
+    analyzer_settings: AnalyzerSettings,
+    pre_analyzer: SpectrumAnalyzer,
+    post_analyzer: SpectrumAnalyzer,
+    show_pre: bool,
+    show_post: bool,
+    graph_view: GraphView,
+    /// Also draw each band's own curve
+    show_bands: bool,
+    file_path: ImString,
+    file_status: String,
+    export_sample_rate: u32,
+    response_resolution: u32,
+}
+
+impl WindowState {
+    fn new(state: Arc<EditorState>) -> WindowState {
+        let analyzer_settings = AnalyzerSettings::default();
+        WindowState {
+            state,
+            dragging_band: None,
+            dragging_gain: false,
+            menu_band: None,
+            analyzer_settings,
+            pre_analyzer: SpectrumAnalyzer::new(analyzer_settings.fft_size),
+            post_analyzer: SpectrumAnalyzer::new(analyzer_settings.fft_size),
+            show_pre: false,
+            show_post: true,
+            graph_view: GraphView::Magnitude,
+            show_bands: false,
+            file_path: ImString::with_capacity(256),
+            file_status: String::new(),
+            export_sample_rate: 48000,
+            response_resolution: 48,
+        }
+    }
 }
 
 pub struct EQPluginEditor {
     pub is_open: bool,
     pub state: Arc<EditorState>,
+    /// Latency changes are reported from here, on the UI thread
+    pub latency: Arc<LatencyReport>,
 }
 
 impl Editor for EQPluginEditor {
@@ -103,6 +642,10 @@ impl Editor for EQPluginEditor {
         (WINDOW_WIDTH as i32, WINDOW_HEIGHT as i32)
     }
 
+    fn idle(&mut self) {
+        self.latency.report();
+    }
+
     fn open(&mut self, parent: *mut ::std::ffi::c_void) -> bool {
         //::log::info!("self.running {}", self.running);
         if self.is_open {
@@ -125,15 +668,16 @@ impl Editor for EQPluginEditor {
         ImguiWindow::open_parented(
             &VstParent(parent),
             settings,
-            self.state.clone(),
-            |ctx: &mut Context, _state: &mut Arc<EditorState>| {
+            WindowState::new(self.state.clone()),
+            |ctx: &mut Context, _window_state: &mut WindowState| {
                 ctx.fonts().add_font(&[FontSource::TtfData {
                     data: include_bytes!("../FiraCode-Regular.ttf"),
                     size_pixels: 20.0,
                     config: None,
                 }]);
             },
-            |_run: &mut bool, ui: &Ui, state: &mut Arc<EditorState>| {
+            |_run: &mut bool, ui: &Ui, window_state: &mut WindowState| {
+                let state = window_state.state.clone();
                 let w = Window::new(im_str!("Example 1: Basic sliders"))
                     .size([WINDOW_WIDTH_F, WINDOW_HEIGHT_F], Condition::Appearing)
                     .position([0.0, 0.0], Condition::Appearing)
@@ -144,80 +688,232 @@ impl Editor for EQPluginEditor {
                     let graph_width = 1200.0;
                     let graph_height = 500.0;
 
-                    let db_px_step = graph_height / 40.0;
+                    let view = window_state.graph_view;
+                    let axis = view.axis();
+                    let db_px_step = axis.px_step(graph_height);
 
-                    let [cx, cy] = ui.cursor_screen_pos();
+                    let graph = GraphArea::new(ui, [graph_width, graph_height], db_px_step);
                     let [mx, my] = ui.io().mouse_pos;
-                    let [px, py] = [
-                        map_to_freq((mx - cx) / graph_width),
-                        -(my - cy - (graph_height * 0.5)) / 10.0,
-                    ];
-                    let [px, py] = [px.min(20000.0).max(10.0), py.min(24.0).max(-96.0)];
+                    let [px, py] = graph.from_screen([mx, my]);
+                    let px = px.clamp(10.0, 20000.0);
+                    let readout = if view == GraphView::Magnitude {
+                        format!("{}hz {}dB", px as i32, py.clamp(-96.0, 24.0))
+                    } else {
+                        format!("{}hz {:.1}{}", px as i32, py, axis.unit)
+                    };
 
                     ui.get_window_draw_list().add_text(
                         [mx - 40.0, my - 25.0],
                         ui.style_color(StyleColor::Text),
-                        &ImString::new(format!("{}hz {}dB", px as i32, py)),
+                        ImString::new(readout),
                     );
 
                     let sample_rate = state.sample_rate.get();
 
                     let params = &state.params;
+                    // Oversampled bands cramp less near nyquist, show what is heard
+                    let filter_rate = params.filter_rate(sample_rate);
 
-                    let mut graph_y_values = vec![0.0f32; graph_width as usize];
-
-                    let mut bandcoeffs_iir2 = Vec::new();
-                    let mut bandcoeffs_iir1 = Vec::new();
-
-                    for band in state.params.bands.iter() {
-                        //TODO reuse coeffs from DSP
-
-                        let f0 = band.freq.get();
-                        let gain = band.db_gain.get();
-                        let q_value = band.q_value.get();
-                        let fs = sample_rate;
-                        bandcoeffs_iir2.push(get_coefficients_iir2(
-                            band.get_kind(),
-                            f0,
-                            gain,
-                            q_value,
-                            fs,
-                        ));
-                        bandcoeffs_iir1.push(get_coefficients_iir1(band.get_kind(), f0, gain, fs));
-                    }
+                    //TODO reuse coeffs from DSP
+                    let band_coeffs = params
+                        .band_settings()
+                        .iter()
+                        .map(|settings| BandCoefficients::new(settings, filter_rate))
+                        .collect::<Vec<BandCoefficients>>();
 
-                    for (i, graph_y) in graph_y_values.iter_mut().enumerate() {
-                        let f_hz = map_to_freq((i as f32) / graph_width);
-                        for (i, band) in state.params.bands.iter().enumerate() {
-                            let iir2mode = band.mode.get().floor() == 1.0;
-                            if iir2mode {
-                                let y =
-                                    bandcoeffs_iir2[i].get_bode_sample(f_hz, sample_rate).norm();
-                                *graph_y += -(y.max(0.0).log(10.0) * 20.0) as f32;
-                            } else {
-                                let y =
-                                    bandcoeffs_iir1[i].get_bode_sample(f_hz, sample_rate).norm();
-                                *graph_y += -(y.max(0.0).log(10.0) * 20.0) as f32;
-                            }
+                    let band_y_values = band_coeffs
+                        .iter()
+                        .map(|coeffs| view.band_values(coeffs, graph_width as usize, filter_rate))
+                        .collect::<Vec<Vec<f32>>>();
 
-                            //let y = -new_band.get_bode_sample(z).arg().to_degrees() * 0.2;
-                            //*graph_y += y as f32;
+                    // Stereo bands make up the main curve, every other routing
+                    // target in use gets its own curve on top of that
+                    let curve_for = |routing: BandRouting| {
+                        let mut values = vec![0.0f32; graph_width as usize];
+                        for (band, band_values) in params.bands.iter().zip(&band_y_values) {
+                            let band_routing = band.get_routing();
+                            if !band.is_enabled() {
+                                continue;
+                            }
+                            if band_routing == routing || band_routing == BandRouting::Stereo {
+                                for (y, band_y) in values.iter_mut().zip(band_values) {
+                                    *y += band_y;
+                                }
+                            }
+                        }
+                        values
+                    };
+                    let graph_y_values = curve_for(BandRouting::Stereo);
+                    if window_state.show_bands {
+                        for (band, values) in params.bands.iter().zip(&band_y_values) {
+                            let mut color = band_color(ui, band, StyleColor::PlotLines);
+                            color[3] = 0.35;
+                            draw_eq_curve(
+                                ui,
+                                [graph_width, graph_height],
+                                db_px_step,
+                                1.0,
+                                graph_width as usize,
+                                color,
+                                |i| values[i],
+                            );
+                        }
+                    }
+                    for routing in ROUTING_TARGETS.iter() {
+                        if !params
+                            .bands
+                            .iter()
+                            .any(|band| band.is_enabled() && band.get_routing() == *routing)
+                        {
+                            continue;
                         }
+                        let values = curve_for(*routing);
+                        draw_eq_curve(
+                            ui,
+                            [graph_width, graph_height],
+                            db_px_step,
+                            2.0,
+                            graph_width as usize,
+                            routing_color(*routing).unwrap(),
+                            |i| values[i],
+                        );
+                    }
+
+                    let dt = ui.io().delta_time;
+                    let settings = window_state.analyzer_settings;
+                    if window_state.show_pre {
+                        let analyzer = &mut window_state.pre_analyzer;
+                        analyzer.update(&state.pre_samples, &settings, sample_rate, dt);
+                        let color = [0.4, 0.6, 1.0, 0.5];
+                        draw_analyzer(
+                            ui,
+                            analyzer,
+                            [graph_width, graph_height],
+                            color,
+                            sample_rate,
+                        );
+                    }
+                    if window_state.show_post {
+                        let analyzer = &mut window_state.post_analyzer;
+                        analyzer.update(&state.post_samples, &settings, sample_rate, dt);
+                        let color = [1.0, 0.6, 0.3, 0.5];
+                        draw_analyzer(
+                            ui,
+                            analyzer,
+                            [graph_width, graph_height],
+                            color,
+                            sample_rate,
+                        );
                     }
 
                     draw_eq_graph(
                         ui,
                         im_str!("test"),
                         [graph_width, graph_height],
-                        db_px_step,
+                        axis,
                         2.0,
                         graph_width as usize,
                         |i| graph_y_values[i],
                     );
-                    ui.columns(4, im_str!("cols"), false);
+                    let graph_hovered = ui.is_item_hovered();
+                    band_nodes(ui, window_state, graph, graph_hovered);
+                    popup_select(
+                        ui,
+                        &state,
+                        &params.phase_mode,
+                        0,
+                        |j| {
+                            ui.radio_button_bool(
+                                &ImString::new(PhaseMode::from_u8(j as u8).to_string()),
+                                params.get_phase_mode() as usize == j,
+                            )
+                        },
+                        2,
+                    );
+                    ui.same_line(0.0);
+                    popup_select(
+                        ui,
+                        &state,
+                        &params.oversampling,
+                        0,
+                        |j| {
+                            ui.radio_button_bool(
+                                &ImString::new(Oversampling::from_u8(j as u8).to_string()),
+                                params.get_oversampling() as usize == j,
+                            )
+                        },
+                        4,
+                    );
+                    ui.same_line(0.0);
+                    popup_select(
+                        ui,
+                        &state,
+                        &params.filter_design,
+                        0,
+                        |j| {
+                            ui.radio_button_bool(
+                                &ImString::new(FilterDesign::from_u8(j as u8).to_string()),
+                                params.get_filter_design() as usize == j,
+                            )
+                        },
+                        2,
+                    );
+                    ui.same_line(0.0);
+                    popup_select(
+                        ui,
+                        &state,
+                        &params.precision,
+                        0,
+                        |j| {
+                            ui.radio_button_bool(
+                                &ImString::new(Precision::from_u8(j as u8).to_string()),
+                                params.get_precision() as usize == j,
+                            )
+                        },
+                        2,
+                    );
+                    if params.get_phase_mode() == PhaseMode::Linear
+                        && params.get_precision() == Precision::Double
+                    {
+                        ui.same_line(0.0);
+                        ui.text("Linear phase is 32 bit");
+                    }
+                    ui.same_line(0.0);
+                    input_float(ui, &state, &params.preamp, 0);
+                    ui.same_line(0.0);
+                    toggle(ui, &state, &params.bypass, "Bypass", 0);
+                    ui.same_line(0.0);
+                    popup_select(
+                        ui,
+                        &state,
+                        &params.delta,
+                        0,
+                        |j| {
+                            ui.radio_button_bool(
+                                &ImString::new(DeltaMode::from_u8(j as u8).to_string()),
+                                params.get_delta() == DeltaMode::from_u8(j as u8),
+                            )
+                        },
+                        params.bands.len() + 2,
+                    );
+                    ui.same_line(0.0);
+                    toggle(ui, &state, &params.auto_gain, "Auto gain", 0);
+                    if params.is_auto_gain() {
+                        ui.same_line(0.0);
+                        ui.text(format!("{:+.1} dB", params.auto_gain_db(filter_rate)));
+                    }
+                    ui.same_line(0.0);
+                    input_float(ui, &state, &params.output_trim, 0);
+                    ui.same_line(0.0);
+                    analyzer_controls(ui, window_state);
+                    view_controls(ui, window_state);
+                    file_controls(ui, window_state);
+                    let columns = params.bands.len().min(MAX_BAND_COLUMNS);
+                    ui.columns(columns as i32, im_str!("cols"), false);
                     for (i, band) in params.bands.iter().enumerate() {
                         popup_select(
                             ui,
+                            &state,
                             &band.kind,
                             i,
                             |j| {
@@ -231,11 +927,15 @@ impl Editor for EQPluginEditor {
                             },
                             8,
                         );
-                        input_float(&ui, &band.freq, i);
-                        input_float(&ui, &band.db_gain, i);
-                        input_float(&ui, &band.q_value, i);
+                        toggle(ui, &state, &band.enabled, "On", i);
+                        ui.same_line(0.0);
+                        toggle(ui, &state, &band.solo, "Solo", i);
+                        input_float(&ui, &state, &band.freq, i);
+                        input_float(&ui, &state, &band.db_gain, i);
+                        input_float(&ui, &state, &band.q_value, i);
                         popup_select(
                             ui,
+                            &state,
                             &band.mode,
                             i,
                             |j| {
@@ -244,11 +944,56 @@ impl Editor for EQPluginEditor {
                                         "{}",
                                         BandMode::from_u8(j as u8).to_string()
                                     )),
-                                    band.get_mode() as usize == j,
+                                    band.mode.get() as usize == j,
                                 )
                             },
                             2,
                         );
+                        popup_select(
+                            ui,
+                            &state,
+                            &band.slope,
+                            i,
+                            |j| {
+                                ui.radio_button_bool(
+                                    &ImString::new(band.slope.display(j as f32)),
+                                    band.slope.get() as usize == j,
+                                )
+                            },
+                            7,
+                        );
+                        popup_select(
+                            ui,
+                            &state,
+                            &band.routing,
+                            i,
+                            |j| {
+                                ui.radio_button_bool(
+                                    &ImString::new(BandRouting::from_u8(j as u8).to_string()),
+                                    band.get_routing() as usize == j,
+                                )
+                            },
+                            5,
+                        );
+                        popup_select(
+                            ui,
+                            &state,
+                            &band.dynamic,
+                            i,
+                            |j| {
+                                ui.radio_button_bool(
+                                    &ImString::new(DynamicMode::from_u8(j as u8).to_string()),
+                                    band.get_dynamic() as usize == j,
+                                )
+                            },
+                            3,
+                        );
+                        if band.get_dynamic() != DynamicMode::Off {
+                            input_float(&ui, &state, &band.threshold, i);
+                            input_float(&ui, &state, &band.ratio, i);
+                            input_float(&ui, &state, &band.attack, i);
+                            input_float(&ui, &state, &band.release, i);
+                        }
                         ui.next_column();
                     }
                 });
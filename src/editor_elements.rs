@@ -1,6 +1,101 @@
 use imgui::*;
 
-use crate::units::reverse_map_to_freq;
+use crate::spectrum::SPECTRUM_MIN_DB;
+use crate::units::{map_to_freq, reverse_map_to_freq};
+
+/// Vertical axis of the eq graph, centered on 0
+pub struct ValueAxis {
+    /// Value at the top of the graph, minus this at the bottom
+    pub range: f32,
+    /// Values that get a labelled grid line
+    pub lines: &'static [f32],
+    pub unit: &'static str,
+}
+
+impl ValueAxis {
+    /// Pixels per unit on a graph `graph_height` tall
+    pub fn px_step(&self, graph_height: f32) -> f32 {
+        graph_height * 0.5 / self.range
+    }
+}
+
+pub const MAGNITUDE_AXIS: ValueAxis = ValueAxis {
+    range: 20.0,
+    lines: &[-12.0, -6.0, 0.0, 6.0, 12.0],
+    unit: "db",
+};
+
+pub const PHASE_AXIS: ValueAxis = ValueAxis {
+    range: 720.0,
+    lines: &[-540.0, -360.0, -180.0, 0.0, 180.0, 360.0, 540.0],
+    unit: "°",
+};
+
+pub const GROUP_DELAY_AXIS: ValueAxis = ValueAxis {
+    range: 20.0,
+    lines: &[-15.0, -10.0, -5.0, 0.0, 5.0, 10.0, 15.0],
+    unit: "ms",
+};
+
+/// Maps between screen positions and hz/dB on the eq graph
+#[derive(Clone, Copy)]
+pub struct GraphArea {
+    pub pos: [f32; 2],
+    pub size: [f32; 2],
+    pub db_px_step: f32,
+}
+
+impl GraphArea {
+    /// The graph starting at the current cursor position
+    pub fn new(ui: &Ui, size: [f32; 2], db_px_step: f32) -> GraphArea {
+        GraphArea {
+            pos: ui.cursor_screen_pos(),
+            size,
+            db_px_step,
+        }
+    }
+
+    fn center_y(&self) -> f32 {
+        self.pos[1] + 4.0 + self.size[1] * 0.5 //TODO same offset as draw_eq_graph
+    }
+
+    pub fn to_screen(&self, freq: f32, db: f32) -> [f32; 2] {
+        [
+            self.pos[0] + reverse_map_to_freq(freq) * self.size[0],
+            self.center_y() - db * self.db_px_step,
+        ]
+    }
+
+    /// Returns [hz, dB] at a screen position
+    pub fn from_screen(&self, pos: [f32; 2]) -> [f32; 2] {
+        [
+            map_to_freq((pos[0] - self.pos[0]) / self.size[0]),
+            (self.center_y() - pos[1]) / self.db_px_step,
+        ]
+    }
+}
+
+pub fn draw_band_node(ui: &Ui, pos: [f32; 2], label: &str, mut color: [f32; 4], highlight: bool) {
+    let draw_list = ui.get_window_draw_list();
+    color[3] = if highlight { 1.0 } else { 0.6 };
+    draw_list.add_circle(pos, 9.0, color).filled(true).build();
+    draw_list.add_text([pos[0] - 4.0, pos[1] - 10.0], [0.0, 0.0, 0.0, 1.0], label);
+}
+
+/// Shows where a dynamic band's gain has been moved to, as a faint node joined to
+/// the band's own node
+pub fn draw_dynamic_node(ui: &Ui, pos: [f32; 2], live_pos: [f32; 2], mut color: [f32; 4]) {
+    let draw_list = ui.get_window_draw_list();
+    color[3] = 0.4;
+    draw_list
+        .add_line(pos, live_pos, color)
+        .thickness(2.0)
+        .build();
+    draw_list
+        .add_circle(live_pos, 6.0, color)
+        .filled(true)
+        .build();
+}
 
 fn draw_hz_line(ui: &Ui, freq: f32, graph_width: f32, graph_height: f32, draw_text: bool) {
     let [cx, cy] = ui.cursor_screen_pos();
@@ -19,9 +114,16 @@ fn draw_hz_line(ui: &Ui, freq: f32, graph_width: f32, graph_height: f32, draw_te
     }
 }
 
-fn draw_db_line(ui: &Ui, db: f32, graph_width: f32, graph_height: f32, db_px_step: f32) {
+fn draw_value_line(
+    ui: &Ui,
+    value: f32,
+    unit: &str,
+    graph_width: f32,
+    graph_height: f32,
+    px_step: f32,
+) {
     let [cx, cy] = ui.cursor_screen_pos();
-    let db_height = cy + (-db) * db_px_step - graph_height / 2.0;
+    let db_height = cy + (-value) * px_step - graph_height / 2.0;
     ui.get_window_draw_list()
         .add_line(
             [cx, db_height],
@@ -33,30 +135,54 @@ fn draw_db_line(ui: &Ui, db: f32, graph_width: f32, graph_height: f32, db_px_ste
     ui.get_window_draw_list().add_text(
         [cx, db_height],
         ui.style_color(StyleColor::Text),
-        &ImString::new(format!("{}db", db)),
+        ImString::new(format!("{}{}", value, unit)),
     );
 }
 
-pub fn draw_eq_graph<F: Fn(usize) -> f32>(
+/// Draws an analyzer spectrum, 0 dBFS at the top of the graph down to
+/// `SPECTRUM_MIN_DB` at the bottom. Does not move the cursor, so it can be drawn
+/// behind the eq graph.
+pub fn draw_spectrum<F: Fn(usize) -> f32>(
     ui: &Ui,
-    id: &ImStr,
     size: [f32; 2],
-    db_px_step: f32,
+    color: [f32; 4],
     thinkness: f32,
     length: usize,
     value_fn: F,
 ) {
     let [cx, mut cy] = ui.cursor_screen_pos();
     cy += 4.0; //TODO off by a bit
-    ui.invisible_button(id, size);
+    let scale = size[0] / length as f32;
+    let db_to_y = |db: f32| cy + (db / SPECTRUM_MIN_DB).clamp(0.0, 1.0) * size[1];
+    let draw_list = ui.get_window_draw_list();
+    let mut last = db_to_y(value_fn(0));
+    for i in 1..length {
+        let next = db_to_y(value_fn(i));
+        let fi = i as f32;
+        draw_list
+            .add_line(
+                [cx + (fi - 1.0) * scale, last],
+                [cx + fi * scale, next],
+                color,
+            )
+            .thickness(thinkness)
+            .build();
+        last = next;
+    }
+}
 
-    let mut color = if ui.is_item_hovered() {
-        ui.style_color(StyleColor::PlotLinesHovered)
-    } else {
-        ui.style_color(StyleColor::PlotLines)
-    };
+#[allow(clippy::too_many_arguments)]
+fn draw_curve_at<F: Fn(usize) -> f32>(
+    ui: &Ui,
+    [cx, cy]: [f32; 2],
+    size: [f32; 2],
+    db_px_step: f32,
+    thinkness: f32,
+    length: usize,
+    color: [f32; 4],
+    value_fn: F,
+) {
     let scale = (size[0] as f32 / length as f32) as f32;
-    color[3] = (color[3] * 0.9).min(1.0).max(0.0);
     let v_center = size[1] / 2.0;
     let mut last = value_fn(0) * db_px_step;
     {
@@ -76,6 +202,64 @@ pub fn draw_eq_graph<F: Fn(usize) -> f32>(
             last = next;
         }
     }
+}
+
+/// Draws an extra response curve in `color` on the eq graph axes. Like
+/// `draw_spectrum` it does not move the cursor, call it before `draw_eq_graph`.
+pub fn draw_eq_curve<F: Fn(usize) -> f32>(
+    ui: &Ui,
+    size: [f32; 2],
+    db_px_step: f32,
+    thinkness: f32,
+    length: usize,
+    color: [f32; 4],
+    value_fn: F,
+) {
+    let [cx, mut cy] = ui.cursor_screen_pos();
+    cy += 4.0; //TODO off by a bit
+    draw_curve_at(
+        ui,
+        [cx, cy],
+        size,
+        db_px_step,
+        thinkness,
+        length,
+        color,
+        value_fn,
+    );
+}
+
+/// Draws the main response curve with a grid for `axis`
+pub fn draw_eq_graph<F: Fn(usize) -> f32>(
+    ui: &Ui,
+    id: &ImStr,
+    size: [f32; 2],
+    axis: &ValueAxis,
+    thinkness: f32,
+    length: usize,
+    value_fn: F,
+) {
+    let db_px_step = axis.px_step(size[1]);
+    let [cx, mut cy] = ui.cursor_screen_pos();
+    cy += 4.0; //TODO off by a bit
+    ui.invisible_button(id, size);
+
+    let mut color = if ui.is_item_hovered() {
+        ui.style_color(StyleColor::PlotLinesHovered)
+    } else {
+        ui.style_color(StyleColor::PlotLines)
+    };
+    color[3] = (color[3] * 0.9).min(1.0).max(0.0);
+    draw_curve_at(
+        ui,
+        [cx, cy],
+        size,
+        db_px_step,
+        thinkness,
+        length,
+        color,
+        value_fn,
+    );
 
     for n in [
         20, 30, 50, 100, 200, 300, 500, 1000, 2000, 3000, 5000, 10000, 20000,
@@ -93,7 +277,7 @@ pub fn draw_eq_graph<F: Fn(usize) -> f32>(
         draw_hz_line(ui, *n as f32, size[0], size[1], false);
     }
 
-    for db in [-12.0, -6.0, 0.0, 6.0, 12.0].iter() {
-        draw_db_line(ui, *db, size[0], size[1], db_px_step);
+    for value in axis.lines.iter() {
+        draw_value_line(ui, *value, axis.unit, size[0], size[1], db_px_step);
     }
 }
@@ -1,64 +1,48 @@
 use super::parameter::Parameter;
 
-use core::fmt;
+pub use eq_core::{
+    BandKind, BandMode, BandRouting, BandSettings, DeltaMode, DynamicMode, FilterDesign,
+    Oversampling, PhaseMode, Precision,
+};
+use eq_core::{
+    loudness_change_db, BandSmoothing, Smoothing, SmoothingStyle, MAX_AUTO_GAIN_DB,
+};
 
 use std::{f32::consts::LN_2, ops::Index, sync::Arc};
 
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum BandKind {
-    Bell,
-    LowPass,
-    HighPass,
-    LowShelf,
-    HighShelf,
-    Notch,
-    BandPass,
-    AllPass,
-}
+/// Number of bands, picked at build time with the `bands-8`, `bands-16` or
+/// `bands-24` cargo features. The largest enabled feature wins.
+#[cfg(not(any(feature = "bands-8", feature = "bands-16", feature = "bands-24")))]
+pub const FILTER_COUNT: usize = 4;
+#[cfg(all(
+    feature = "bands-8",
+    not(any(feature = "bands-16", feature = "bands-24"))
+))]
+pub const FILTER_COUNT: usize = 8;
+#[cfg(all(feature = "bands-16", not(feature = "bands-24")))]
+pub const FILTER_COUNT: usize = 16;
+#[cfg(feature = "bands-24")]
+pub const FILTER_COUNT: usize = 24;
 
-impl BandKind {
-    pub fn from_u8(value: u8) -> BandKind {
-        match value {
-            0 => BandKind::Bell,
-            1 => BandKind::LowPass,
-            2 => BandKind::HighPass,
-            3 => BandKind::LowShelf,
-            4 => BandKind::HighShelf,
-            5 => BandKind::Notch,
-            6 => BandKind::BandPass,
-            7 => BandKind::AllPass,
-            _ => BandKind::LowPass,
-        }
-    }
-}
+/// kind, freq, db_gain, q_value, mode, routing, dynamic, threshold, ratio,
+/// attack, release, enabled, solo and slope, the slot of each in a band
+pub const PARAMETERS_PER_BAND: usize = 14;
 
-impl fmt::Display for BandKind {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum BandMode {
-    FirstOrder,
-    SecondOrder,
-}
+/// VST2 hosts address parameters by index. The band parameters the plugin started
+/// with, kind to mode, keep their band by band indices, the slots added since
+/// come after all of them, also band by band, then the globals.
+pub const ORIGINAL_PARAMETERS_PER_BAND: usize = 5;
 
-impl BandMode {
-    pub fn from_u8(value: u8) -> BandMode {
-        match value {
-            0 => BandMode::FirstOrder,
-            1 => BandMode::SecondOrder,
-            _ => BandMode::SecondOrder,
-        }
-    }
-}
+/// Parameters that apply to the whole plugin, stored after the bands:
+/// phase mode, preamp, bypass, delta, auto gain, output trim, oversampling, filter
+/// design and precision
+pub const GLOBAL_PARAMETERS: usize = 9;
 
-impl fmt::Display for BandMode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
+/// Stable parameter ids, for formats that do not address parameters by index.
+/// Band parameters are `band << 8 | slot`, globals
+/// count up from here. New parameters go last in their group, so ids never move.
+#[cfg_attr(not(feature = "clap"), allow(dead_code))]
+pub const GLOBAL_ID_BASE: u32 = 1 << 16;
 
 pub struct BandParameters {
     pub kind: Parameter,
@@ -66,6 +50,17 @@ pub struct BandParameters {
     pub db_gain: Parameter,
     pub q_value: Parameter,
     pub mode: Parameter,
+    pub routing: Parameter,
+    pub dynamic: Parameter,
+    pub threshold: Parameter,
+    pub ratio: Parameter,
+    pub attack: Parameter,
+    pub release: Parameter,
+    pub enabled: Parameter,
+    pub solo: Parameter,
+    /// The cascaded slopes of `BandMode`, 0 leaves the slope to `mode`. Kept apart
+    /// from `mode` so stored values of `mode` keep meaning first or second order.
+    pub slope: Parameter,
 }
 
 impl BandParameters {
@@ -73,8 +68,106 @@ impl BandParameters {
         return BandKind::from_u8(self.kind.get() as u8);
     }
 
-    pub fn get_mode(&self) -> f32 {
-        self.mode.get().floor()
+    pub fn get_mode(&self) -> BandMode {
+        match self.slope.get() as u8 {
+            0 => BandMode::from_u8(self.mode.get() as u8),
+            slope => BandMode::from_u8(slope + 1),
+        }
+    }
+
+    pub fn get_routing(&self) -> BandRouting {
+        BandRouting::from_u8(self.routing.get() as u8)
+    }
+
+    pub fn get_dynamic(&self) -> DynamicMode {
+        DynamicMode::from_u8(self.dynamic.get() as u8)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get() >= 0.5
+    }
+
+    pub fn is_solo(&self) -> bool {
+        self.solo.get() >= 0.5
+    }
+
+    pub fn settings(&self) -> BandSettings {
+        BandSettings {
+            kind: self.get_kind(),
+            freq: self.freq.get(),
+            db_gain: self.db_gain.get(),
+            q_value: self.q_value.get(),
+            mode: self.get_mode(),
+            routing: self.get_routing(),
+            dynamic: self.get_dynamic(),
+            threshold: self.threshold.get(),
+            ratio: self.ratio.get(),
+            attack: self.attack.get(),
+            release: self.release.get(),
+            enabled: self.is_enabled(),
+            solo: self.is_solo(),
+            // Plugin wide, filled in by `EQEffectParameters::settings`
+            design: FilterDesign::Bilinear,
+        }
+    }
+
+    /// Set every parameter of the band to `settings` through `set`, which gets
+    /// the plain value
+    pub fn apply_settings<F: Fn(&Parameter, f32)>(&self, settings: &BandSettings, set: F) {
+        let values = [
+            (&self.kind, settings.kind as usize as f32),
+            (&self.freq, settings.freq),
+            (&self.db_gain, settings.db_gain),
+            (&self.q_value, settings.q_value),
+            (&self.mode, (settings.mode as usize).min(1) as f32),
+            (&self.routing, settings.routing as usize as f32),
+            (&self.dynamic, settings.dynamic as usize as f32),
+            (&self.threshold, settings.threshold),
+            (&self.ratio, settings.ratio),
+            (&self.attack, settings.attack),
+            (&self.release, settings.release),
+            (&self.enabled, settings.enabled as usize as f32),
+            (&self.solo, settings.solo as usize as f32),
+            (
+                &self.slope,
+                (settings.mode as usize).saturating_sub(1) as f32,
+            ),
+        ];
+        for (parameter, value) in values.iter() {
+            set(parameter, *value);
+        }
+    }
+
+    /// The parameter in `slot`, see `PARAMETERS_PER_BAND`
+    pub fn slot(&self, slot: usize) -> &Parameter {
+        match slot {
+            0 => &self.kind,
+            1 => &self.freq,
+            2 => &self.db_gain,
+            3 => &self.q_value,
+            4 => &self.mode,
+            5 => &self.routing,
+            6 => &self.dynamic,
+            7 => &self.threshold,
+            8 => &self.ratio,
+            9 => &self.attack,
+            10 => &self.release,
+            11 => &self.enabled,
+            12 => &self.solo,
+            _ => &self.slope,
+        }
+    }
+
+    pub fn smoothing(&self) -> BandSmoothing {
+        let smoothing = |parameter: &Parameter| Smoothing {
+            style: parameter.smoothing_style(),
+            ms: parameter.get_smoothing_ms(),
+        };
+        BandSmoothing {
+            freq: smoothing(&self.freq),
+            db_gain: smoothing(&self.db_gain),
+            q_value: smoothing(&self.q_value),
+        }
     }
 
     pub fn dsp_update(&self) -> bool {
@@ -88,6 +181,24 @@ impl BandParameters {
             true
         } else if self.mode.dsp_update() {
             true
+        } else if self.routing.dsp_update() {
+            true
+        } else if self.dynamic.dsp_update() {
+            true
+        } else if self.threshold.dsp_update() {
+            true
+        } else if self.ratio.dsp_update() {
+            true
+        } else if self.attack.dsp_update() {
+            true
+        } else if self.release.dsp_update() {
+            true
+        } else if self.enabled.dsp_update() {
+            true
+        } else if self.solo.dsp_update() {
+            true
+        } else if self.slope.dsp_update() {
+            true
         } else {
             false
         }
@@ -96,40 +207,168 @@ impl BandParameters {
 
 pub struct EQEffectParameters {
     pub bands: Vec<Arc<BandParameters>>,
+    pub phase_mode: Parameter,
+    pub preamp: Parameter,
+    pub bypass: Parameter,
+    pub delta: Parameter,
+    pub auto_gain: Parameter,
+    pub output_trim: Parameter,
+    pub oversampling: Parameter,
+    pub filter_design: Parameter,
+    pub precision: Parameter,
 }
 
 impl Index<usize> for EQEffectParameters {
     type Output = Parameter;
     fn index(&self, i: usize) -> &Self::Output {
-        match i {
-            0 => &self.bands[0].kind,
-            1 => &self.bands[0].freq,
-            2 => &self.bands[0].db_gain,
-            3 => &self.bands[0].q_value,
-            4 => &self.bands[0].mode,
-            5 => &self.bands[1].kind,
-            6 => &self.bands[1].freq,
-            7 => &self.bands[1].db_gain,
-            8 => &self.bands[1].q_value,
-            9 => &self.bands[1].mode,
-            10 => &self.bands[2].kind,
-            11 => &self.bands[2].freq,
-            12 => &self.bands[2].db_gain,
-            13 => &self.bands[2].q_value,
-            14 => &self.bands[2].mode,
-            15 => &self.bands[3].kind,
-            16 => &self.bands[3].freq,
-            17 => &self.bands[3].db_gain,
-            18 => &self.bands[3].q_value,
-            19 => &self.bands[3].mode,
-            _ => &self.bands[3].kind,
+        if i >= self.len() {
+            return &self.bands[self.bands.len() - 1].kind;
+        }
+        if let Some((band, slot)) = self.band_slot(i) {
+            return self.bands[band].slot(slot);
+        }
+        match i - self.globals_start() {
+            0 => &self.phase_mode,
+            1 => &self.preamp,
+            2 => &self.bypass,
+            3 => &self.delta,
+            4 => &self.auto_gain,
+            5 => &self.output_trim,
+            6 => &self.oversampling,
+            7 => &self.filter_design,
+            _ => &self.precision,
         }
     }
 }
 
 impl EQEffectParameters {
     pub fn len(&self) -> usize {
-        19
+        self.globals_start() + GLOBAL_PARAMETERS
+    }
+
+    /// Host index of the first global parameter
+    pub fn globals_start(&self) -> usize {
+        self.bands.len() * PARAMETERS_PER_BAND
+    }
+
+    /// Band and slot of the band parameter at host index `index`
+    pub fn band_slot(&self, index: usize) -> Option<(usize, usize)> {
+        let original = self.bands.len() * ORIGINAL_PARAMETERS_PER_BAND;
+        let added = PARAMETERS_PER_BAND - ORIGINAL_PARAMETERS_PER_BAND;
+        if index < original {
+            Some((
+                index / ORIGINAL_PARAMETERS_PER_BAND,
+                index % ORIGINAL_PARAMETERS_PER_BAND,
+            ))
+        } else if index < self.globals_start() {
+            let index = index - original;
+            Some((index / added, ORIGINAL_PARAMETERS_PER_BAND + index % added))
+        } else {
+            None
+        }
+    }
+
+    /// Host index of `slot` of `band`, the inverse of `band_slot`
+    pub fn band_index(&self, band: usize, slot: usize) -> usize {
+        if slot < ORIGINAL_PARAMETERS_PER_BAND {
+            band * ORIGINAL_PARAMETERS_PER_BAND + slot
+        } else {
+            let added = PARAMETERS_PER_BAND - ORIGINAL_PARAMETERS_PER_BAND;
+            self.bands.len() * ORIGINAL_PARAMETERS_PER_BAND + band * added + slot
+                - ORIGINAL_PARAMETERS_PER_BAND
+        }
+    }
+
+    pub fn get_phase_mode(&self) -> PhaseMode {
+        PhaseMode::from_u8(self.phase_mode.get() as u8)
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.bypass.get() >= 0.5
+    }
+
+    pub fn get_delta(&self) -> DeltaMode {
+        DeltaMode::from_u8(self.delta.get() as u8)
+    }
+
+    pub fn get_oversampling(&self) -> Oversampling {
+        Oversampling::from_u8(self.oversampling.get() as u8)
+    }
+
+    /// Rate the band filters run at for `sample_rate`, with the oversampling of
+    /// minimum phase mode
+    pub fn filter_rate(&self, sample_rate: f32) -> f32 {
+        match self.get_phase_mode() {
+            PhaseMode::Minimum => sample_rate * self.get_oversampling().factor() as f32,
+            PhaseMode::Linear => sample_rate,
+        }
+    }
+
+    pub fn is_auto_gain(&self) -> bool {
+        self.auto_gain.get() >= 0.5
+    }
+
+    /// Auto gain compensation in dB for bands running at `filter_rate`, the same
+    /// estimate the engine compensates with. 0 while auto gain is off.
+    pub fn auto_gain_db(&self, filter_rate: f32) -> f32 {
+        if self.is_auto_gain() {
+            (-loudness_change_db(&self.band_settings(), filter_rate))
+                .clamp(-MAX_AUTO_GAIN_DB, MAX_AUTO_GAIN_DB)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn get_filter_design(&self) -> FilterDesign {
+        FilterDesign::from_u8(self.filter_design.get() as u8)
+    }
+
+    pub fn get_precision(&self) -> Precision {
+        Precision::from_u8(self.precision.get() as u8)
+    }
+
+    /// Settings of band `i`, with the plugin wide filter design
+    pub fn settings(&self, i: usize) -> BandSettings {
+        BandSettings {
+            design: self.get_filter_design(),
+            ..self.bands[i].settings()
+        }
+    }
+
+    pub fn band_settings(&self) -> Vec<BandSettings> {
+        (0..self.bands.len()).map(|i| self.settings(i)).collect()
+    }
+
+    /// Host index of a parameter that belongs to this set
+    pub fn index_of(&self, parameter: &Parameter) -> Option<usize> {
+        (0..self.len()).find(|i| std::ptr::eq(&self[*i], parameter))
+    }
+
+    /// Stable id of the parameter at host index `index`, see `GLOBAL_ID_BASE`
+    #[cfg_attr(not(feature = "clap"), allow(dead_code))]
+    pub fn id(&self, index: usize) -> u32 {
+        match self.band_slot(index) {
+            Some((band, slot)) => ((band << 8) | slot) as u32,
+            None => GLOBAL_ID_BASE + (index - self.globals_start()) as u32,
+        }
+    }
+
+    #[cfg_attr(not(feature = "clap"), allow(dead_code))]
+    pub fn index_of_id(&self, id: u32) -> Option<usize> {
+        let index = if id >= GLOBAL_ID_BASE {
+            self.globals_start() + (id - GLOBAL_ID_BASE) as usize
+        } else {
+            let (band, slot) = ((id >> 8) as usize, (id & 0xff) as usize);
+            if band >= self.bands.len() || slot >= PARAMETERS_PER_BAND {
+                return None;
+            }
+            self.band_index(band, slot)
+        };
+        if index < self.len() {
+            Some(index)
+        } else {
+            None
+        }
     }
 }
 
@@ -143,7 +382,8 @@ fn new_band_pram_set(n: usize) -> BandParameters {
             |x| BandKind::from_u8(x as u8).to_string(),
             |x| x,
             |x| x,
-        ),
+        )
+        .stepped(),
         freq: Parameter::new(
             &format!("Band {} hz", n),
             1000.0,
@@ -152,7 +392,8 @@ fn new_band_pram_set(n: usize) -> BandParameters {
             |x| format!("hz {:.2}", x),
             |x| x.powf(2.0),
             |x| x.powf(0.5),
-        ),
+        )
+        .with_smoothing(SmoothingStyle::OnePole, 20.0),
         db_gain: Parameter::new(
             &format!("Band {} dB", n),
             0.0,
@@ -161,7 +402,8 @@ fn new_band_pram_set(n: usize) -> BandParameters {
             |x| format!("dB {:.2}", x),
             |x| x,
             |x| x,
-        ),
+        )
+        .with_smoothing(SmoothingStyle::Linear, 20.0),
         q_value: Parameter::new(
             &format!("Band {} Q Value", n),
             LN_2,
@@ -170,7 +412,8 @@ fn new_band_pram_set(n: usize) -> BandParameters {
             |x| format!("Q {:.2}", x),
             |x| x,
             |x| x,
-        ),
+        )
+        .with_smoothing(SmoothingStyle::Linear, 20.0),
         mode: Parameter::new(
             &format!("Band {} mode", n),
             1.0,
@@ -179,16 +422,234 @@ fn new_band_pram_set(n: usize) -> BandParameters {
             |x| BandMode::from_u8(x as u8).to_string(),
             |x| x,
             |x| x,
+        )
+        .stepped(),
+        routing: Parameter::new(
+            &format!("Band {} routing", n),
+            0.0,
+            0.0,
+            4.0,
+            |x| BandRouting::from_u8(x as u8).to_string(),
+            |x| x,
+            |x| x,
+        )
+        .stepped(),
+        dynamic: Parameter::new(
+            &format!("Band {} dynamic", n),
+            0.0,
+            0.0,
+            2.0,
+            |x| DynamicMode::from_u8(x as u8).to_string(),
+            |x| x,
+            |x| x,
+        )
+        .stepped(),
+        threshold: Parameter::new(
+            &format!("Band {} threshold", n),
+            -24.0,
+            -60.0,
+            0.0,
+            |x| format!("thr {:.1} dB", x),
+            |x| x,
+            |x| x,
         ),
+        ratio: Parameter::new(
+            &format!("Band {} ratio", n),
+            2.0,
+            1.0,
+            20.0,
+            |x| format!("ratio {:.1}:1", x),
+            |x| x.powf(2.0),
+            |x| x.powf(0.5),
+        ),
+        attack: Parameter::new(
+            &format!("Band {} attack", n),
+            10.0,
+            0.1,
+            200.0,
+            |x| format!("att {:.1} ms", x),
+            |x| x.powf(3.0),
+            |x| x.powf(1.0 / 3.0),
+        ),
+        release: Parameter::new(
+            &format!("Band {} release", n),
+            100.0,
+            1.0,
+            2000.0,
+            |x| format!("rel {:.0} ms", x),
+            |x| x.powf(3.0),
+            |x| x.powf(1.0 / 3.0),
+        ),
+        enabled: Parameter::new(
+            &format!("Band {} enabled", n),
+            1.0,
+            0.0,
+            1.0,
+            |x| if x >= 0.5 { "On" } else { "Off" }.to_string(),
+            |x| x,
+            |x| x,
+        )
+        .stepped(),
+        solo: Parameter::new(
+            &format!("Band {} solo", n),
+            0.0,
+            0.0,
+            1.0,
+            |x| if x >= 0.5 { "Solo" } else { "-" }.to_string(),
+            |x| x,
+            |x| x,
+        )
+        .stepped(),
+        slope: Parameter::new(
+            &format!("Band {} slope", n),
+            0.0,
+            0.0,
+            6.0,
+            |x| match x as u8 {
+                0 => "-".to_string(),
+                slope => BandMode::from_u8(slope + 1).to_string(),
+            },
+            |x| x,
+            |x| x,
+        )
+        .stepped(),
     }
 }
 
 impl Default for EQEffectParameters {
     fn default() -> EQEffectParameters {
         EQEffectParameters {
-            bands: (0..4)
-                .map(|_| Arc::new(new_band_pram_set(1)))
+            bands: (0..FILTER_COUNT)
+                .map(|i| Arc::new(new_band_pram_set(i + 1)))
                 .collect::<Vec<Arc<BandParameters>>>(),
+            phase_mode: Parameter::new(
+                "Phase mode",
+                0.0,
+                0.0,
+                1.0,
+                |x| PhaseMode::from_u8(x as u8).to_string(),
+                |x| x,
+                |x| x,
+            )
+            .stepped(),
+            preamp: Parameter::new(
+                "Preamp",
+                0.0,
+                -24.0,
+                24.0,
+                |x| format!("preamp {:.1} dB", x),
+                |x| x,
+                |x| x,
+            ),
+            bypass: Parameter::new(
+                "Bypass",
+                0.0,
+                0.0,
+                1.0,
+                |x| if x >= 0.5 { "Bypassed" } else { "Active" }.to_string(),
+                |x| x,
+                |x| x,
+            )
+            .stepped(),
+            // Off, all bands, then each band on its own
+            delta: Parameter::new(
+                "Delta",
+                0.0,
+                0.0,
+                (FILTER_COUNT + 1) as f32,
+                |x| DeltaMode::from_u8(x as u8).to_string(),
+                |x| x,
+                |x| x,
+            )
+            .stepped(),
+            auto_gain: Parameter::new(
+                "Auto gain",
+                0.0,
+                0.0,
+                1.0,
+                |x| if x >= 0.5 { "On" } else { "Off" }.to_string(),
+                |x| x,
+                |x| x,
+            )
+            .stepped(),
+            output_trim: Parameter::new(
+                "Output trim",
+                0.0,
+                -24.0,
+                24.0,
+                |x| format!("trim {:.1} dB", x),
+                |x| x,
+                |x| x,
+            ),
+            oversampling: Parameter::new(
+                "Oversampling",
+                0.0,
+                0.0,
+                3.0,
+                |x| Oversampling::from_u8(x as u8).to_string(),
+                |x| x,
+                |x| x,
+            )
+            .stepped(),
+            filter_design: Parameter::new(
+                "Filter design",
+                0.0,
+                0.0,
+                1.0,
+                |x| FilterDesign::from_u8(x as u8).to_string(),
+                |x| x,
+                |x| x,
+            )
+            .stepped(),
+            precision: Parameter::new(
+                "Precision",
+                0.0,
+                0.0,
+                1.0,
+                |x| Precision::from_u8(x as u8).to_string(),
+                |x| x,
+                |x| x,
+            )
+            .stepped(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn original_band_parameters_keep_their_indices() {
+        let params = EQEffectParameters::default();
+        let original = ["Type", "hz", "dB", "Q Value", "mode"];
+        for band in 0..params.bands.len() {
+            for (slot, name) in original.iter().enumerate() {
+                let index = band * ORIGINAL_PARAMETERS_PER_BAND + slot;
+                assert_eq!(
+                    params[index].get_name(),
+                    format!("Band {} {}", band + 1, name)
+                );
+            }
+        }
+        assert_eq!(params[6].get_name(), "Band 2 hz");
+    }
+
+    #[test]
+    fn band_slots_and_ids_round_trip() {
+        let params = EQEffectParameters::default();
+        for index in 0..params.len() {
+            if let Some((band, slot)) = params.band_slot(index) {
+                assert_eq!(params.band_index(band, slot), index);
+                assert!(std::ptr::eq(&params[index], params.bands[band].slot(slot)));
+            }
+            assert_eq!(params.index_of_id(params.id(index)), Some(index));
+            assert_eq!(params.index_of(&params[index]), Some(index));
         }
+        assert_eq!(params.band_slot(params.globals_start()), None);
+        assert!(std::ptr::eq(
+            &params[params.globals_start()],
+            &params.phase_mode
+        ));
     }
 }
@@ -0,0 +1,337 @@
+use crate::eq_effect_parameters::{
+    BandRouting, DynamicMode, EQEffectParameters, Oversampling, PhaseMode,
+};
+use crate::units::Units;
+use eq_core::{BandCoefficients, Biquad};
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ExportError {
+    /// Band number (from 1) and the setting the target can't describe
+    UnsupportedBand(usize, String),
+    /// A plugin wide setting the target can't describe
+    UnsupportedSetting(String),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::UnsupportedBand(band, message) => write!(f, "band {}: {}", band, message),
+            ExportError::UnsupportedSetting(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Biquads of one band and the channels (0 left, 1 right) they filter
+struct BandBiquads {
+    band: usize,
+    channels: &'static [usize],
+    biquads: Vec<Biquad>,
+}
+
+impl EQEffectParameters {
+    /// The biquads the minimum phase bands run at `sample_rate`. Flat and disabled
+    /// bands are left out, dynamic and mid/side bands are an error, and so are
+    /// bypass, linear phase and oversampling, which the biquads would not match.
+    fn band_biquads(&self, sample_rate: f32) -> Result<Vec<BandBiquads>, ExportError> {
+        let unsupported = |message: &str| Err(ExportError::UnsupportedSetting(message.into()));
+        if self.is_bypassed() {
+            return unsupported("the EQ is bypassed");
+        }
+        if self.get_phase_mode() == PhaseMode::Linear {
+            return unsupported("linear phase mode can't be exported");
+        }
+        if self.get_oversampling() != Oversampling::Off {
+            return unsupported("oversampled bands can't be exported");
+        }
+        let mut bands = Vec::new();
+        for i in 0..self.bands.len() {
+            let settings = self.settings(i);
+            if !settings.enabled || (settings.kind.uses_gain() && settings.db_gain == 0.0) {
+                continue;
+            }
+            let unsupported = |message: &str| ExportError::UnsupportedBand(i + 1, message.into());
+            if settings.dynamic != DynamicMode::Off {
+                return Err(unsupported("dynamic bands can't be exported"));
+            }
+            let channels: &'static [usize] = match settings.routing {
+                BandRouting::Stereo => &[0, 1],
+                BandRouting::Left => &[0],
+                BandRouting::Right => &[1],
+                BandRouting::Mid | BandRouting::Side => {
+                    return Err(unsupported("mid/side bands can't be exported"))
+                }
+            };
+            bands.push(BandBiquads {
+                band: i + 1,
+                channels,
+                biquads: BandCoefficients::new(&settings, sample_rate).biquads(),
+            });
+        }
+        Ok(bands)
+    }
+
+    /// Output trim plus auto gain, in dB, for bands at `sample_rate`
+    fn output_gain_db(&self, sample_rate: f32) -> f32 {
+        self.output_trim.get() + self.auto_gain_db(sample_rate)
+    }
+
+    /// `filters` and `pipeline` sections of a CamillaDSP config with the preamp,
+    /// bands and output gain at `sample_rate`, to merge into a config that has the
+    /// devices.
+    pub fn save_camilladsp(&self, sample_rate: f32) -> Result<String, ExportError> {
+        let bands = self.band_biquads(sample_rate)?;
+        let mut text = format!("# Basic EQ at {} Hz\nfilters:\n", sample_rate);
+        text += &format!(
+            "  preamp:\n    type: Gain\n    parameters:\n      gain: {}\n",
+            self.preamp.get()
+        );
+        for band in &bands {
+            for (stage, biquad) in band.biquads.iter().enumerate() {
+                text += &format!(
+                    "  band_{}_{}:\n    type: Biquad\n    parameters:\n      type: Free\n",
+                    band.band,
+                    stage + 1
+                );
+                text += &format!(
+                    "      b0: {}\n      b1: {}\n      b2: {}\n      a1: {}\n      a2: {}\n",
+                    biquad.b0, biquad.b1, biquad.b2, biquad.a1, biquad.a2
+                );
+            }
+        }
+        text += &format!(
+            "  output_gain:\n    type: Gain\n    parameters:\n      gain: {}\n",
+            self.output_gain_db(sample_rate)
+        );
+        text += "pipeline:\n";
+        for channel in 0..2 {
+            text += &format!("  - type: Filter\n    channel: {}\n    names:\n", channel);
+            text += "      - preamp\n";
+            for band in bands.iter().filter(|band| band.channels.contains(&channel)) {
+                for stage in 0..band.biquads.len() {
+                    text += &format!("      - band_{}_{}\n", band.band, stage + 1);
+                }
+            }
+            text += "      - output_gain\n";
+        }
+        Ok(text)
+    }
+
+    /// Biquads at `sample_rate` in the form the miniDSP advanced biquad
+    /// programming takes, which expects `a1` and `a2` negated. The preamp is a
+    /// gain only biquad at the start and the output gain one at the end. Both
+    /// channels get the same biquads, so bands routed to one channel are an error.
+    pub fn save_minidsp(&self, sample_rate: f32) -> Result<String, ExportError> {
+        let bands = self.band_biquads(sample_rate)?;
+        if let Some(band) = bands.iter().find(|band| band.channels.len() != 2) {
+            return Err(ExportError::UnsupportedBand(
+                band.band,
+                "only stereo bands can be exported".into(),
+            ));
+        }
+        let gain = |db: f32| {
+            if db != 0.0 {
+                Some(Biquad::gain(db.db_to_lin() as f64))
+            } else {
+                None
+            }
+        };
+        let biquads = gain(self.preamp.get())
+            .into_iter()
+            .chain(bands.iter().flat_map(|band| band.biquads.iter().copied()))
+            .chain(gain(self.output_gain_db(sample_rate)));
+        Ok(biquads
+            .enumerate()
+            .map(|(i, biquad)| {
+                format!(
+                    "biquad{},\nb0={},\nb1={},\nb2={},\na1={},\na2={}",
+                    i + 1,
+                    biquad.b0,
+                    biquad.b1,
+                    biquad.b2,
+                    // Not `-biquad.a1`, which turns the zeros of gain only
+                    // biquads into -0
+                    0.0 - biquad.a1,
+                    0.0 - biquad.a2
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",\n")
+            + "\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eq_effect_parameters::{BandKind, BandMode};
+
+    /// A bell and a 24 dB/oct high pass on both channels, and a low shelf on the
+    /// left, between -3 dB of preamp and 1.5 dB of output trim
+    fn params() -> EQEffectParameters {
+        let params = EQEffectParameters::default();
+        params.preamp.set(-3.0);
+        params.output_trim.set(1.5);
+        let bell = &params.bands[0];
+        bell.freq.set(1000.0);
+        bell.db_gain.set(6.0);
+        bell.q_value.set(1.0);
+        let high_pass = &params.bands[1];
+        high_pass.kind.set(BandKind::HighPass as usize as f32);
+        high_pass.freq.set(80.0);
+        high_pass.slope.set(1.0);
+        assert_eq!(high_pass.get_mode(), BandMode::Butterworth24);
+        let shelf = &params.bands[2];
+        shelf.kind.set(BandKind::LowShelf as usize as f32);
+        shelf.freq.set(200.0);
+        shelf.db_gain.set(3.0);
+        shelf.routing.set(BandRouting::Left as usize as f32);
+        params
+    }
+
+    #[test]
+    fn camilladsp_config_matches_the_reference_text() {
+        let expected = r#"# Basic EQ at 48000 Hz
+filters:
+  preamp:
+    type: Gain
+    parameters:
+      gain: -3
+  band_1_1:
+    type: Biquad
+    parameters:
+      type: Free
+      b0: 1.0439530917834914
+      b1: -1.8953207219295534
+      b2: 0.8677222791958698
+      a1: -1.8953207219295534
+      a2: 0.9116753709793611
+  band_2_1:
+    type: Biquad
+    parameters:
+      type: Free
+      b0: 0.9903908819885104
+      b1: -1.9807817639770209
+      b2: 0.9903908819885104
+      a1: -1.980727458716605
+      a2: 0.9808360692374369
+  band_2_2:
+    type: Biquad
+    parameters:
+      type: Free
+      b0: 0.9959813104522185
+      b1: -1.991962620904437
+      b2: 0.9959813104522185
+      a1: -1.9919080091088144
+      a2: 0.9920172327000595
+  band_3_1:
+    type: Biquad
+    parameters:
+      type: Free
+      b0: 1.0032679345652087
+      b1: -1.965263859833324
+      b2: 0.9627966186736107
+      a1: -1.9653807827576988
+      a2: 0.9659476303144446
+  output_gain:
+    type: Gain
+    parameters:
+      gain: 1.5
+pipeline:
+  - type: Filter
+    channel: 0
+    names:
+      - preamp
+      - band_1_1
+      - band_2_1
+      - band_2_2
+      - band_3_1
+      - output_gain
+  - type: Filter
+    channel: 1
+    names:
+      - preamp
+      - band_1_1
+      - band_2_1
+      - band_2_2
+      - output_gain
+"#;
+        assert_eq!(params().save_camilladsp(48000.0).unwrap(), expected);
+    }
+
+    #[test]
+    fn minidsp_biquads_match_the_reference_text() {
+        let params = params();
+        params.bands[2].enabled.set(0.0);
+        let expected = "biquad1,\n\
+            b0=0.7079457640647888,\n\
+            b1=0,\n\
+            b2=0,\n\
+            a1=0,\n\
+            a2=0,\n\
+            biquad2,\n\
+            b0=1.0439530917834914,\n\
+            b1=-1.8953207219295534,\n\
+            b2=0.8677222791958698,\n\
+            a1=1.8953207219295534,\n\
+            a2=-0.9116753709793611,\n\
+            biquad3,\n\
+            b0=0.9903908819885104,\n\
+            b1=-1.9807817639770209,\n\
+            b2=0.9903908819885104,\n\
+            a1=1.980727458716605,\n\
+            a2=-0.9808360692374369,\n\
+            biquad4,\n\
+            b0=0.9959813104522185,\n\
+            b1=-1.991962620904437,\n\
+            b2=0.9959813104522185,\n\
+            a1=1.9919080091088144,\n\
+            a2=-0.9920172327000595,\n\
+            biquad5,\n\
+            b0=1.1885021924972534,\n\
+            b1=0,\n\
+            b2=0,\n\
+            a1=0,\n\
+            a2=0\n";
+        assert_eq!(params.save_minidsp(48000.0).unwrap(), expected);
+    }
+
+    #[test]
+    fn one_sided_bands_are_not_exported_to_minidsp() {
+        assert!(matches!(
+            params().save_minidsp(48000.0),
+            Err(ExportError::UnsupportedBand(3, _))
+        ));
+    }
+
+    /// Both exports of `params` fail for a plugin wide setting
+    fn assert_unsupported_setting(params: &EQEffectParameters) {
+        assert!(matches!(
+            params.save_camilladsp(48000.0),
+            Err(ExportError::UnsupportedSetting(_))
+        ));
+        assert!(matches!(
+            params.save_minidsp(48000.0),
+            Err(ExportError::UnsupportedSetting(_))
+        ));
+    }
+
+    #[test]
+    fn settings_the_biquads_would_not_match_are_errors() {
+        let stereo_params = || {
+            let params = params();
+            params.bands[2].enabled.set(0.0);
+            params
+        };
+        let params = stereo_params();
+        params.bypass.set(1.0);
+        assert_unsupported_setting(&params);
+        let params = stereo_params();
+        params.phase_mode.set(PhaseMode::Linear as usize as f32);
+        assert_unsupported_setting(&params);
+        let params = stereo_params();
+        params.oversampling.set(Oversampling::X2 as usize as f32);
+        assert_unsupported_setting(&params);
+    }
+}
@@ -1,58 +1,102 @@
 #[macro_use]
 extern crate vst;
 
+mod autoeq;
 mod editor;
 pub mod editor_elements;
 mod eq_effect_parameters;
+mod export;
 mod parameter;
-pub mod units;
+mod preset;
+mod response;
+mod spectrum;
+mod state;
+pub use eq_core::units;
 
 mod atomic_bool;
-
-use basic_audio_filters::first_order_iir::IIR1Coefficients;
-use basic_audio_filters::first_order_iir::IIR1;
-use basic_audio_filters::second_order_iir::IIR2Coefficients;
-use basic_audio_filters::second_order_iir::IIR2;
-
-use editor::{EQPluginEditor, EditorState};
-use eq_effect_parameters::{BandKind, BandParameters, EQEffectParameters};
+#[cfg(feature = "clap")]
+mod clap_plugin;
+#[cfg(feature = "lv2")]
+mod lv2_plugin;
+#[cfg(feature = "lv2")]
+pub use lv2_plugin::{manifest_ttl, plugin_ttl};
+
+use autoeq::AutoEqError;
+use editor::{EQPluginEditor, EditHost, EditorState};
+use eq_core::{BandSettings, EqEngine, LinearPhaseDesigner, PhaseMode, Sample};
+use eq_effect_parameters::{BandParameters, EQEffectParameters, FILTER_COUNT};
+use export::ExportError;
+use preset::PresetError;
+use response::FrequencyResponse;
+use spectrum::SampleRing;
 
 use vst::buffer::AudioBuffer;
+use vst::channels::ChannelInfo;
 use vst::editor::Editor;
-use vst::plugin::{Category, Info, Plugin, PluginParameters};
+use vst::plugin::{Category, HostCallback, Info, Plugin, PluginParameters};
 use vst::util::AtomicFloat;
 
-use std::sync::Arc;
-
-const FILTER_COUNT: usize = 4;
-
-fn get_coefficients_iir2(
-    kind: BandKind,
-    f0: f32,
-    db_gain: f32,
-    q_value: f32,
-    fs: f32,
-) -> IIR2Coefficients {
-    match kind {
-        BandKind::Bell => IIR2Coefficients::bell(f0, db_gain, q_value, fs),
-        BandKind::LowPass => IIR2Coefficients::lowpass(f0, db_gain, q_value, fs),
-        BandKind::HighPass => IIR2Coefficients::highpass(f0, db_gain, q_value, fs),
-        BandKind::LowShelf => IIR2Coefficients::lowshelf(f0, db_gain, q_value, fs),
-        BandKind::HighShelf => IIR2Coefficients::highshelf(f0, db_gain, q_value, fs),
-        BandKind::Notch => IIR2Coefficients::notch(f0, db_gain, q_value, fs),
-        BandKind::BandPass => IIR2Coefficients::bandpass(f0, db_gain, q_value, fs),
-        BandKind::AllPass => IIR2Coefficients::allpass(f0, db_gain, q_value, fs),
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// VST2 has no separate sidechain bus, with the `vst-sidechain` feature inputs 3
+/// and 4 are the sidechain for the dynamic bands and hosts see a 4 in 2 out effect
+#[cfg(feature = "vst-sidechain")]
+const VST_INPUTS: i32 = 4;
+#[cfg(not(feature = "vst-sidechain"))]
+const VST_INPUTS: i32 = 2;
+
+/// audioMasterIOChanged, vst 0.2 has no wrapper for it
+const HOST_OPCODE_IO_CHANGED: i32 = 13;
+
+/// Tell the host that the plugin latency changed, not from the audio thread
+fn report_latency(host: &HostCallback, latency: i32) {
+    let effect = host.raw_effect();
+    if let (Some(callback), false) = (host.raw_callback(), effect.is_null()) {
+        unsafe {
+            (*effect).initialDelay = latency;
+        }
+        callback(
+            effect,
+            HOST_OPCODE_IO_CHANGED,
+            0,
+            0,
+            std::ptr::null_mut(),
+            0.0,
+        );
     }
 }
 
-fn get_coefficients_iir1(kind: BandKind, f0: f32, db_gain: f32, fs: f32) -> IIR1Coefficients {
-    match kind {
-        BandKind::LowPass => IIR1Coefficients::lowpass(f0, db_gain, fs),
-        BandKind::HighPass => IIR1Coefficients::highpass(f0, db_gain, fs),
-        BandKind::LowShelf => IIR1Coefficients::lowshelf(f0, db_gain, fs),
-        BandKind::HighShelf => IIR1Coefficients::highshelf(f0, db_gain, fs),
-        BandKind::AllPass => IIR1Coefficients::allpass(f0, db_gain, fs),
-        _ => IIR1Coefficients::allpass(f0, db_gain, fs),
+/// The latency the engine runs at, set from the audio thread, and reported to the
+/// host from the UI thread by the editor's idle and when the host resumes the plugin
+pub struct LatencyReport {
+    host: HostCallback,
+    latency: AtomicUsize,
+    reported: AtomicUsize,
+}
+
+impl LatencyReport {
+    fn new(host: HostCallback, latency: usize) -> LatencyReport {
+        LatencyReport {
+            host,
+            latency: AtomicUsize::new(latency),
+            reported: AtomicUsize::new(latency),
+        }
+    }
+
+    /// Only stores the latency, safe on the audio thread
+    fn set(&self, latency: usize) {
+        self.latency.store(latency, Ordering::Release);
+    }
+
+    /// Report the latency if the host has not seen it yet
+    pub fn report(&self) {
+        let latency = self.latency.load(Ordering::Acquire);
+        if self.reported.swap(latency, Ordering::AcqRel) != latency {
+            report_latency(&self.host, latency as i32);
+        }
     }
 }
 
@@ -60,13 +104,17 @@ pub struct EditorFilterData {
     pub params: Arc<BandParameters>,
 }
 
-struct EQPlugin {
+pub struct EQPlugin {
     params: Arc<EQEffectParameters>,
     editor: Option<EQPluginEditor>,
-    filter_iir2_l: Vec<IIR2>,
-    filter_iir2_r: Vec<IIR2>,
-    filter_iir1_l: Vec<IIR1>,
-    filter_iir1_r: Vec<IIR1>,
+    engine: EqEngine,
+    /// Static gain plus gain change of each band, shown in the editor
+    dynamic_gains: Arc<Vec<AtomicFloat>>,
+    /// Only held so the designer thread lives as long as the plugin
+    _designer: Option<LinearPhaseDesigner>,
+    latency: Arc<LatencyReport>,
+    pre_samples: Arc<SampleRing>,
+    post_samples: Arc<SampleRing>,
     time: Arc<AtomicFloat>,
     sample_rate: Arc<AtomicFloat>,
     block_size: i64,
@@ -74,29 +122,44 @@ struct EQPlugin {
 
 impl Default for EQPlugin {
     fn default() -> Self {
+        EQPlugin::with_host(HostCallback::default())
+    }
+}
+
+impl EQPlugin {
+    fn with_host(host: HostCallback) -> Self {
+        EQPlugin::with_edit_host(host, Arc::new(host))
+    }
+
+    /// `edit_host` receives the parameter edits made in the editor
+    fn with_edit_host(host: HostCallback, edit_host: Arc<dyn EditHost>) -> Self {
         let params = Arc::new(EQEffectParameters::default());
         let time = Arc::new(AtomicFloat::new(0.0));
         let sample_rate = Arc::new(AtomicFloat::new(48000.0));
+        let pre_samples = Arc::new(SampleRing::new());
+        let post_samples = Arc::new(SampleRing::new());
+
+        let dynamic_gains = Arc::new(
+            params
+                .bands
+                .iter()
+                .map(|band| AtomicFloat::new(band.db_gain.get()))
+                .collect::<Vec<AtomicFloat>>(),
+        );
+
+        let mut engine = EqEngine::new(FILTER_COUNT, sample_rate.get());
+        let designer_params = params.clone();
+        let designer_sample_rate = sample_rate.clone();
+        let designer = engine.spawn_kernel_designer(move || {
+            if designer_params.get_phase_mode() == PhaseMode::Linear {
+                Some((designer_params.band_settings(), designer_sample_rate.get()))
+            } else {
+                None
+            }
+        });
 
-        let coeffs = IIR2Coefficients::bell(1000.0, 0.0, 1.0, 48000.0);
-
-        let filter_iir2_l = (0..FILTER_COUNT)
-            .map(|_| IIR2::from(coeffs))
-            .collect::<Vec<IIR2>>();
-        let filter_iir2_r = (0..FILTER_COUNT)
-            .map(|_| IIR2::from(coeffs))
-            .collect::<Vec<IIR2>>();
-
-        let coeffs = IIR1Coefficients::lowpass(1000.0, 0.0, 48000.0);
-
-        let filter_iir1_l = (0..FILTER_COUNT)
-            .map(|_| IIR1::from(coeffs))
-            .collect::<Vec<IIR1>>();
-        let filter_iir1_r = (0..FILTER_COUNT)
-            .map(|_| IIR1::from(coeffs))
-            .collect::<Vec<IIR1>>();
-
-        Self {
+        let latency = Arc::new(LatencyReport::new(host, engine.latency()));
+        let mut plugin = Self {
             params: params.clone(),
             sample_rate: sample_rate.clone(),
             block_size: 128,
@@ -106,13 +169,39 @@ impl Default for EQPlugin {
                 state: Arc::new(EditorState {
                     params: params.clone(),
                     sample_rate: sample_rate.clone(),
+                    pre_samples: pre_samples.clone(),
+                    post_samples: post_samples.clone(),
+                    dynamic_gains: dynamic_gains.clone(),
+                    host: edit_host,
                 }),
+                latency: latency.clone(),
             }),
-            filter_iir2_l,
-            filter_iir2_r,
-            filter_iir1_l,
-            filter_iir1_r,
+            engine,
+            dynamic_gains,
+            _designer: designer,
+            latency,
+            pre_samples,
+            post_samples,
+        };
+        plugin.sync_engine();
+        plugin.engine.reset();
+        plugin
+    }
+
+    /// Hand every parameter to the engine, whether it changed or not
+    fn sync_engine(&mut self) {
+        for (i, band) in self.params.bands.iter().enumerate() {
+            self.engine.set_band_smoothing(i, band.smoothing());
+            self.engine.set_band(i, self.params.settings(i));
         }
+        self.engine.set_phase_mode(self.params.get_phase_mode());
+        self.engine.set_oversampling(self.params.get_oversampling());
+        self.engine.set_precision(self.params.get_precision());
+        self.engine.set_preamp(self.params.preamp.get());
+        self.engine.set_bypass(self.params.is_bypassed());
+        self.engine.set_delta(self.params.get_delta());
+        self.engine.set_auto_gain(self.params.is_auto_gain());
+        self.engine.set_output_trim(self.params.output_trim.get());
     }
 }
 
@@ -134,31 +223,166 @@ fn setup_logging() {
     ::log::info!("init");
 }
 
+impl EQPlugin {
+    /// Process one block of stereo audio, in f32 or f64. `sidechain` feeds the dynamic
+    /// bands that are in sidechain mode, without it they follow the main input.
+    pub fn process_block<S: Sample>(
+        &mut self,
+        inputs: [&[S]; 2],
+        sidechain: Option<[&[S]; 2]>,
+        outputs: [&mut [S]; 2],
+    ) {
+        let params = self.params.clone();
+        let design = params.get_filter_design();
+        for (i, band) in params.bands.iter().enumerate() {
+            self.engine.set_band_smoothing(i, band.smoothing());
+            if band.dsp_update() || self.engine.band(i).design != design {
+                self.engine.set_band(i, params.settings(i));
+            }
+        }
+        self.engine.set_preamp(params.preamp.get());
+        self.engine.set_bypass(params.is_bypassed());
+        self.engine.set_delta(params.get_delta());
+        self.engine.set_auto_gain(params.is_auto_gain());
+        self.engine.set_output_trim(params.output_trim.get());
+        self.engine.set_precision(params.get_precision());
+        self.engine.set_phase_mode(params.get_phase_mode());
+        self.engine.set_oversampling(params.get_oversampling());
+        // Reported later by the editor or on resume, the host must not hear of it here
+        self.latency.set(self.engine.latency());
+
+        let [input_left, input_right] = inputs;
+        let [output_left, output_right] = outputs;
+        output_left.copy_from_slice(input_left);
+        output_right.copy_from_slice(input_right);
+        for (l, r) in output_left.iter().zip(output_right.iter()) {
+            self.pre_samples
+                .push(((l.to_f64() + r.to_f64()) * 0.5) as f32);
+        }
+
+        self.engine
+            .process([&mut *output_left, &mut *output_right], sidechain);
+
+        for (l, r) in output_left.iter().zip(output_right.iter()) {
+            self.post_samples
+                .push(((l.to_f64() + r.to_f64()) * 0.5) as f32);
+        }
+        for (i, gain) in self.dynamic_gains.iter().enumerate() {
+            gain.set(self.engine.band_gain(i));
+        }
+    }
+
+    /// Load a state chunk or text preset, see `EQEffectParameters::load_preset`
+    pub fn load_preset(&self, data: &[u8]) -> Result<(), PresetError> {
+        self.params.load_preset(data)
+    }
+
+    /// The current settings as a text preset
+    pub fn preset_text(&self) -> String {
+        self.params.save_preset_text()
+    }
+
+    /// Load an Equalizer APO / AutoEQ parametric EQ, see `autoeq::parse_autoeq`
+    pub fn load_autoeq(&self, text: &str) -> Result<(), AutoEqError> {
+        self.params.load_autoeq(text)
+    }
+
+    /// The current settings as an Equalizer APO parametric EQ
+    pub fn autoeq_text(&self) -> Result<String, AutoEqError> {
+        self.params.save_autoeq()
+    }
+
+    /// The preamp and bands as a CamillaDSP config at `sample_rate`
+    pub fn camilladsp_config(&self, sample_rate: f32) -> Result<String, ExportError> {
+        self.params.save_camilladsp(sample_rate)
+    }
+
+    /// The preamp and bands as miniDSP biquads at `sample_rate`
+    pub fn minidsp_biquads(&self, sample_rate: f32) -> Result<String, ExportError> {
+        self.params.save_minidsp(sample_rate)
+    }
+
+    /// Response of the preamp and bands at `sample_rate`, see
+    /// `EQEffectParameters::frequency_response`
+    pub fn frequency_response(
+        &self,
+        points_per_octave: f32,
+        sample_rate: f32,
+    ) -> FrequencyResponse {
+        self.params
+            .frequency_response(points_per_octave, sample_rate)
+    }
+
+    /// Plain settings of every band
+    pub fn band_settings(&self) -> Vec<BandSettings> {
+        self.params.band_settings()
+    }
+
+    /// Latency in samples of the selected phase mode and oversampling
+    pub fn latency(&self) -> usize {
+        self.engine.latency()
+    }
+
+    /// Clear the audio buffered in the engine, see `EqEngine::clear_buffers`
+    #[cfg_attr(not(feature = "clap"), allow(dead_code))]
+    pub fn clear_buffers(&mut self) {
+        self.engine.clear_buffers();
+    }
+}
+
 impl Plugin for EQPlugin {
     fn get_info(&self) -> Info {
         Info {
-            name: "Basic IMGUI EQ in Rust 0.1".to_string(),
+            name: format!("Basic IMGUI EQ in Rust 0.1 {} Band", FILTER_COUNT),
             vendor: "DGriffin".to_string(),
-            unique_id: 237955111,
+            // Each band count has its own parameter layout, so give it its own id
+            unique_id: 237955111 + FILTER_COUNT as i32 - 4,
             version: 2,
-            inputs: 2,
+            inputs: VST_INPUTS,
             outputs: 2,
             // This `parameters` bit is important; without it, none of our
             // parameters will be shown!
             parameters: self.params.len() as i32,
             category: Category::Effect,
+            preset_chunks: true,
+            f64_precision: true,
+            initial_delay: self.latency() as i32,
             ..Default::default()
         }
     }
 
+    fn new(host: HostCallback) -> Self {
+        EQPlugin::with_host(host)
+    }
+
+    fn get_input_info(&self, input: i32) -> ChannelInfo {
+        let (name, short_name) = match input {
+            0 => ("Left", "L"),
+            1 => ("Right", "R"),
+            2 => ("Sidechain left", "SC L"),
+            _ => ("Sidechain right", "SC R"),
+        };
+        ChannelInfo::new(name.to_string(), Some(short_name.to_string()), true, None)
+    }
+
     fn set_sample_rate(&mut self, rate: f32) {
         self.sample_rate.set(rate);
+        self.sync_engine();
+        self.engine.set_sample_rate(rate);
     }
 
     fn set_block_size(&mut self, block_size: i64) {
         self.block_size = block_size;
     }
 
+    /// Hosts read the latency when resuming, so catch up on changes made while
+    /// suspended and report it
+    fn resume(&mut self) {
+        self.sync_engine();
+        self.latency.set(self.engine.latency());
+        self.latency.report();
+    }
+
     fn init(&mut self) {
         setup_logging();
         //setup_logger();
@@ -173,54 +397,42 @@ impl Plugin for EQPlugin {
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        println!("{}", vec![1.0][0]);
         self.time
             .set(self.time.get() + (1.0 / self.sample_rate.get()) * self.block_size as f32);
-        let sample_rate = self.sample_rate.get();
 
         let (inputs, outputs) = buffer.split();
-        let (inputs_left, inputs_right) = inputs.split_at(1);
+        // Inputs 3 and 4 are the sidechain, if the host connects them
+        let sidechain = if inputs.len() >= 4 {
+            Some([inputs.get(2), inputs.get(3)])
+        } else {
+            None
+        };
         let (mut outputs_left, mut outputs_right) = outputs.split_at_mut(1);
+        self.process_block(
+            [inputs.get(0), inputs.get(1)],
+            sidechain,
+            [&mut outputs_left[0], &mut outputs_right[0]],
+        );
+    }
 
-        let inputs_stereo = inputs_left[0].iter().zip(inputs_right[0].iter());
-        let outputs_stereo = outputs_left[0].iter_mut().zip(outputs_right[0].iter_mut());
-
-        for (input_pair, output_pair) in inputs_stereo.zip(outputs_stereo) {
-            for (i, band) in self.params.bands.iter().enumerate() {
-                if !band.dsp_update() {
-                    continue;
-                }
-                let f0 = band.freq.get();
-                let db_gain = band.db_gain.get();
-                let q_value = band.q_value.get();
-                let iir2mode = band.mode.get().floor() == 1.0;
-                let fs = sample_rate;
-                if iir2mode {
-                    let coeffs = get_coefficients_iir2(band.get_kind(), f0, db_gain, q_value, fs);
-                    self.filter_iir2_l[i].update(coeffs);
-                    self.filter_iir2_r[i].update(coeffs);
-                } else {
-                    let coeffs = get_coefficients_iir1(band.get_kind(), f0, db_gain, fs);
-                    self.filter_iir1_l[i].update(coeffs);
-                    self.filter_iir1_r[i].update(coeffs);
-                }
-            }
+    /// The same as `process`, for hosts that process in double precision
+    fn process_f64(&mut self, buffer: &mut AudioBuffer<f64>) {
+        self.time
+            .set(self.time.get() + (1.0 / self.sample_rate.get()) * self.block_size as f32);
 
-            let (output_l, output_r) = output_pair;
-            *output_l = *input_pair.0;
-            *output_r = *input_pair.1;
-
-            for i in 0..self.filter_iir2_l.len() {
-                let iir2mode = self.params.bands[i].mode.get().floor() == 1.0;
-                if iir2mode {
-                    *output_l = self.filter_iir2_l[i].process(*output_l);
-                    *output_r = self.filter_iir2_r[i].process(*output_r);
-                } else {
-                    *output_l = self.filter_iir1_l[i].process(*output_l);
-                    *output_r = self.filter_iir1_r[i].process(*output_r);
-                }
-            }
-        }
+        let (inputs, outputs) = buffer.split();
+        // Inputs 3 and 4 are the sidechain, if the host connects them
+        let sidechain = if inputs.len() >= 4 {
+            Some([inputs.get(2), inputs.get(3)])
+        } else {
+            None
+        };
+        let (mut outputs_left, mut outputs_right) = outputs.split_at_mut(1);
+        self.process_block(
+            [inputs.get(0), inputs.get(1)],
+            sidechain,
+            [&mut outputs_left[0], &mut outputs_right[0]],
+        );
     }
 
     // Return the parameter object. This method can be omitted if the
@@ -267,6 +479,26 @@ impl PluginParameters for EQEffectParameters {
             "".to_string()
         }
     }
+
+    fn get_preset_data(&self) -> Vec<u8> {
+        self.save_state()
+    }
+
+    fn get_bank_data(&self) -> Vec<u8> {
+        self.save_state()
+    }
+
+    fn load_preset_data(&self, data: &[u8]) {
+        if let Err(err) = self.load_state(data) {
+            ::log::warn!("could not load preset: {}", err);
+        }
+    }
+
+    fn load_bank_data(&self, data: &[u8]) {
+        if let Err(err) = self.load_state(data) {
+            ::log::warn!("could not load bank: {}", err);
+        }
+    }
 }
 
 plugin_main!(EQPlugin);
@@ -0,0 +1,404 @@
+//! LV2 entry point, built with the `lv2` feature. Every parameter is a control
+//! port, in host index order after the audio ports. `plugin_ttl` and
+//! `manifest_ttl` describe the ports, `scripts/lv2_bundler.sh` writes them into a
+//! bundle with the library.
+
+use crate::eq_effect_parameters::{
+    EQEffectParameters, FILTER_COUNT, GLOBAL_PARAMETERS, PARAMETERS_PER_BAND,
+};
+use crate::{setup_logging, EQPlugin};
+
+use vst::plugin::Plugin;
+
+use std::ffi::{c_void, CString};
+use std::fmt::Write;
+use std::os::raw::c_char;
+use std::sync::{Arc, OnceLock};
+
+/// Port indices, control ports for the parameters follow `FIRST_CONTROL_PORT`
+const INPUT_LEFT: u32 = 0;
+const INPUT_RIGHT: u32 = 1;
+const OUTPUT_LEFT: u32 = 2;
+const OUTPUT_RIGHT: u32 = 3;
+const SIDECHAIN_LEFT: u32 = 4;
+const SIDECHAIN_RIGHT: u32 = 5;
+const LATENCY: u32 = 6;
+const FIRST_CONTROL_PORT: u32 = 7;
+
+/// Blocks longer than this are processed in parts, so nothing is allocated in `run`
+const MAX_BLOCK: usize = 1024;
+
+/// Symbol, unit and whether the port is best shown on a log scale, for each band
+/// parameter in slot order. Hosts save port values by symbol, so these never change.
+const BAND_PORTS: [(&str, Option<&str>, bool); PARAMETERS_PER_BAND] = [
+    ("kind", None, false),
+    ("freq", Some("units:hz"), true),
+    ("gain", Some("units:db"), false),
+    ("q", None, false),
+    ("mode", None, false),
+    ("routing", None, false),
+    ("dynamic", None, false),
+    ("threshold", Some("units:db"), false),
+    ("ratio", None, false),
+    ("attack", Some("units:ms"), true),
+    ("release", Some("units:ms"), true),
+    ("enabled", None, false),
+    ("solo", None, false),
+    ("slope", None, false),
+];
+
+const GLOBAL_PORTS: [(&str, Option<&str>, bool); GLOBAL_PARAMETERS] = [
+    ("phase_mode", None, false),
+    ("preamp", Some("units:db"), false),
+    ("bypass", None, false),
+    ("delta", None, false),
+    ("auto_gain", None, false),
+    ("output_trim", Some("units:db"), false),
+    ("oversampling", None, false),
+    ("filter_design", None, false),
+    ("precision", None, false),
+];
+
+/// Like the VST id, each band count has its own URI
+fn plugin_uri() -> String {
+    format!(
+        "https://github.com/DGriffin91/rust-basic-eq-plugin#{}-band",
+        FILTER_COUNT
+    )
+}
+
+/// Port symbol, unit and log scale of the parameter at host index `index`
+fn port_info(params: &EQEffectParameters, index: usize) -> (String, Option<&'static str>, bool) {
+    match params.band_slot(index) {
+        Some((band, slot)) => {
+            let (symbol, unit, logarithmic) = BAND_PORTS[slot];
+            (format!("band{}_{}", band + 1, symbol), unit, logarithmic)
+        }
+        None => {
+            let (symbol, unit, logarithmic) = GLOBAL_PORTS[index - params.globals_start()];
+            (symbol.to_string(), unit, logarithmic)
+        }
+    }
+}
+
+/// The bundle's `manifest.ttl`, `binary` is the file name of the plugin library
+pub fn manifest_ttl(binary: &str) -> String {
+    format!(
+        "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .
+@prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+
+<{}>
+    a lv2:Plugin ;
+    lv2:binary <{}> ;
+    rdfs:seeAlso <basic_eq.ttl> .
+",
+        plugin_uri(),
+        binary
+    )
+}
+
+fn audio_port(index: u32, direction: &str, symbol: &str, name: &str, sidechain: bool) -> String {
+    let extra = if sidechain {
+        "\n        lv2:portProperty lv2:isSideChain, lv2:connectionOptional ;"
+    } else {
+        ""
+    };
+    format!(
+        "[
+        a lv2:AudioPort, lv2:{}Port ;
+        lv2:index {} ;
+        lv2:symbol \"{}\" ;
+        lv2:name \"{}\" ;{}
+    ]",
+        direction, index, symbol, name, extra
+    )
+}
+
+/// The bundle's `basic_eq.ttl`, describing every port
+pub fn plugin_ttl() -> String {
+    let params = EQEffectParameters::default();
+    let mut ports = vec![
+        audio_port(INPUT_LEFT, "Input", "in_l", "Left in", false),
+        audio_port(INPUT_RIGHT, "Input", "in_r", "Right in", false),
+        audio_port(OUTPUT_LEFT, "Output", "out_l", "Left out", false),
+        audio_port(OUTPUT_RIGHT, "Output", "out_r", "Right out", false),
+        audio_port(SIDECHAIN_LEFT, "Input", "sc_l", "Sidechain left", true),
+        audio_port(SIDECHAIN_RIGHT, "Input", "sc_r", "Sidechain right", true),
+        format!(
+            "[
+        a lv2:ControlPort, lv2:OutputPort ;
+        lv2:index {} ;
+        lv2:symbol \"latency\" ;
+        lv2:name \"Latency\" ;
+        lv2:portProperty lv2:reportsLatency, lv2:integer ;
+        units:unit units:frame ;
+    ]",
+            LATENCY
+        ),
+    ];
+
+    for i in 0..params.len() {
+        let parameter = &params[i];
+        let (symbol, unit, logarithmic) = port_info(&params, i);
+        let mut port = String::new();
+        write!(
+            port,
+            "[
+        a lv2:ControlPort, lv2:InputPort ;
+        lv2:index {} ;
+        lv2:symbol \"{}\" ;
+        lv2:name \"{}\" ;
+        lv2:default {:?} ;
+        lv2:minimum {:?} ;
+        lv2:maximum {:?} ;",
+            FIRST_CONTROL_PORT as usize + i,
+            symbol,
+            parameter.get_name(),
+            parameter.default,
+            parameter.min,
+            parameter.max
+        )
+        .unwrap();
+        if let Some(unit) = unit {
+            write!(port, "\n        units:unit {} ;", unit).unwrap();
+        }
+        if logarithmic {
+            port.push_str("\n        lv2:portProperty pprops:logarithmic ;");
+        }
+        if parameter.is_stepped() {
+            port.push_str("\n        lv2:portProperty lv2:integer, lv2:enumeration ;");
+            // Steps past the last variant show as an earlier one, those are left out
+            let mut labels: Vec<String> = Vec::new();
+            for step in parameter.min as i32..=parameter.max as i32 {
+                let label = parameter.display(step as f32);
+                if !labels.contains(&label) {
+                    write!(
+                        port,
+                        "\n        lv2:scalePoint [ rdfs:label \"{}\" ; rdf:value {} ] ;",
+                        label, step
+                    )
+                    .unwrap();
+                    labels.push(label);
+                }
+            }
+        }
+        port.push_str("\n    ]");
+        ports.push(port);
+    }
+
+    format!(
+        "@prefix doap: <http://usefulinc.com/ns/doap#> .
+@prefix lv2: <http://lv2plug.in/ns/lv2core#> .
+@prefix pprops: <http://lv2plug.in/ns/ext/port-props#> .
+@prefix rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#> .
+@prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+@prefix units: <http://lv2plug.in/ns/extensions/units#> .
+
+<{}>
+    a lv2:Plugin, lv2:EQPlugin ;
+    doap:name \"Basic IMGUI EQ in Rust 0.1 {} Band\" ;
+    doap:license <https://www.gnu.org/licenses/gpl-3.0> ;
+    lv2:optionalFeature lv2:hardRTCapable ;
+    lv2:port {} .
+",
+        plugin_uri(),
+        FILTER_COUNT,
+        ports.join(" , ")
+    )
+}
+
+#[repr(C)]
+struct LV2Feature {
+    uri: *const c_char,
+    data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct LV2Descriptor {
+    uri: *const c_char,
+    instantiate: unsafe extern "C" fn(
+        descriptor: *const LV2Descriptor,
+        sample_rate: f64,
+        bundle_path: *const c_char,
+        features: *const *const LV2Feature,
+    ) -> *mut c_void,
+    connect_port: unsafe extern "C" fn(instance: *mut c_void, port: u32, data: *mut c_void),
+    activate: unsafe extern "C" fn(instance: *mut c_void),
+    run: unsafe extern "C" fn(instance: *mut c_void, sample_count: u32),
+    deactivate: unsafe extern "C" fn(instance: *mut c_void),
+    cleanup: unsafe extern "C" fn(instance: *mut c_void),
+    extension_data: unsafe extern "C" fn(uri: *const c_char) -> *const c_void,
+}
+
+/// Descriptor and the URI it points at, built on first use
+struct Descriptor {
+    descriptor: LV2Descriptor,
+    _uri: CString,
+}
+
+// Only points into itself
+unsafe impl Send for Descriptor {}
+unsafe impl Sync for Descriptor {}
+
+struct LV2EQ {
+    eq: EQPlugin,
+    params: Arc<EQEffectParameters>,
+    sample_rate: f32,
+    inputs: [*const f32; 2],
+    outputs: [*mut f32; 2],
+    sidechain: [*const f32; 2],
+    latency: *mut f32,
+    controls: Vec<*const f32>,
+    /// Control values last handed to the parameters, so unchanged ports are skipped
+    last_controls: Vec<f32>,
+    /// Copies of the input and sidechain channels, since hosts may process in place
+    scratch: [Vec<f32>; 4],
+}
+
+unsafe extern "C" fn instantiate(
+    _descriptor: *const LV2Descriptor,
+    sample_rate: f64,
+    _bundle_path: *const c_char,
+    _features: *const *const LV2Feature,
+) -> *mut c_void {
+    setup_logging();
+    let eq = EQPlugin::default();
+    let params = eq.params.clone();
+    let len = params.len();
+    Box::into_raw(Box::new(LV2EQ {
+        eq,
+        params,
+        sample_rate: sample_rate as f32,
+        inputs: [std::ptr::null(); 2],
+        outputs: [std::ptr::null_mut(); 2],
+        sidechain: [std::ptr::null(); 2],
+        latency: std::ptr::null_mut(),
+        controls: vec![std::ptr::null(); len],
+        last_controls: vec![f32::NAN; len],
+        scratch: [
+            vec![0.0; MAX_BLOCK],
+            vec![0.0; MAX_BLOCK],
+            vec![0.0; MAX_BLOCK],
+            vec![0.0; MAX_BLOCK],
+        ],
+    })) as *mut c_void
+}
+
+unsafe extern "C" fn connect_port(instance: *mut c_void, port: u32, data: *mut c_void) {
+    let lv2_eq = &mut *(instance as *mut LV2EQ);
+    match port {
+        INPUT_LEFT => lv2_eq.inputs[0] = data as *const f32,
+        INPUT_RIGHT => lv2_eq.inputs[1] = data as *const f32,
+        OUTPUT_LEFT => lv2_eq.outputs[0] = data as *mut f32,
+        OUTPUT_RIGHT => lv2_eq.outputs[1] = data as *mut f32,
+        SIDECHAIN_LEFT => lv2_eq.sidechain[0] = data as *const f32,
+        SIDECHAIN_RIGHT => lv2_eq.sidechain[1] = data as *const f32,
+        LATENCY => lv2_eq.latency = data as *mut f32,
+        _ => {
+            if let Some(control) = lv2_eq
+                .controls
+                .get_mut((port - FIRST_CONTROL_PORT) as usize)
+            {
+                *control = data as *const f32;
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn activate(instance: *mut c_void) {
+    let lv2_eq = &mut *(instance as *mut LV2EQ);
+    lv2_eq.eq.set_sample_rate(lv2_eq.sample_rate);
+}
+
+unsafe extern "C" fn run(instance: *mut c_void, sample_count: u32) {
+    let lv2_eq = &mut *(instance as *mut LV2EQ);
+    for (i, control) in lv2_eq.controls.iter().enumerate() {
+        if let Some(value) = control.as_ref() {
+            if *value != lv2_eq.last_controls[i] {
+                lv2_eq.params[i].set(*value);
+                lv2_eq.last_controls[i] = *value;
+            }
+        }
+    }
+
+    let channels = [
+        lv2_eq.inputs[0],
+        lv2_eq.inputs[1],
+        lv2_eq.outputs[0],
+        lv2_eq.outputs[1],
+    ];
+    if channels.iter().any(|channel| channel.is_null()) {
+        return;
+    }
+    let has_sidechain = lv2_eq.sidechain.iter().all(|channel| !channel.is_null());
+    let frames = sample_count as usize;
+
+    let mut start = 0;
+    while start < frames {
+        let len = (frames - start).min(MAX_BLOCK);
+        let (inputs, sidechain) = lv2_eq.scratch.split_at_mut(2);
+        for (scratch, input) in inputs.iter_mut().zip(&lv2_eq.inputs) {
+            scratch[..len].copy_from_slice(std::slice::from_raw_parts(input.add(start), len));
+        }
+        if has_sidechain {
+            for (scratch, input) in sidechain.iter_mut().zip(&lv2_eq.sidechain) {
+                scratch[..len].copy_from_slice(std::slice::from_raw_parts(input.add(start), len));
+            }
+        }
+        let output_left = std::slice::from_raw_parts_mut(lv2_eq.outputs[0].add(start), len);
+        let output_right = std::slice::from_raw_parts_mut(lv2_eq.outputs[1].add(start), len);
+        lv2_eq.eq.process_block(
+            [&inputs[0][..len], &inputs[1][..len]],
+            if has_sidechain {
+                Some([&sidechain[0][..len], &sidechain[1][..len]])
+            } else {
+                None
+            },
+            [output_left, output_right],
+        );
+        start += len;
+    }
+
+    if let Some(latency) = lv2_eq.latency.as_mut() {
+        *latency = lv2_eq.eq.latency() as f32;
+    }
+}
+
+unsafe extern "C" fn deactivate(_instance: *mut c_void) {}
+
+unsafe extern "C" fn cleanup(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut LV2EQ));
+}
+
+unsafe extern "C" fn extension_data(_uri: *const c_char) -> *const c_void {
+    std::ptr::null()
+}
+
+fn descriptor() -> &'static Descriptor {
+    static DESCRIPTOR: OnceLock<Descriptor> = OnceLock::new();
+    DESCRIPTOR.get_or_init(|| {
+        let uri = CString::new(plugin_uri()).unwrap();
+        Descriptor {
+            descriptor: LV2Descriptor {
+                uri: uri.as_ptr(),
+                instantiate,
+                connect_port,
+                activate,
+                run,
+                deactivate,
+                cleanup,
+                extension_data,
+            },
+            _uri: uri,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn lv2_descriptor(index: u32) -> *const LV2Descriptor {
+    if index == 0 {
+        &descriptor().descriptor
+    } else {
+        std::ptr::null()
+    }
+}
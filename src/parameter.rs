@@ -2,6 +2,8 @@ use vst::util::AtomicFloat;
 
 use crate::{atomic_bool::AtomicBool, units::Units};
 
+use eq_core::SmoothingStyle;
+
 pub struct Parameter {
     name: String,
     normalized_value: AtomicFloat,
@@ -13,6 +15,9 @@ pub struct Parameter {
     pub transform_func: fn(f32) -> f32,
     pub inv_transform_func: fn(f32) -> f32,
     need_to_update_dsp: AtomicBool,
+    smoothing_style: SmoothingStyle,
+    smoothing_ms: AtomicFloat,
+    stepped: bool,
 }
 
 impl Parameter {
@@ -27,7 +32,7 @@ impl Parameter {
     ) -> Parameter {
         Parameter {
             name: String::from(name),
-            normalized_value: AtomicFloat::new(default.from_range(min, max)),
+            normalized_value: AtomicFloat::new(inv_transform_func(default.from_range(min, max))),
             value: AtomicFloat::new(default),
             default,
             min,
@@ -36,9 +41,41 @@ impl Parameter {
             transform_func,
             inv_transform_func,
             need_to_update_dsp: AtomicBool::new(true),
+            smoothing_style: SmoothingStyle::Linear,
+            smoothing_ms: AtomicFloat::new(0.0),
+            stepped: false,
         }
     }
 
+    /// Only takes whole numbers, like the band type
+    pub fn stepped(mut self) -> Parameter {
+        self.stepped = true;
+        self
+    }
+
+    pub fn is_stepped(&self) -> bool {
+        self.stepped
+    }
+
+    /// Smooth changes of this parameter in the DSP over `ms` milliseconds
+    pub fn with_smoothing(mut self, style: SmoothingStyle, ms: f32) -> Parameter {
+        self.smoothing_style = style;
+        self.smoothing_ms.set(ms.max(0.0));
+        self
+    }
+
+    pub fn smoothing_style(&self) -> SmoothingStyle {
+        self.smoothing_style
+    }
+
+    pub fn get_smoothing_ms(&self) -> f32 {
+        self.smoothing_ms.get()
+    }
+
+    pub fn set_smoothing_ms(&self, ms: f32) {
+        self.smoothing_ms.set(ms.max(0.0));
+    }
+
     pub fn get_normalized(&self) -> f32 {
         self.normalized_value.get()
     }
@@ -47,12 +84,22 @@ impl Parameter {
         (self.inv_transform_func)(self.default.from_range(self.min, self.max))
     }
 
+    /// Plain value at normalized position `x`
+    pub fn normalized_to_value(&self, x: f32) -> f32 {
+        (self.transform_func)(x.clamp(0.0, 1.0)).to_range(self.min, self.max)
+    }
+
+    /// Normalized position of the plain value `x`
+    pub fn value_to_normalized(&self, x: f32) -> f32 {
+        let x = x.max(self.min).min(self.max);
+        (self.inv_transform_func)(x.from_range(self.min, self.max))
+    }
+
     pub fn set_normalized(&self, x: f32) {
         self.need_to_update_dsp.set(true);
         let x = x.max(0.0).min(1.0);
         self.normalized_value.set(x);
-        self.value
-            .set((self.transform_func)(x).to_range(self.min, self.max));
+        self.value.set(self.normalized_to_value(x));
     }
 
     pub fn get(&self) -> f32 {
@@ -63,12 +110,16 @@ impl Parameter {
         self.need_to_update_dsp.set(true);
         let x = x.max(self.min).min(self.max);
         self.value.set(x);
-        self.normalized_value
-            .set((self.inv_transform_func)(x.from_range(self.min, self.max)));
+        self.normalized_value.set(self.value_to_normalized(x));
     }
 
     pub fn get_display(&self) -> String {
-        (self.display_func)(self.value.get())
+        self.display(self.value.get())
+    }
+
+    /// How the plain value `x` is shown to the user
+    pub fn display(&self, x: f32) -> String {
+        (self.display_func)(x)
     }
 
     pub fn get_name(&self) -> String {
@@ -0,0 +1,88 @@
+use crate::eq_effect_parameters::EQEffectParameters;
+use crate::state::{StateError, MAGIC};
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum PresetError {
+    State(StateError),
+    NotText,
+    /// Line number (from 1) and what is wrong with it
+    Line(usize, String),
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PresetError::State(err) => write!(f, "{}", err),
+            PresetError::NotText => write!(f, "preset is neither a state chunk nor text"),
+            PresetError::Line(line, message) => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl From<StateError> for PresetError {
+    fn from(err: StateError) -> Self {
+        PresetError::State(err)
+    }
+}
+
+impl EQEffectParameters {
+    /// Load either a state chunk made by `save_state`, or a text preset
+    pub fn load_preset(&self, data: &[u8]) -> Result<(), PresetError> {
+        if data.starts_with(MAGIC) {
+            return Ok(self.load_state(data)?);
+        }
+        let text = std::str::from_utf8(data).map_err(|_| PresetError::NotText)?;
+        self.load_preset_text(text)
+    }
+
+    /// Text presets have one `parameter name = value` per line, with the name as
+    /// shown to the host and the value in plain units. Names are not case sensitive,
+    /// `#` starts a comment and parameters that are not listed keep their value.
+    pub fn load_preset_text(&self, text: &str) -> Result<(), PresetError> {
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name.trim(), value.trim()),
+                _ => {
+                    return Err(PresetError::Line(
+                        n + 1,
+                        "expected `name = value`".to_string(),
+                    ))
+                }
+            };
+            let parameter = (0..self.len())
+                .map(|i| &self[i])
+                .find(|parameter| parameter.get_name().eq_ignore_ascii_case(name))
+                .ok_or_else(|| PresetError::Line(n + 1, format!("unknown parameter {}", name)))?;
+            let value = value
+                .parse::<f32>()
+                .map_err(|_| PresetError::Line(n + 1, format!("{} is not a number", value)))?;
+            // Presets from before the slope parameter kept the slopes in `mode`
+            match self
+                .bands
+                .iter()
+                .find(|band| std::ptr::eq(&band.mode, parameter))
+            {
+                Some(band) if value >= 2.0 => {
+                    band.mode.set(1.0);
+                    band.slope.set(value - 1.0);
+                }
+                _ => parameter.set(value),
+            }
+        }
+        Ok(())
+    }
+
+    /// Every parameter as a text preset
+    pub fn save_preset_text(&self) -> String {
+        (0..self.len())
+            .map(|i| format!("{} = {}\n", self[i].get_name(), self[i].get()))
+            .collect()
+    }
+}
@@ -0,0 +1,113 @@
+use crate::eq_effect_parameters::{BandRouting, EQEffectParameters};
+use crate::units::Units;
+use eq_core::BandCoefficients;
+
+use rustfft::num_complex::Complex;
+
+/// Lowest and highest frequency of an exported response
+const RESPONSE_MIN_HZ: f32 = 20.0;
+const RESPONSE_MAX_HZ: f32 = 20000.0;
+
+/// Response of the EQ at log spaced frequencies
+pub struct FrequencyResponse {
+    pub freqs: Vec<f32>,
+    /// Preamp and the enabled stereo bands, what both channels get
+    pub sum: Vec<Complex<f64>>,
+    /// Each band on its own, whatever its routing
+    pub bands: Vec<Vec<Complex<f64>>>,
+}
+
+fn db(x: Complex<f64>) -> f64 {
+    20.0 * x.norm().max(1e-20).log10()
+}
+
+fn degrees(x: Complex<f64>) -> f64 {
+    x.arg().to_degrees()
+}
+
+impl FrequencyResponse {
+    /// Every column as CSV, magnitude in dB and phase in degrees
+    pub fn to_csv(&self) -> String {
+        let mut text = "frequency_hz,sum_db,sum_deg".to_string();
+        for i in 0..self.bands.len() {
+            text += &format!(",band_{}_db,band_{}_deg", i + 1, i + 1);
+        }
+        text += "\n";
+        for (i, freq) in self.freqs.iter().enumerate() {
+            text += &format!(
+                "{},{:.4},{:.4}",
+                freq,
+                db(self.sum[i]),
+                degrees(self.sum[i])
+            );
+            for band in &self.bands {
+                text += &format!(",{:.4},{:.4}", db(band[i]), degrees(band[i]));
+            }
+            text += "\n";
+        }
+        text
+    }
+
+    /// The summed response, or band `band` (from 0) on its own, as a REW
+    /// frequency response text file
+    pub fn to_rew_txt(&self, band: Option<usize>) -> String {
+        let (name, values) = match band {
+            Some(i) => (format!("Band {}", i + 1), &self.bands[i]),
+            None => ("Sum".to_string(), &self.sum),
+        };
+        let mut text = format!(
+            "* Basic EQ response: {}\n* Freq(Hz) SPL(dB) Phase(degrees)\n",
+            name
+        );
+        for (freq, value) in self.freqs.iter().zip(values) {
+            text += &format!("{} {:.4} {:.4}\n", freq, db(*value), degrees(*value));
+        }
+        text
+    }
+}
+
+impl EQEffectParameters {
+    /// Response of the minimum phase bands at `sample_rate`, with
+    /// `points_per_octave` points from 20 Hz up to 20 kHz or nyquist
+    pub fn frequency_response(
+        &self,
+        points_per_octave: f32,
+        sample_rate: f32,
+    ) -> FrequencyResponse {
+        let max_hz = RESPONSE_MAX_HZ.min(sample_rate * 0.5);
+        let octaves = (max_hz / RESPONSE_MIN_HZ).log2();
+        let points = (octaves * points_per_octave.max(1.0)).ceil() as usize + 1;
+        let freqs = (0..points)
+            .map(|i| RESPONSE_MIN_HZ * 2.0f32.powf(octaves * i as f32 / (points - 1) as f32))
+            .collect::<Vec<f32>>();
+
+        let band_coeffs = self
+            .band_settings()
+            .iter()
+            .map(|settings| BandCoefficients::new(settings, sample_rate))
+            .collect::<Vec<BandCoefficients>>();
+        let bands = band_coeffs
+            .iter()
+            .map(|coeffs| {
+                freqs
+                    .iter()
+                    .map(|f_hz| coeffs.response(*f_hz, sample_rate))
+                    .collect::<Vec<Complex<f64>>>()
+            })
+            .collect::<Vec<Vec<Complex<f64>>>>();
+
+        let preamp = Complex::new(self.preamp.get().db_to_lin() as f64, 0.0);
+        let sum = (0..freqs.len())
+            .map(|i| {
+                self.bands
+                    .iter()
+                    .zip(&bands)
+                    .filter(|(band, _)| {
+                        band.is_enabled() && band.get_routing() == BandRouting::Stereo
+                    })
+                    .fold(preamp, |sum, (_, values)| sum * values[i])
+            })
+            .collect();
+        FrequencyResponse { freqs, sum, bands }
+    }
+}
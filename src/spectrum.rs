@@ -0,0 +1,166 @@
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use vst::util::AtomicFloat;
+
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::units::Units;
+
+pub const FFT_SIZES: [usize; 5] = [1024, 2048, 4096, 8192, 16384];
+const RING_SIZE: usize = 16384;
+/// Floor of the analyzer display
+pub const SPECTRUM_MIN_DB: f32 = -90.0;
+/// How fast held peaks fall back, in dB per second
+const PEAK_DECAY_DB_PER_S: f32 = 20.0;
+
+/// Lock free ring of the most recent samples, written by the DSP and read by the editor.
+/// The reader may see a few samples that are being overwritten, which is fine for display.
+pub struct SampleRing {
+    samples: Vec<AtomicFloat>,
+    write_pos: AtomicUsize,
+}
+
+impl SampleRing {
+    pub fn new() -> SampleRing {
+        SampleRing {
+            samples: (0..RING_SIZE).map(|_| AtomicFloat::new(0.0)).collect(),
+            write_pos: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn push(&self, x: f32) {
+        let pos = self.write_pos.load(Ordering::Relaxed);
+        self.samples[pos % RING_SIZE].set(x);
+        self.write_pos.store(pos.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Copy the latest `out.len()` samples, oldest first
+    pub fn read_latest(&self, out: &mut [f32]) {
+        let end = self.write_pos.load(Ordering::Acquire);
+        let len = out.len().min(RING_SIZE);
+        let start = end.wrapping_sub(len);
+        for (i, x) in out.iter_mut().take(len).enumerate() {
+            *x = self.samples[start.wrapping_add(i) % RING_SIZE].get();
+        }
+    }
+}
+
+impl Default for SampleRing {
+    fn default() -> Self {
+        SampleRing::new()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct AnalyzerSettings {
+    pub fft_size: usize,
+    /// 0 shows every frame as is, values toward 1 average over more frames
+    pub averaging: f32,
+    /// Tilt in dB per octave around 1 kHz, 4.5 makes pink noise look flat
+    pub slope: f32,
+}
+
+impl Default for AnalyzerSettings {
+    fn default() -> Self {
+        AnalyzerSettings {
+            fft_size: 4096,
+            averaging: 0.7,
+            slope: 4.5,
+        }
+    }
+}
+
+/// Editor side windowed FFT of a `SampleRing` with averaging and peak hold
+pub struct SpectrumAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    planner: FftPlanner<f32>,
+    window: Vec<f32>,
+    samples: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    /// Averaged level of each bin in dB
+    pub smoothed: Vec<f32>,
+    /// Held peak level of each bin in dB
+    pub peaks: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(fft_size: usize) -> SpectrumAnalyzer {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let mut analyzer = SpectrumAnalyzer {
+            fft,
+            planner,
+            window: Vec::new(),
+            samples: Vec::new(),
+            buffer: Vec::new(),
+            smoothed: Vec::new(),
+            peaks: Vec::new(),
+        };
+        analyzer.resize(fft_size);
+        analyzer
+    }
+
+    fn resize(&mut self, fft_size: usize) {
+        self.fft = self.planner.plan_fft_forward(fft_size);
+        // Hann window
+        self.window = (0..fft_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / fft_size as f32).cos())
+            .collect();
+        self.samples = vec![0.0; fft_size];
+        self.buffer = vec![Complex::new(0.0, 0.0); fft_size];
+        self.smoothed = vec![SPECTRUM_MIN_DB; fft_size / 2];
+        self.peaks = vec![SPECTRUM_MIN_DB; fft_size / 2];
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Analyze the latest samples in `ring`, `dt` is the time since the last update in seconds
+    pub fn update(
+        &mut self,
+        ring: &SampleRing,
+        settings: &AnalyzerSettings,
+        sample_rate: f32,
+        dt: f32,
+    ) {
+        if settings.fft_size != self.fft_size() {
+            self.resize(settings.fft_size);
+        }
+        ring.read_latest(&mut self.samples);
+
+        let window_sum: f32 = self.window.iter().sum();
+        for ((c, x), w) in self.buffer.iter_mut().zip(&self.samples).zip(&self.window) {
+            *c = Complex::new(x * w, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+
+        let bin_hz = sample_rate / self.fft_size() as f32;
+        let peak_decay = PEAK_DECAY_DB_PER_S * dt;
+        for (i, (smoothed, peak)) in self
+            .smoothed
+            .iter_mut()
+            .zip(self.peaks.iter_mut())
+            .enumerate()
+        {
+            let f_hz = (i as f32 * bin_hz).max(1.0);
+            let tilt = settings.slope * (f_hz / 1000.0).log2();
+            let db = ((self.buffer[i].norm() * 2.0 / window_sum).lin_to_db() + tilt)
+                .max(SPECTRUM_MIN_DB);
+            *smoothed = db + (*smoothed - db) * settings.averaging;
+            *peak = (*peak - peak_decay).max(*smoothed);
+        }
+    }
+
+    /// Highest level in `values` between the two frequencies, for drawing on a log axis
+    pub fn level_between(&self, values: &[f32], f_lo: f32, f_hi: f32, sample_rate: f32) -> f32 {
+        let bin_hz = sample_rate / self.fft_size() as f32;
+        let last = values.len() - 1;
+        let lo = ((f_lo / bin_hz).round() as usize).min(last);
+        let hi = ((f_hi / bin_hz).round() as usize).max(lo).min(last);
+        values[lo..=hi]
+            .iter()
+            .fold(SPECTRUM_MIN_DB, |level, x| level.max(*x))
+    }
+}
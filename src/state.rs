@@ -0,0 +1,348 @@
+use crate::eq_effect_parameters::{EQEffectParameters, GLOBAL_PARAMETERS, PARAMETERS_PER_BAND};
+
+use std::{convert::TryInto, fmt};
+
+pub const MAGIC: &[u8; 4] = b"BEQP";
+
+/// Bump this whenever the meaning of stored values changes, and add a step
+/// to `migrate` that brings the older values up to date.
+/// Appending new values to a band is handled without a version bump, missing
+/// values are filled in from the parameter defaults, the same goes for globals.
+/// Version 2 added the globals section after the bands, version 3 moved the
+/// cascaded slopes out of the band's mode into its slope value.
+pub const STATE_VERSION: u32 = 3;
+
+/// Band values that `migrate` rewrites, by their slot in the band
+const MODE_SLOT: usize = 4;
+const SLOPE_SLOT: usize = 13;
+
+#[derive(Debug)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a Basic EQ state chunk"),
+            StateError::UnsupportedVersion(v) => write!(
+                f,
+                "state version {} is newer than the supported version {}",
+                v, STATE_VERSION
+            ),
+            StateError::Truncated => write!(f, "state chunk is truncated"),
+        }
+    }
+}
+
+struct StoredState {
+    version: u32,
+    bands: Vec<Vec<f32>>,
+    globals: Vec<f32>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.pos + n > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, StateError> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f32s(&mut self, n: usize) -> Result<Vec<f32>, StateError> {
+        (0..n).map(|_| self.f32()).collect()
+    }
+}
+
+fn parse(data: &[u8]) -> Result<StoredState, StateError> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.bytes(4)? != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let version = reader.u32()?;
+    if version > STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    let band_count = reader.u32()? as usize;
+    let values_per_band = reader.u32()? as usize;
+    let bands = (0..band_count)
+        .map(|_| reader.f32s(values_per_band))
+        .collect::<Result<Vec<Vec<f32>>, StateError>>()?;
+    let globals = if version >= 2 {
+        let global_count = reader.u32()? as usize;
+        reader.f32s(global_count)?
+    } else {
+        Vec::new()
+    };
+    Ok(StoredState {
+        version,
+        bands,
+        globals,
+    })
+}
+
+/// Bring values stored by an older version up to the current layout.
+/// `band_defaults` are the plain defaults of one band's values.
+fn migrate(mut state: StoredState, band_defaults: &[f32]) -> StoredState {
+    // Version 1 had no globals, they are left empty and load as their defaults
+    if state.version < 3 {
+        // Modes 2 and up were the cascaded slopes, now slope 1 and up on top of
+        // second order
+        for values in state.bands.iter_mut() {
+            let mode = match values.get(MODE_SLOT) {
+                Some(&mode) if mode >= 2.0 => mode,
+                _ => continue,
+            };
+            if values.len() <= SLOPE_SLOT {
+                let len = values.len();
+                values.extend_from_slice(&band_defaults[len..=SLOPE_SLOT]);
+            }
+            values[MODE_SLOT] = 1.0;
+            values[SLOPE_SLOT] = mode - 1.0;
+        }
+    }
+    state
+}
+
+impl EQEffectParameters {
+    /// Chunk layout, all numbers little endian:
+    /// magic "BEQP", u32 version, u32 band count, u32 values per band,
+    /// band count * values per band f32 plain parameter values, band by band in
+    /// slot order, u32 global count, global count f32 plain values in `Index` order.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        data.extend_from_slice(&(self.bands.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(PARAMETERS_PER_BAND as u32).to_le_bytes());
+        for band in self.bands.iter() {
+            for j in 0..PARAMETERS_PER_BAND {
+                data.extend_from_slice(&band.slot(j).get().to_le_bytes());
+            }
+        }
+        let globals_start = self.globals_start();
+        data.extend_from_slice(&(GLOBAL_PARAMETERS as u32).to_le_bytes());
+        for i in globals_start..globals_start + GLOBAL_PARAMETERS {
+            data.extend_from_slice(&self[i].get().to_le_bytes());
+        }
+        data
+    }
+
+    /// Restore every parameter from a chunk made by `save_state`.
+    /// Bands or values that the chunk does not contain go back to their defaults.
+    pub fn load_state(&self, data: &[u8]) -> Result<(), StateError> {
+        let state = parse(data)?;
+        ::log::info!("loading state version {}", state.version);
+        let band_defaults = (0..PARAMETERS_PER_BAND)
+            .map(|j| self.bands[0].slot(j).default)
+            .collect::<Vec<f32>>();
+        let state = migrate(state, &band_defaults);
+        for (i, band) in self.bands.iter().enumerate() {
+            let stored = state.bands.get(i);
+            for j in 0..PARAMETERS_PER_BAND {
+                let parameter = band.slot(j);
+                let value = stored.and_then(|values| values.get(j).copied());
+                parameter.set(value.unwrap_or(parameter.default));
+            }
+        }
+        let globals_start = self.globals_start();
+        for j in 0..GLOBAL_PARAMETERS {
+            let parameter = &self[globals_start + j];
+            let value = state.globals.get(j).copied();
+            parameter.set(value.unwrap_or(parameter.default));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eq_effect_parameters::{BandMode, BandRouting, DynamicMode};
+    use crate::parameter::Parameter;
+
+    /// A value of `parameter` away from its default, whole for stepped ones
+    fn changed_value(parameter: &Parameter, i: usize) -> f32 {
+        let x = parameter.min + (parameter.max - parameter.min) * ((i % 7) as f32 + 1.0) / 9.0;
+        let x = if parameter.is_stepped() { x.ceil() } else { x };
+        if x != parameter.default {
+            x
+        } else if x == parameter.max {
+            parameter.min
+        } else {
+            parameter.max
+        }
+    }
+
+    fn changed_parameters() -> EQEffectParameters {
+        let params = EQEffectParameters::default();
+        for i in 0..params.len() {
+            params[i].set(changed_value(&params[i], i));
+        }
+        params
+    }
+
+    /// Version 1 stored kind, freq, db_gain, q_value and mode for each band
+    const VERSION_1_VALUES_PER_BAND: usize = 5;
+
+    /// A version 1 chunk as version 1 wrote it: the first values of each band of
+    /// `params` and no globals section
+    fn version_1_chunk(params: &EQEffectParameters) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&(params.bands.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(VERSION_1_VALUES_PER_BAND as u32).to_le_bytes());
+        for band in params.bands.iter() {
+            for j in 0..VERSION_1_VALUES_PER_BAND {
+                data.extend_from_slice(&band.slot(j).get().to_le_bytes());
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn round_trip_restores_every_parameter() {
+        let saved = changed_parameters();
+        let loaded = EQEffectParameters::default();
+        loaded.load_state(&saved.save_state()).unwrap();
+        for i in 0..saved.len() {
+            assert_ne!(saved[i].get(), saved[i].default, "{}", saved[i].get_name());
+            assert_eq!(loaded[i].get(), saved[i].get(), "{}", saved[i].get_name());
+        }
+    }
+
+    #[test]
+    fn version_1_loads_bands_and_default_globals() {
+        let saved = changed_parameters();
+        let loaded = changed_parameters();
+        loaded.load_state(&version_1_chunk(&saved)).unwrap();
+        // The stored values come back, routing, dynamics, enable, solo, slope and
+        // the globals go back to their defaults
+        for i in 0..saved.len() {
+            let stored = matches!(
+                saved.band_slot(i),
+                Some((_, slot)) if slot < VERSION_1_VALUES_PER_BAND
+            );
+            let expected = if stored {
+                saved[i].get()
+            } else {
+                saved[i].default
+            };
+            assert_eq!(loaded[i].get(), expected, "{}", saved[i].get_name());
+        }
+        for band in loaded.bands.iter() {
+            assert_eq!(band.get_routing(), BandRouting::Stereo);
+            assert_eq!(band.get_dynamic(), DynamicMode::Off);
+            assert!(band.is_enabled());
+            assert!(!band.is_solo());
+        }
+        // Saving again writes the current version, which reads back the same
+        let resaved = loaded.save_state();
+        assert_eq!(resaved[4..8], STATE_VERSION.to_le_bytes());
+        let reloaded = changed_parameters();
+        reloaded.load_state(&resaved).unwrap();
+        for i in 0..saved.len() {
+            assert_eq!(
+                reloaded[i].get(),
+                loaded[i].get(),
+                "{}",
+                saved[i].get_name()
+            );
+        }
+    }
+
+    #[test]
+    fn cascaded_modes_before_version_3_load_as_slopes() {
+        let params = EQEffectParameters::default();
+        for (mode, expected) in [
+            (0.0, BandMode::FirstOrder),
+            (1.0, BandMode::SecondOrder),
+            (2.0, BandMode::Butterworth24),
+            (7.0, BandMode::LinkwitzRiley48),
+        ] {
+            let mut data = params.save_state();
+            data[4..8].copy_from_slice(&2u32.to_le_bytes());
+            let mode_offset = 16 + 4 * MODE_SLOT;
+            data[mode_offset..mode_offset + 4].copy_from_slice(&f32::to_le_bytes(mode));
+            let loaded = EQEffectParameters::default();
+            loaded.load_state(&data).unwrap();
+            assert_eq!(loaded.bands[0].get_mode(), expected);
+            assert!(loaded.bands[0].mode.get() <= 1.0);
+            assert_eq!(loaded.bands[1].get_mode(), BandMode::SecondOrder);
+        }
+    }
+
+    #[test]
+    fn version_1_cascaded_modes_fill_the_missing_values() {
+        let params = changed_parameters();
+        let mut data = version_1_chunk(&params);
+        let mode_offset = 16 + 4 * MODE_SLOT;
+        data[mode_offset..mode_offset + 4].copy_from_slice(&7f32.to_le_bytes());
+        let loaded = changed_parameters();
+        loaded.load_state(&data).unwrap();
+        assert_eq!(loaded.bands[0].get_mode(), BandMode::LinkwitzRiley48);
+        for j in VERSION_1_VALUES_PER_BAND..PARAMETERS_PER_BAND {
+            let parameter = loaded.bands[0].slot(j);
+            if j != SLOPE_SLOT {
+                assert_eq!(
+                    parameter.get(),
+                    parameter.default,
+                    "{}",
+                    parameter.get_name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_chunks_are_rejected() {
+        let params = changed_parameters();
+        for data in [params.save_state(), version_1_chunk(&params)] {
+            for len in 0..data.len() {
+                assert!(matches!(
+                    params.load_state(&data[..len]),
+                    Err(StateError::Truncated)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn bad_magic_and_newer_versions_are_rejected() {
+        let params = EQEffectParameters::default();
+        let mut data = params.save_state();
+        data[0] = b'X';
+        assert!(matches!(
+            params.load_state(&data),
+            Err(StateError::BadMagic)
+        ));
+        assert!(matches!(
+            params.load_state(b"not a chunk at all"),
+            Err(StateError::BadMagic)
+        ));
+        let mut data = params.save_state();
+        data[4..8].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            params.load_state(&data),
+            Err(StateError::UnsupportedVersion(v)) if v == STATE_VERSION + 1
+        ));
+    }
+}